@@ -0,0 +1,11 @@
+CREATE TABLE email_outbox(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    recipient_email TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    html_body TEXT NOT NULL,
+    text_body TEXT NOT NULL,
+    n_retries SMALLINT NOT NULL DEFAULT 0,
+    execute_after TIMESTAMPTZ NOT NULL DEFAULT now(),
+    created_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,6 @@
+-- Tokens are now stored as SHA3-256 hashes: outstanding plaintext tokens can't
+-- be matched anymore, so they are dropped.
+DELETE FROM subscription_tokens;
+ALTER TABLE subscription_tokens RENAME subscription_token TO subscription_token_hash;
+ALTER TABLE subscription_tokens ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+ALTER TABLE subscription_tokens ADD COLUMN consumed_at TIMESTAMPTZ NULL;
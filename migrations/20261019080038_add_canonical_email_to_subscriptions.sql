@@ -0,0 +1,24 @@
+-- Used to detect duplicate subscriptions, whatever the case of the address
+-- (and its `+tag`, when `email_policy.strip_plus_tags` is on: the application
+-- canonicalizes the existing rows on startup, see `strip_plus_tags_from_canonical_emails`).
+ALTER TABLE subscriptions ADD COLUMN canonical_email TEXT NULL;
+-- The form `SubscriberEmail` gives with the default policy: lowercased.
+UPDATE subscriptions SET canonical_email = lower(email);
+-- Subscriptions differing only by case are merged into one, the confirmed
+-- one if any, the oldest otherwise, before the constraint can be added.
+CREATE TEMPORARY TABLE duplicate_subscriptions AS
+SELECT id FROM (
+    SELECT id, row_number() OVER (
+        PARTITION BY canonical_email
+        ORDER BY status = 'confirmed' DESC, subscribed_at, id
+    ) AS rank
+    FROM subscriptions
+) AS ranked
+WHERE rank > 1;
+DELETE FROM subscription_tokens
+WHERE subscription_id IN (SELECT id FROM duplicate_subscriptions);
+DELETE FROM subscriptions
+WHERE id IN (SELECT id FROM duplicate_subscriptions);
+DROP TABLE duplicate_subscriptions;
+ALTER TABLE subscriptions ALTER COLUMN canonical_email SET NOT NULL;
+ALTER TABLE subscriptions ADD CONSTRAINT subscriptions_canonical_email_key UNIQUE (canonical_email);
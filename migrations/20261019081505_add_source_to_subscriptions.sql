@@ -0,0 +1,2 @@
+-- Which site the subscription form was embedded on, for attribution.
+ALTER TABLE subscriptions ADD COLUMN source TEXT NULL;
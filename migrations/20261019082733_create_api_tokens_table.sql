@@ -0,0 +1,12 @@
+CREATE TABLE api_tokens(
+    id uuid PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (user_id),
+    name TEXT NOT NULL,
+    -- Only the SHA3-256 hash of the token is kept.
+    token_hash TEXT NOT NULL UNIQUE,
+    scopes TEXT[] NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NULL,
+    last_used_at TIMESTAMPTZ NULL,
+    revoked_at TIMESTAMPTZ NULL
+);
@@ -0,0 +1,12 @@
+-- TOTP secret, kept while the enrollment is pending, enabled once confirmed.
+ALTER TABLE users ADD COLUMN totp_secret TEXT NULL;
+ALTER TABLE users ADD COLUMN totp_enabled_at TIMESTAMPTZ NULL;
+-- Last accepted time step, so that a code cannot be replayed.
+ALTER TABLE users ADD COLUMN totp_last_step BIGINT NULL;
+
+CREATE TABLE recovery_codes(
+    user_id uuid NOT NULL REFERENCES users (user_id),
+    code_hash TEXT NOT NULL,
+    used_at TIMESTAMPTZ NULL,
+    PRIMARY KEY (user_id, code_hash)
+);
@@ -0,0 +1,11 @@
+-- Where password reset links are sent. Accounts without one cannot reset.
+ALTER TABLE users ADD COLUMN email TEXT NULL;
+
+CREATE TABLE password_reset_tokens(
+    -- Only the SHA3-256 hash of the token is kept.
+    token_hash TEXT PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (user_id),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ NULL
+);
@@ -0,0 +1,3 @@
+-- Existing users could do everything until now: they become owners.
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'owner'
+    CHECK (role IN ('owner', 'editor', 'analyst'));
@@ -0,0 +1,15 @@
+-- Invited users have no password until they accept their invitation.
+ALTER TABLE users ALTER COLUMN password_hash DROP NOT NULL;
+ALTER TABLE users ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+-- Disabled users can neither log in nor use their sessions and API tokens.
+ALTER TABLE users ADD COLUMN disabled_at TIMESTAMPTZ NULL;
+
+CREATE TABLE user_invitations(
+    -- Only the SHA3-256 hash of the token is kept.
+    token_hash TEXT PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    invited_by uuid NULL REFERENCES users (user_id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    accepted_at TIMESTAMPTZ NULL
+);
@@ -0,0 +1,11 @@
+-- Every logged-in session, so that users can see and revoke them.
+CREATE TABLE user_sessions(
+    session_id uuid PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    ip TEXT NOT NULL,
+    user_agent TEXT NOT NULL,
+    revoked_at TIMESTAMPTZ NULL
+);
+CREATE INDEX user_sessions_user_id ON user_sessions (user_id);
@@ -0,0 +1,15 @@
+-- Who did what and when, for the security-relevant actions.
+CREATE TABLE audit_log(
+    id BIGSERIAL PRIMARY KEY,
+    occurred_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    -- Kept when the user is deleted, their username stays in `actor_username`.
+    actor_id uuid NULL REFERENCES users (user_id) ON DELETE SET NULL,
+    -- For failed logins, the username that was attempted.
+    actor_username TEXT NULL,
+    ip TEXT NOT NULL,
+    action TEXT NOT NULL,
+    target TEXT NULL,
+    changes JSONB NOT NULL DEFAULT '{}'
+);
+CREATE INDEX audit_log_action_idx ON audit_log (action, id);
+CREATE INDEX audit_log_actor_id_idx ON audit_log (actor_id, id);
@@ -0,0 +1,2 @@
+-- Language the subscriber is written to in, as a language tag such as `fr`.
+ALTER TABLE subscriptions ADD COLUMN locale TEXT NOT NULL DEFAULT 'en';
@@ -0,0 +1,244 @@
+//! Persistent record of the security-relevant actions: who did what, when and
+//! from where. Entries are written in the transaction of the action they
+//! describe whenever there is one, so that they cannot disagree.
+use std::str::FromStr;
+
+use actix_web::HttpRequest;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Map, Value};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::utils::client_ip;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    LoginSucceeded,
+    LoginFailed,
+    PasswordChanged,
+    NewsletterPublished,
+    SubscriberUpdated,
+    SubscriberDeleted,
+    SubscribersExported,
+    ApiTokenCreated,
+}
+
+impl AuditAction {
+    pub const ALL: [AuditAction; 8] = [
+        AuditAction::LoginSucceeded,
+        AuditAction::LoginFailed,
+        AuditAction::PasswordChanged,
+        AuditAction::NewsletterPublished,
+        AuditAction::SubscriberUpdated,
+        AuditAction::SubscriberDeleted,
+        AuditAction::SubscribersExported,
+        AuditAction::ApiTokenCreated,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::LoginSucceeded => "login.succeeded",
+            AuditAction::LoginFailed => "login.failed",
+            AuditAction::PasswordChanged => "password.changed",
+            AuditAction::NewsletterPublished => "newsletter.published",
+            AuditAction::SubscriberUpdated => "subscriber.updated",
+            AuditAction::SubscriberDeleted => "subscriber.deleted",
+            AuditAction::SubscribersExported => "subscribers.exported",
+            AuditAction::ApiTokenCreated => "api_token.created",
+        }
+    }
+}
+
+impl FromStr for AuditAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|action| action.as_str() == s)
+            .ok_or_else(|| anyhow::anyhow!("`{}` is not a known audit action", s))
+    }
+}
+
+impl std::fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An entry of the audit log, about to be recorded.
+#[derive(Debug)]
+pub struct AuditEvent {
+    action: AuditAction,
+    ip: String,
+    actor_id: Option<Uuid>,
+    actor_username: Option<String>,
+    target: Option<String>,
+    changes: Value,
+}
+
+impl AuditEvent {
+    pub fn new(action: AuditAction, request: &HttpRequest) -> Self {
+        Self {
+            action,
+            ip: client_ip(request),
+            actor_id: None,
+            actor_username: None,
+            target: None,
+            changes: json!({}),
+        }
+    }
+
+    /// The user acting. Their username is looked up when recording.
+    pub fn actor(mut self, user_id: Uuid) -> Self {
+        self.actor_id = Some(user_id);
+        self
+    }
+
+    /// The username given to a failed login, which may not exist.
+    pub fn attempted_username(mut self, username: &str) -> Self {
+        self.actor_username = Some(username.to_string());
+        self
+    }
+
+    pub fn target(mut self, target: impl ToString) -> Self {
+        self.target = Some(target.to_string());
+        self
+    }
+
+    /// What the action changed, see `diff`, or any detail worth keeping.
+    pub fn changes(mut self, changes: Value) -> Self {
+        self.changes = changes;
+        self
+    }
+
+    #[tracing::instrument(
+        name = "Record audit event",
+        skip(self, executor),
+        fields(action = %self.action, actor_id = ?self.actor_id)
+    )]
+    pub async fn record<'e>(self, executor: impl PgExecutor<'e>) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO audit_log (actor_id, actor_username, ip, action, target, changes)
+            VALUES (
+                COALESCE($1, (SELECT user_id FROM users WHERE username = $2)),
+                COALESCE($2, (SELECT username FROM users WHERE user_id = $1)),
+                $3, $4, $5, $6
+            )
+            "#,
+            self.actor_id,
+            self.actor_username,
+            self.ip,
+            self.action.as_str(),
+            self.target,
+            self.changes,
+        )
+        .execute(executor)
+        .await
+        .context("Failed to record the audit event")?;
+        Ok(())
+    }
+}
+
+/// The fields that differ between two JSON objects, as
+/// `{"field": {"from": <before>, "to": <after>}}`.
+pub fn diff(before: &Value, after: &Value) -> Value {
+    let empty = Map::new();
+    let before = before.as_object().unwrap_or(&empty);
+    let after = after.as_object().unwrap_or(&empty);
+    let mut changes = Map::new();
+    for key in before.keys().chain(after.keys()) {
+        let (from, to) = (
+            before.get(key).unwrap_or(&Value::Null),
+            after.get(key).unwrap_or(&Value::Null),
+        );
+        if from != to {
+            changes.insert(key.clone(), json!({ "from": from, "to": to }));
+        }
+    }
+    Value::Object(changes)
+}
+
+/// A recorded entry of the audit log.
+#[derive(Debug, serde::Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub occurred_at: DateTime<Utc>,
+    pub actor_id: Option<Uuid>,
+    pub actor_username: Option<String>,
+    pub ip: String,
+    pub action: String,
+    pub target: Option<String>,
+    pub changes: Value,
+}
+
+#[derive(Debug, Default)]
+pub struct AuditFilter {
+    pub action: Option<AuditAction>,
+    pub actor_username: Option<String>,
+}
+
+/// Up to `limit` entries, newest first, starting below the id `before`.
+#[tracing::instrument(name = "List audit entries", skip(pool))]
+pub async fn list_audit_entries(
+    filter: &AuditFilter,
+    before: Option<i64>,
+    limit: i64,
+    pool: &PgPool,
+) -> Result<Vec<AuditEntry>, anyhow::Error> {
+    let entries = sqlx::query_as!(
+        AuditEntry,
+        r#"
+        SELECT id, occurred_at, actor_id, actor_username, ip, action, target, changes
+        FROM audit_log
+        WHERE ($1::text IS NULL OR action = $1)
+            AND ($2::text IS NULL OR actor_username = $2)
+            AND ($3::bigint IS NULL OR id < $3)
+        ORDER BY id DESC
+        LIMIT $4
+        "#,
+        filter.action.map(|a| a.as_str()),
+        filter.actor_username.as_deref(),
+        before,
+        limit,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to list the audit entries")?;
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn actions_round_trip_through_their_name() {
+        for action in AuditAction::ALL {
+            assert_eq!(action.as_str().parse::<AuditAction>().unwrap(), action);
+        }
+        assert!("login".parse::<AuditAction>().is_err());
+    }
+
+    #[test]
+    fn diff_only_keeps_the_changed_fields() {
+        let before =
+            json!({"name": "Ursula", "email": "ursula@example.com", "status": "confirmed"});
+        let after = json!({"name": "Ursula", "email": "ursula@example.org", "status": "confirmed"});
+        assert_eq!(
+            diff(&before, &after),
+            json!({"email": {"from": "ursula@example.com", "to": "ursula@example.org"}})
+        );
+        assert_eq!(diff(&before, &before), json!({}));
+    }
+
+    #[test]
+    fn diff_reports_added_and_removed_fields() {
+        assert_eq!(
+            diff(&json!({"a": 1}), &json!({"b": 2})),
+            json!({"a": {"from": 1, "to": null}, "b": {"from": null, "to": 2}})
+        );
+    }
+}
@@ -1,112 +0,0 @@
-use std::str::FromStr;
-
-use crate::telemetry::spawn_blocking_with_tracing;
-use anyhow::Context;
-use argon2::{Argon2, PasswordHash, PasswordVerifier};
-use secrecy::{ExposeSecret, Secret};
-use sqlx::PgPool;
-
-#[derive(thiserror::Error, Debug)]
-pub enum AuthError {
-    #[error("Invalid Credentials")]
-    InvalidCredentials(#[source] anyhow::Error),
-    #[error(transparent)]
-    UnexpectedError(#[from] anyhow::Error),
-}
-
-pub struct Credentials {
-    pub username: String,
-    pub password: Secret<String>,
-}
-
-impl FromStr for Credentials {
-    type Err = anyhow::Error;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut credentials = s.splitn(2, ':');
-        let username = credentials
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("A username mu be proved for basic auth"))?
-            .to_string();
-
-        let password = credentials
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("A password mu be proved for basic auth"))?
-            .to_string();
-        Ok(Self {
-            username,
-            password: Secret::new(password),
-        })
-    }
-}
-
-#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
-pub async fn validate_credentials(
-    credentials: Credentials,
-    pool: &PgPool,
-) -> Result<uuid::Uuid, AuthError> {
-    let mut user_id = None;
-    let mut expected_password_hash = Secret::new(
-        "$argon2id$v=19$m=15000,t=2,p=1$\
-gZiV/M1gPc22ElAH/Jh1Hw$\
-CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
-            .to_string(),
-    );
-    if let Some((stored_user_id, stored_expected_password_hash)) =
-        get_stored_credentials(&credentials.username, &pool)
-            .await
-            .map_err(AuthError::UnexpectedError)?
-    {
-        user_id = Some(stored_user_id);
-        expected_password_hash = stored_expected_password_hash;
-    }
-
-    spawn_blocking_with_tracing(move || {
-        verify_password(expected_password_hash, credentials.password)
-    })
-    .await
-    .context("FAiled to wpawn blocking tasj")??;
-
-    user_id
-        .ok_or_else(|| anyhow::anyhow!("Unknown username."))
-        .map_err(AuthError::InvalidCredentials)
-}
-
-#[tracing::instrument(
-    name = "Verify password hash",
-    skip(expected_password_hash, password_candidate)
-)]
-fn verify_password(
-    expected_password_hash: Secret<String>,
-    password_candidate: Secret<String>,
-) -> Result<(), AuthError> {
-    let expected_password_hash = PasswordHash::new(&expected_password_hash.expose_secret())
-        .context("echec de lecture au format PHC")?;
-
-    Argon2::default()
-        .verify_password(
-            password_candidate.expose_secret().as_bytes(),
-            &expected_password_hash,
-        )
-        .context("Password verification failed")
-        .map_err(AuthError::InvalidCredentials)
-}
-
-#[tracing::instrument(name = "get stored credentials", skip(username, pool))]
-async fn get_stored_credentials(
-    username: &str,
-    pool: &PgPool,
-) -> Result<Option<(uuid::Uuid, Secret<String>)>, anyhow::Error> {
-    let row = sqlx::query!(
-        r#"
-        SELECT user_id, password_hash FROM users
-        WHERE username = $1
-        "#,
-        username,
-    )
-    .fetch_optional(pool)
-    .await
-    .context("Failed to perfom query to validate credentials")?
-    .map(|x| (x.user_id, Secret::new(x.password_hash)));
-    Ok(row)
-}
@@ -0,0 +1,235 @@
+use std::str::FromStr;
+
+use actix_web::HttpRequest;
+use anyhow::Context;
+use chrono::Utc;
+use rand::Rng;
+use secrecy::{ExposeSecret, Secret};
+use sha3::{Digest, Sha3_256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{rate_limit::RateLimiter, utils::client_ip};
+
+use super::{
+    basic_authentication, get_role, get_two_factor_state, validate_credentials_throttled,
+    AuthError, PasswordHashing, Permission, Role,
+};
+
+const TOKEN_PREFIX: &str = "z2p_";
+
+/// What an API token is allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ApiScope {
+    NewslettersPublish,
+    SubscribersRead,
+    SubscribersWrite,
+    AuditLogRead,
+}
+
+impl ApiScope {
+    pub const ALL: [ApiScope; 4] = [
+        ApiScope::NewslettersPublish,
+        ApiScope::SubscribersRead,
+        ApiScope::SubscribersWrite,
+        ApiScope::AuditLogRead,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::NewslettersPublish => "newsletters:publish",
+            ApiScope::SubscribersRead => "subscribers:read",
+            ApiScope::SubscribersWrite => "subscribers:write",
+            ApiScope::AuditLogRead => "audit_log:read",
+        }
+    }
+
+    /// The permission the token owner's role needs for the scope to be usable.
+    pub fn permission(&self) -> Permission {
+        match self {
+            ApiScope::NewslettersPublish => Permission::PublishNewsletters,
+            ApiScope::SubscribersRead => Permission::ReadSubscribers,
+            ApiScope::SubscribersWrite => Permission::WriteSubscribers,
+            ApiScope::AuditLogRead => Permission::ReadAuditLog,
+        }
+    }
+}
+
+impl FromStr for ApiScope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|scope| scope.as_str() == s)
+            .ok_or_else(|| anyhow::anyhow!("`{}` is not a known API scope", s))
+    }
+}
+
+impl std::fmt::Display for ApiScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An authenticated caller of the API.
+#[derive(Debug)]
+pub struct ApiCaller {
+    pub user_id: Uuid,
+    /// A scope is only usable if the role also permits it.
+    pub role: Role,
+    /// `None` when the caller authenticated with its password: it can do
+    /// anything its role permits.
+    scopes: Option<Vec<ApiScope>>,
+}
+
+impl ApiCaller {
+    pub fn has_scope(&self, scope: ApiScope) -> bool {
+        match &self.scopes {
+            Some(scopes) => scopes.contains(&scope),
+            None => true,
+        }
+    }
+}
+
+pub fn generate_api_token() -> Secret<String> {
+    let mut rng = rand::thread_rng();
+    let token: String = std::iter::repeat_with(|| rng.sample(rand::distributions::Alphanumeric))
+        .map(char::from)
+        .take(40)
+        .collect();
+    Secret::new(format!("{}{}", TOKEN_PREFIX, token))
+}
+
+/// Like subscription tokens, API tokens are only stored hashed.
+pub fn hash_api_token(token: &str) -> String {
+    hex::encode(Sha3_256::digest(token.as_bytes()))
+}
+
+/// Authenticate a request with either an `Authorization: Bearer` API token
+/// or `Authorization: Basic` credentials.
+/// Basic credentials are subject to the same throttling as the login form.
+#[tracing::instrument(
+    name = "Authenticate API caller",
+    skip(request, pool, rate_limiter, hashing)
+)]
+pub async fn authenticate_api_caller(
+    request: &HttpRequest,
+    pool: &PgPool,
+    rate_limiter: &RateLimiter,
+    hashing: &PasswordHashing,
+) -> Result<ApiCaller, AuthError> {
+    let headers = request.headers();
+    let bearer_token = headers
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "));
+    match bearer_token {
+        Some(token) => validate_api_token(Secret::new(token.trim().to_string()), pool).await,
+        None => {
+            let credentials =
+                basic_authentication(headers).map_err(AuthError::InvalidCredentials)?;
+            let user_id = validate_credentials_throttled(
+                credentials,
+                &client_ip(request),
+                pool,
+                rate_limiter,
+                hashing,
+            )
+            .await?;
+            // A password alone must not bypass the second factor.
+            if get_two_factor_state(user_id, pool).await?.enabled {
+                return Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+                    "Accounts with two-factor authentication must use an API token."
+                )));
+            }
+            Ok(ApiCaller {
+                user_id,
+                role: get_role(user_id, pool).await?,
+                scopes: None,
+            })
+        }
+    }
+}
+
+#[tracing::instrument(name = "Validate API token", skip(token, pool), fields(token_id))]
+async fn validate_api_token(token: Secret<String>, pool: &PgPool) -> Result<ApiCaller, AuthError> {
+    let stored = sqlx::query!(
+        r#"
+        SELECT t.id, t.user_id, t.scopes, t.expires_at, u.role
+        FROM api_tokens t JOIN users u ON u.user_id = t.user_id
+        WHERE t.token_hash = $1 AND t.revoked_at IS NULL AND u.disabled_at IS NULL
+        "#,
+        hash_api_token(token.expose_secret()),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the API token")?
+    .ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Unknown API token.")))?;
+    tracing::Span::current().record("token_id", &tracing::field::display(&stored.id));
+    if matches!(stored.expires_at, Some(expires_at) if expires_at <= Utc::now()) {
+        return Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+            "The API token has expired."
+        )));
+    }
+    sqlx::query!(
+        r#"UPDATE api_tokens SET last_used_at = now() WHERE id = $1"#,
+        stored.id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record the API token usage")?;
+    Ok(ApiCaller {
+        user_id: stored.user_id,
+        role: stored.role.parse()?,
+        scopes: Some(
+            stored
+                .scopes
+                .iter()
+                .filter_map(|scope| scope.parse().ok())
+                .collect(),
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scopes_round_trip_through_their_name() {
+        for scope in ApiScope::ALL {
+            assert_eq!(scope.as_str().parse::<ApiScope>().unwrap(), scope);
+        }
+        assert!("subscribers:delete".parse::<ApiScope>().is_err());
+    }
+
+    #[test]
+    fn password_callers_have_every_scope() {
+        let caller = ApiCaller {
+            user_id: Uuid::new_v4(),
+            role: Role::Owner,
+            scopes: None,
+        };
+        assert!(ApiScope::ALL.into_iter().all(|s| caller.has_scope(s)));
+    }
+
+    #[test]
+    fn token_callers_only_have_their_scopes() {
+        let caller = ApiCaller {
+            user_id: Uuid::new_v4(),
+            role: Role::Owner,
+            scopes: Some(vec![ApiScope::SubscribersRead]),
+        };
+        assert!(caller.has_scope(ApiScope::SubscribersRead));
+        assert!(!caller.has_scope(ApiScope::NewslettersPublish));
+    }
+
+    #[test]
+    fn every_scope_maps_to_a_permission_some_role_lacks() {
+        for scope in ApiScope::ALL {
+            assert!(Role::Owner.permits(scope.permission()));
+        }
+        assert!(!Role::Analyst.permits(ApiScope::SubscribersWrite.permission()));
+    }
+}
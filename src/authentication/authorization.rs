@@ -0,0 +1,234 @@
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+    str::FromStr,
+};
+
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::ContentType,
+    web, HttpMessage, HttpResponse,
+};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{templates::Templates, utils::e500};
+
+use super::middleware::MiddlewareFuture;
+
+/// What a user may do is decided by their role.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// Everything, including managing the other users.
+    Owner,
+    /// Manages the subscribers and publishes newsletters.
+    Editor,
+    /// Read-only access.
+    Analyst,
+}
+
+/// An action guarded by a role check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    ReadSubscribers,
+    WriteSubscribers,
+    PublishNewsletters,
+    ManageUsers,
+    ReadAuditLog,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Owner, Role::Editor, Role::Analyst];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Editor => "editor",
+            Role::Analyst => "analyst",
+        }
+    }
+
+    pub fn permits(&self, permission: Permission) -> bool {
+        match self {
+            Role::Owner => true,
+            Role::Editor => {
+                permission != Permission::ManageUsers && permission != Permission::ReadAuditLog
+            }
+            Role::Analyst => permission == Permission::ReadSubscribers,
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|role| role.as_str() == s)
+            .ok_or_else(|| anyhow::anyhow!("`{}` is not a known role", s))
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::fmt::Display for Permission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Permission::ReadSubscribers => "read subscribers",
+            Permission::WriteSubscribers => "manage subscribers",
+            Permission::PublishNewsletters => "publish newsletters",
+            Permission::ManageUsers => "manage users",
+            Permission::ReadAuditLog => "read the audit log",
+        })
+    }
+}
+
+#[tracing::instrument(name = "Get user role", skip(pool))]
+pub async fn get_role(user_id: Uuid, pool: &PgPool) -> Result<Role, anyhow::Error> {
+    let row = sqlx::query!(r#"SELECT role FROM users WHERE user_id = $1"#, user_id)
+        .fetch_one(pool)
+        .await
+        .context("Failed to retrieve the user's role")?;
+    row.role.parse()
+}
+
+/// Answer 403 unless the admin's role grants `Permission`. Must run after
+/// `RejectAnonymousUsers`, which stores the role in the request extensions.
+/// Meant for `wrap` on admin routes or scopes.
+pub struct RequirePermission(pub Permission);
+
+impl<S, B> Transform<S, ServiceRequest> for RequirePermission
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = RequirePermissionMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequirePermissionMiddleware {
+            service: Rc::new(service),
+            permission: self.0,
+        }))
+    }
+}
+
+pub struct RequirePermissionMiddleware<S> {
+    service: Rc<S>,
+    permission: Permission,
+}
+
+impl<S, B> Service<ServiceRequest> for RequirePermissionMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = MiddlewareFuture<B>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let role = req.extensions().get::<Role>().copied();
+        if matches!(role, Some(role) if role.permits(self.permission)) {
+            let response = self.service.call(req);
+            return Box::pin(async move { Ok(response.await?.map_into_left_body()) });
+        }
+        tracing::warn!(?role, permission = ?self.permission, "Permission denied");
+        let response = forbidden_page(&req, self.permission);
+        Box::pin(async move { Ok(req.into_response(response?).map_into_right_body()) })
+    }
+}
+
+fn forbidden_page(
+    req: &ServiceRequest,
+    permission: Permission,
+) -> Result<HttpResponse, actix_web::Error> {
+    let templates = req
+        .app_data::<web::Data<Templates>>()
+        .ok_or_else(|| e500("The templates are not configured"))?;
+    let mut context = tera::Context::new();
+    context.insert("permission", &permission.to_string());
+    let body = templates
+        .render("admin/forbidden.html", &context)
+        .map_err(e500)?;
+    Ok(HttpResponse::Forbidden()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::TemplateSettings;
+    use actix_web::{test::TestRequest, App};
+
+    #[test]
+    fn roles_round_trip_through_their_name() {
+        for role in Role::ALL {
+            assert_eq!(role.as_str().parse::<Role>().unwrap(), role);
+        }
+        assert!("admin".parse::<Role>().is_err());
+    }
+
+    #[test]
+    fn only_owners_manage_users() {
+        assert!(Role::Owner.permits(Permission::ManageUsers));
+        assert!(!Role::Editor.permits(Permission::ManageUsers));
+        assert!(!Role::Analyst.permits(Permission::ManageUsers));
+    }
+
+    #[test]
+    fn only_owners_read_the_audit_log() {
+        assert!(Role::Owner.permits(Permission::ReadAuditLog));
+        assert!(!Role::Editor.permits(Permission::ReadAuditLog));
+        assert!(!Role::Analyst.permits(Permission::ReadAuditLog));
+    }
+
+    #[test]
+    fn editors_publish_and_analysts_only_read() {
+        assert!(Role::Editor.permits(Permission::PublishNewsletters));
+        assert!(Role::Editor.permits(Permission::WriteSubscribers));
+        assert!(Role::Analyst.permits(Permission::ReadSubscribers));
+        assert!(!Role::Analyst.permits(Permission::WriteSubscribers));
+        assert!(!Role::Analyst.permits(Permission::PublishNewsletters));
+    }
+
+    async fn status_for(role: Option<Role>) -> u16 {
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(
+                    Templates::new(&TemplateSettings { hot_reload: false }).unwrap(),
+                ))
+                .wrap(RequirePermission(Permission::PublishNewsletters))
+                .wrap_fn(move |req, srv| {
+                    if let Some(role) = role {
+                        req.extensions_mut().insert(role);
+                    }
+                    srv.call(req)
+                })
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let response = actix_web::test::call_service(&app, TestRequest::get().to_request()).await;
+        response.status().as_u16()
+    }
+
+    #[actix_web::test]
+    async fn the_guard_checks_the_role_of_the_request() {
+        assert_eq!(status_for(Some(Role::Editor)).await, 200);
+        assert_eq!(status_for(Some(Role::Analyst)).await, 403);
+        assert_eq!(status_for(None).await, 403);
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+    sync::Arc,
+};
+
+use actix_session::SessionExt;
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{
+        header::{ContentType, ORIGIN},
+        Method,
+    },
+    web, FromRequest, HttpMessage, HttpRequest, HttpResponse,
+};
+
+use crate::{
+    i18n::I18n,
+    session_state::TypedSession,
+    templates::Templates,
+    utils::{constant_time_eq, e500},
+};
+
+use super::middleware::MiddlewareFuture;
+
+/// The header carrying the token, for scripts.
+const CSRF_HEADER: &str = "X-CSRF-Token";
+const FORM_CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
+
+/// The hidden field of the forms carrying the token.
+#[derive(serde::Deserialize)]
+struct CsrfField {
+    csrf_token: Option<String>,
+}
+
+/// Reject the POST requests a cross-site form could send, unless they carry
+/// the CSRF token of their session, in the `csrf_token` field or the
+/// `X-CSRF-Token` header. Forms get the token from `TypedSession::csrf_token`.
+///
+/// Other content types, such as the JSON of the API, cannot be sent across
+/// sites without a CORS preflight, and are let through.
+/// Must run inside the `SessionMiddleware`.
+#[derive(Clone)]
+pub struct CsrfProtection {
+    own_origin: Arc<str>,
+}
+
+/// Public forms that are embedded in other sites, as the iframe widget does.
+/// Their session cookie is `SameSite`, so it is not sent from a cross-site
+/// iframe: they are accepted without a token when the `Origin` header is our
+/// own, since a form on another site would name that site.
+const EMBEDDED_FORM_PATHS: &[&str] = &["/subscriptions"];
+
+impl CsrfProtection {
+    /// `base_url` is the origin our own pages are served from.
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            own_origin: base_url.trim_end_matches('/').into(),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for CsrfProtection
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = CsrfProtectionMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CsrfProtectionMiddleware {
+            service: Rc::new(service),
+            own_origin: self.own_origin.clone(),
+        }))
+    }
+}
+
+pub struct CsrfProtectionMiddleware<S> {
+    service: Rc<S>,
+    own_origin: Arc<str>,
+}
+
+impl<S, B> Service<ServiceRequest> for CsrfProtectionMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = MiddlewareFuture<B>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let own_origin = self.own_origin.clone();
+        Box::pin(async move {
+            if !requires_token(&req) || is_embedded_form_from(&req, &own_origin) {
+                return Ok(service.call(req).await?.map_into_left_body());
+            }
+            let expected = TypedSession::from(req.get_session())
+                .get_csrf_token()
+                .map_err(e500)?;
+            let submitted = match req.headers().get(CSRF_HEADER).and_then(|h| h.to_str().ok()) {
+                Some(token) => Some(token.to_string()),
+                None => read_form_token(&mut req).await?,
+            };
+            let valid = matches!(
+                (&expected, &submitted),
+                (Some(expected), Some(submitted))
+                    if constant_time_eq(expected.as_bytes(), submitted.as_bytes())
+            );
+            if !valid {
+                tracing::warn!(
+                    path = %req.path(),
+                    has_session_token = expected.is_some(),
+                    has_submitted_token = submitted.is_some(),
+                    "Rejected a request without a valid CSRF token"
+                );
+                let (request, _) = req.into_parts();
+                let response = expired_form_page(&request)?;
+                return Ok(ServiceResponse::new(request, response).map_into_right_body());
+            }
+            Ok(service.call(req).await?.map_into_left_body())
+        })
+    }
+}
+
+/// Written in the language asked for by the browser.
+fn expired_form_page(request: &HttpRequest) -> Result<HttpResponse, actix_web::Error> {
+    let i18n = request
+        .app_data::<web::Data<I18n>>()
+        .ok_or_else(|| e500("The translations are not configured"))?;
+    let templates = request
+        .app_data::<web::Data<Templates>>()
+        .ok_or_else(|| e500("The templates are not configured"))?;
+    let translator = i18n.translator(i18n.request_locale(request));
+    let body = templates
+        .render("expired_form.html", &translator.context())
+        .map_err(e500)?;
+    Ok(HttpResponse::Forbidden()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// Whether a browser would send the request from another site without a
+/// CORS preflight: a POST with no body or the body of an HTML form.
+fn requires_token(req: &ServiceRequest) -> bool {
+    if req.method() != Method::POST {
+        return false;
+    }
+    let content_type = req.content_type().to_ascii_lowercase();
+    content_type.is_empty()
+        || content_type == FORM_CONTENT_TYPE
+        || content_type == "multipart/form-data"
+        || content_type == "text/plain"
+}
+
+fn is_embedded_form_from(req: &ServiceRequest, own_origin: &str) -> bool {
+    EMBEDDED_FORM_PATHS.contains(&req.path())
+        && req.headers().get(ORIGIN).and_then(|h| h.to_str().ok()) == Some(own_origin)
+}
+
+/// Read the token from a urlencoded body, and put the body back for the handler.
+async fn read_form_token(req: &mut ServiceRequest) -> Result<Option<String>, actix_web::Error> {
+    if req.content_type() != FORM_CONTENT_TYPE {
+        return Ok(None);
+    }
+    let (http_request, payload) = req.parts_mut();
+    let body = web::Bytes::from_request(http_request, payload).await?;
+    let token = std::str::from_utf8(&body)
+        .ok()
+        .and_then(|query| web::Query::<CsrfField>::from_query(query).ok())
+        .and_then(|field| field.into_inner().csrf_token);
+    let (_, mut replayed) = actix_http::h1::Payload::create(true);
+    replayed.unread_data(body);
+    req.set_payload(Payload::from(replayed));
+    Ok(token)
+}
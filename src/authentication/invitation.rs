@@ -0,0 +1,75 @@
+use anyhow::Context;
+use chrono::Utc;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::password_reset::{generate_emailed_token, hash_emailed_token};
+
+/// Issue the single-use token that lets an invited user set their password.
+#[tracing::instrument(name = "Create invitation", skip(transaction))]
+pub async fn create_invitation(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+    invited_by: Uuid,
+    validity: chrono::Duration,
+) -> Result<Secret<String>, anyhow::Error> {
+    let token = generate_emailed_token();
+    sqlx::query!(
+        r#"
+        INSERT INTO user_invitations (token_hash, user_id, invited_by, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        hash_emailed_token(&token),
+        user_id,
+        invited_by,
+        Utc::now() + validity,
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to store the invitation")?;
+    Ok(Secret::new(token))
+}
+
+/// The username of the invitee, if `token` is a pending invitation.
+#[tracing::instrument(name = "Get pending invitation", skip(token, pool))]
+pub async fn get_pending_invitation(
+    token: &Secret<String>,
+    pool: &PgPool,
+) -> Result<Option<String>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT u.username FROM user_invitations i JOIN users u ON u.user_id = i.user_id
+        WHERE i.token_hash = $1 AND i.accepted_at IS NULL AND i.expires_at > now()
+            AND u.disabled_at IS NULL
+        "#,
+        hash_emailed_token(token.expose_secret()),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the invitation")?;
+    Ok(row.map(|row| row.username))
+}
+
+/// Mark the invitation as accepted and return the invitee, unless it is
+/// unknown, expired or already accepted.
+#[tracing::instrument(name = "Accept invitation", skip(token, transaction))]
+pub async fn accept_invitation(
+    transaction: &mut Transaction<'_, Postgres>,
+    token: &Secret<String>,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE user_invitations i SET accepted_at = now()
+        FROM users u
+        WHERE i.token_hash = $1 AND i.accepted_at IS NULL AND i.expires_at > now()
+            AND u.user_id = i.user_id AND u.disabled_at IS NULL
+        RETURNING i.user_id
+        "#,
+        hash_emailed_token(token.expose_secret()),
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to accept the invitation")?;
+    Ok(row.map(|row| row.user_id))
+}
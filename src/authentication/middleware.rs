@@ -0,0 +1,150 @@
+use std::{
+    future::{ready, Future, Ready},
+    ops::Deref,
+    pin::Pin,
+    rc::Rc,
+};
+
+use actix_session::SessionExt;
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    web, HttpMessage,
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    configuration::TwoFactorSettings,
+    session_state::TypedSession,
+    utils::{e500, see_other},
+};
+
+use super::user_sessions::touch_user_session;
+
+/// The id of the logged-in admin, set by `RejectAnonymousUsers`.
+#[derive(Copy, Clone, Debug)]
+pub struct UserId(Uuid);
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Deref for UserId {
+    type Target = Uuid;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+pub(super) type MiddlewareFuture<B> =
+    Pin<Box<dyn Future<Output = Result<ServiceResponse<EitherBody<B>>, actix_web::Error>>>>;
+
+/// Redirect to the login form unless the session belongs to a logged-in admin,
+/// and has not been revoked.
+/// Both their `UserId` and `Role` are then available to the handlers.
+/// Meant for `wrap` on the `/admin` scope.
+pub struct RejectAnonymousUsers;
+
+impl<S, B> Transform<S, ServiceRequest> for RejectAnonymousUsers
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = RejectAnonymousUsersMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RejectAnonymousUsersMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RejectAnonymousUsersMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RejectAnonymousUsersMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = MiddlewareFuture<B>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        Box::pin(async move {
+            let session = TypedSession::from(req.get_session());
+            let user_id = match session.get_user_id().map_err(e500)? {
+                Some(user_id) => user_id,
+                None => return Ok(req.into_response(see_other("/login")).map_into_right_body()),
+            };
+            let pool = req
+                .app_data::<web::Data<PgPool>>()
+                .cloned()
+                .ok_or_else(|| e500("The database pool is not configured"))?;
+            // Sessions opened before they were recorded have no id, and are let go.
+            let role = match session.get_session_id().map_err(e500)? {
+                Some(session_id) => touch_user_session(user_id, session_id, &pool)
+                    .await
+                    .map_err(e500)?,
+                None => None,
+            };
+            let role = match role {
+                Some(role) => role,
+                None => {
+                    tracing::info!(%user_id, "Rejected a revoked session");
+                    session.log_out();
+                    return Ok(req.into_response(see_other("/login")).map_into_right_body());
+                }
+            };
+            req.extensions_mut().insert(UserId(user_id));
+            req.extensions_mut().insert(role);
+            Ok(service.call(req).await?.map_into_left_body())
+        })
+    }
+}
+
+/// When two-factor authentication is required, confine admins who have not
+/// enrolled yet to the enrollment page. Must run after `RejectAnonymousUsers`.
+pub fn require_two_factor_enrollment<S, B>(req: ServiceRequest, srv: &S) -> MiddlewareFuture<B>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    let required = req
+        .app_data::<web::Data<TwoFactorSettings>>()
+        .map(|settings| settings.required)
+        .unwrap_or(false);
+    let exempt = req.path().starts_with("/admin/two_factor") || req.path() == "/admin/logout";
+    if !required || exempt {
+        let response = srv.call(req);
+        return Box::pin(async move { Ok(response.await?.map_into_left_body()) });
+    }
+    match TypedSession::from(req.get_session()).is_two_factor_enrolled() {
+        Ok(true) => {
+            let response = srv.call(req);
+            Box::pin(async move { Ok(response.await?.map_into_left_body()) })
+        }
+        Ok(false) => {
+            let response = see_other("/admin/two_factor");
+            Box::pin(async move { Ok(req.into_response(response).map_into_right_body()) })
+        }
+        Err(e) => {
+            let e = e500(e);
+            Box::pin(async move { Err(e) })
+        }
+    }
+}
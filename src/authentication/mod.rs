@@ -0,0 +1,34 @@
+mod api_token;
+mod authorization;
+mod csrf;
+mod invitation;
+mod middleware;
+mod password;
+mod password_reset;
+mod two_factor;
+mod user_sessions;
+
+pub use api_token::{
+    authenticate_api_caller, generate_api_token, hash_api_token, ApiCaller, ApiScope,
+};
+pub use authorization::{get_role, Permission, RequirePermission, Role};
+pub use csrf::CsrfProtection;
+pub use invitation::{accept_invitation, create_invitation, get_pending_invitation};
+pub use middleware::{require_two_factor_enrollment, RejectAnonymousUsers, UserId};
+pub use password::{
+    basic_authentication, change_password, compute_password_hash, validate_credentials,
+    validate_credentials_throttled, validate_new_password, AuthError, Credentials, PasswordHashing,
+};
+pub use password_reset::{
+    consume_password_reset_token, create_password_reset_token, find_resettable_account,
+    is_password_reset_token_valid, ResettableAccount,
+};
+pub use two_factor::{
+    confirm_two_factor_enrollment, disable_two_factor, get_two_factor_state, otpauth_uri,
+    qr_code_svg, regenerate_recovery_codes, start_two_factor_enrollment, totp_code,
+    verify_second_factor, EnrollmentOutcome, TwoFactorState,
+};
+pub use user_sessions::{
+    list_user_sessions, record_user_session, revoke_all_user_sessions, revoke_other_user_sessions,
+    revoke_user_session, UserSession,
+};
@@ -0,0 +1,413 @@
+use std::str::FromStr;
+
+use actix_web::http::header::HeaderMap;
+
+use super::revoke_all_user_sessions;
+use crate::configuration::PasswordHashingSettings;
+use crate::rate_limit::RateLimiter;
+use crate::telemetry::spawn_blocking_with_tracing;
+use anyhow::Context;
+use argon2::{
+    password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash, PasswordHasher,
+    PasswordVerifier, Version,
+};
+use rand::Rng;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::{PgPool, Postgres, Transaction};
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    #[error("Invalid Credentials")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+impl FromStr for Credentials {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut credentials = s.splitn(2, ':');
+        let username = credentials
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("A username must be provided for basic auth"))?
+            .to_string();
+
+        let password = credentials
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("A password must be provided for basic auth"))?
+            .to_string();
+        Ok(Self {
+            username,
+            password: Secret::new(password),
+        })
+    }
+}
+
+/// Extract the credentials of the `Authorization: Basic` header.
+pub fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+    let header_value = headers
+        .get("Authorization")
+        .context("The Authorization header is missing")?
+        .to_str()
+        .context("The auth header wasn't a valid utf8 string")?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("The authscheme wasnt Basic")?;
+    let decoded_bytes = base64::decode_config(base64encoded_segment, base64::STANDARD)
+        .context("Failed to decode base64")?;
+    let decoded_credentials =
+        String::from_utf8(decoded_bytes).context("the decoded wasn't a valid utf8")?;
+
+    Credentials::from_str(&decoded_credentials)
+}
+
+/// Validate credentials, unless too many attempts failed recently for this
+/// username or from this IP. Refused attempts get the same error as a wrong
+/// password, and skip the password hashing altogether.
+#[tracing::instrument(
+    name = "Validate credentials with throttling",
+    skip(credentials, pool, rate_limiter, hashing)
+)]
+pub async fn validate_credentials_throttled(
+    credentials: Credentials,
+    ip: &str,
+    pool: &PgPool,
+    rate_limiter: &RateLimiter,
+    hashing: &PasswordHashing,
+) -> Result<uuid::Uuid, AuthError> {
+    let username = credentials.username.clone();
+    if let Some(block) = rate_limiter
+        .check_login(&username, ip)
+        .await
+        .context("Failed to check the login throttling")?
+    {
+        tracing::warn!(%block, %username, %ip, "Rejected a login attempt");
+        return Err(AuthError::InvalidCredentials(anyhow::anyhow!(block)));
+    }
+    match validate_credentials(credentials, pool, hashing).await {
+        Ok(user_id) => {
+            rate_limiter
+                .record_login_success(&username)
+                .await
+                .context("Failed to reset the login failures")?;
+            Ok(user_id)
+        }
+        Err(AuthError::InvalidCredentials(e)) => {
+            rate_limiter
+                .record_login_failure(&username, ip)
+                .await
+                .context("Failed to record a login failure")?;
+            Err(AuthError::InvalidCredentials(e))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// How new password hashes are made: Argon2id, with configurable costs.
+#[derive(Clone)]
+pub struct PasswordHashing {
+    params: Params,
+    /// Verified against when the username is unknown, so that the response
+    /// takes as long as for a known username.
+    dummy_hash: Secret<String>,
+}
+
+impl PasswordHashing {
+    pub fn new(settings: &PasswordHashingSettings) -> Result<Self, anyhow::Error> {
+        let params = Params::new(
+            settings.memory_kib,
+            settings.iterations,
+            settings.parallelism,
+            None,
+        )
+        .context("Invalid Argon2 parameters")?;
+        let mut hashing = Self {
+            params,
+            dummy_hash: Secret::new(String::new()),
+        };
+        let random_password: String =
+            std::iter::repeat_with(|| rand::thread_rng().sample(rand::distributions::Alphanumeric))
+                .map(char::from)
+                .take(32)
+                .collect();
+        hashing.dummy_hash = hashing.hash(&Secret::new(random_password))?;
+        Ok(hashing)
+    }
+
+    fn hash(&self, password: &Secret<String>) -> Result<Secret<String>, anyhow::Error> {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        let password_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+            .hash_password(password.expose_secret().as_bytes(), &salt)
+            .context("Failed to hash the password")?
+            .to_string();
+        Ok(Secret::new(password_hash))
+    }
+
+    /// Whether a stored hash was made with another algorithm, or with lower costs
+    /// than the current ones. Stronger hashes are kept, so that lowering the
+    /// settings doesn't weaken the passwords already stored.
+    fn is_outdated(&self, password_hash: &PasswordHash) -> bool {
+        if password_hash.algorithm != Algorithm::Argon2id.ident()
+            || password_hash.version != Some(Version::V0x13 as u32)
+        {
+            return true;
+        }
+        match Params::try_from(password_hash) {
+            Ok(params) => {
+                params.m_cost() < self.params.m_cost()
+                    || params.t_cost() < self.params.t_cost()
+                    || params.p_cost() < self.params.p_cost()
+            }
+            Err(_) => true,
+        }
+    }
+}
+
+/// Check the password of `credentials`. When it matches a hash made with
+/// outdated parameters, the password is rehashed with the current ones.
+#[tracing::instrument(name = "Validate credentials", skip(credentials, pool, hashing))]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    pool: &PgPool,
+    hashing: &PasswordHashing,
+) -> Result<uuid::Uuid, AuthError> {
+    let mut user_id = None;
+    let mut expected_password_hash = hashing.dummy_hash.clone();
+    if let Some((stored_user_id, stored_expected_password_hash)) =
+        get_stored_credentials(&credentials.username, pool)
+            .await
+            .map_err(AuthError::UnexpectedError)?
+    {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_expected_password_hash;
+    }
+
+    let stored_password_hash = expected_password_hash.clone();
+    let known_user = user_id.is_some();
+    let hashing = hashing.clone();
+    let upgraded_password_hash = spawn_blocking_with_tracing(move || {
+        let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
+            .context("Failed to parse the hash in PHC string format")?;
+        verify_password(&expected_password_hash, &credentials.password)?;
+        if !known_user || !hashing.is_outdated(&expected_password_hash) {
+            return Ok(None);
+        }
+        hashing
+            .hash(&credentials.password)
+            .map(Some)
+            .map_err(AuthError::UnexpectedError)
+    })
+    .await
+    .context("Failed to spawn blocking task")??;
+
+    let user_id = user_id
+        .ok_or_else(|| anyhow::anyhow!("Unknown username."))
+        .map_err(AuthError::InvalidCredentials)?;
+    if let Some(password_hash) = upgraded_password_hash {
+        // The login has succeeded already, a failed upgrade can wait for the next one.
+        if let Err(e) =
+            upgrade_password_hash(user_id, &stored_password_hash, password_hash, pool).await
+        {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to upgrade a password hash."
+            );
+        }
+    }
+    Ok(user_id)
+}
+
+#[tracing::instrument(
+    name = "Verify password hash",
+    skip(expected_password_hash, password_candidate)
+)]
+fn verify_password(
+    expected_password_hash: &PasswordHash,
+    password_candidate: &Secret<String>,
+) -> Result<(), AuthError> {
+    Argon2::default()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            expected_password_hash,
+        )
+        .context("Password verification failed")
+        .map_err(AuthError::InvalidCredentials)
+}
+
+/// Swap the hash for one with the current parameters, unless the password
+/// has been changed in the meantime.
+#[tracing::instrument(
+    name = "Upgrade password hash",
+    skip(stored_password_hash, password_hash, pool)
+)]
+async fn upgrade_password_hash(
+    user_id: uuid::Uuid,
+    stored_password_hash: &Secret<String>,
+    password_hash: Secret<String>,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE users SET password_hash = $1
+        WHERE user_id = $2 AND password_hash = $3
+        "#,
+        password_hash.expose_secret(),
+        user_id,
+        stored_password_hash.expose_secret(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the upgraded password hash")?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "get stored credentials", skip(username, pool))]
+async fn get_stored_credentials(
+    username: &str,
+    pool: &PgPool,
+) -> Result<Option<(uuid::Uuid, Secret<String>)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id, password_hash AS "password_hash!" FROM users
+        WHERE username = $1 AND password_hash IS NOT NULL AND disabled_at IS NULL
+        "#,
+        username,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perfom query to validate credentials")?
+    .map(|x| (x.user_id, Secret::new(x.password_hash)));
+    Ok(row)
+}
+
+const MIN_PASSWORD_LENGTH: usize = 12;
+const MAX_PASSWORD_LENGTH: usize = 128;
+
+/// Check a new password typed twice in a form. The error is meant for the user.
+pub fn validate_new_password(
+    password: &Secret<String>,
+    password_check: &Secret<String>,
+) -> Result<(), String> {
+    if password.expose_secret() != password_check.expose_secret() {
+        return Err("You entered two different new passwords.".into());
+    }
+    let length = password.expose_secret().chars().count();
+    if !(MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&length) {
+        return Err(format!(
+            "The new password must be between {} and {} characters long.",
+            MIN_PASSWORD_LENGTH, MAX_PASSWORD_LENGTH
+        ));
+    }
+    Ok(())
+}
+
+/// Hash a new password, off the async executor.
+pub async fn compute_password_hash(
+    password: Secret<String>,
+    hashing: &PasswordHashing,
+) -> Result<Secret<String>, anyhow::Error> {
+    let hashing = hashing.clone();
+    spawn_blocking_with_tracing(move || hashing.hash(&password))
+        .await
+        .context("Failed to spawn blocking task")?
+}
+
+/// Replace the password of a user, and log out all of their sessions.
+#[tracing::instrument(name = "Change password", skip(password_hash, transaction))]
+pub async fn change_password(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: uuid::Uuid,
+    password_hash: Secret<String>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"UPDATE users SET password_hash = $1 WHERE user_id = $2"#,
+        password_hash.expose_secret(),
+        user_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to change the password")?;
+    revoke_all_user_sessions(transaction, user_id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashing(memory_kib: u32, iterations: u32, parallelism: u32) -> PasswordHashing {
+        PasswordHashing::new(&PasswordHashingSettings {
+            memory_kib,
+            iterations,
+            parallelism,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn hashes_made_with_the_current_parameters_are_up_to_date() {
+        let hashing = hashing(8192, 1, 1);
+        let password_hash = hashing
+            .hash(&Secret::new("correct horse".to_string()))
+            .unwrap();
+        let password_hash = PasswordHash::new(password_hash.expose_secret()).unwrap();
+        assert!(!hashing.is_outdated(&password_hash));
+    }
+
+    #[test]
+    fn hashes_made_with_lower_costs_or_other_algorithms_are_outdated() {
+        let hashing = hashing(8192, 2, 2);
+        for stored in [
+            "$argon2id$v=19$m=4096,t=2,p=2$gZiV/M1gPc22ElAH/Jh1Hw$\
+CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno",
+            "$argon2id$v=19$m=8192,t=1,p=2$gZiV/M1gPc22ElAH/Jh1Hw$\
+CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno",
+            "$argon2id$v=19$m=8192,t=2,p=1$gZiV/M1gPc22ElAH/Jh1Hw$\
+CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno",
+            "$argon2i$v=19$m=8192,t=2,p=2$gZiV/M1gPc22ElAH/Jh1Hw$\
+CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno",
+        ] {
+            let password_hash = PasswordHash::new(stored).unwrap();
+            assert!(hashing.is_outdated(&password_hash), "{}", stored);
+        }
+    }
+
+    #[test]
+    fn hashes_made_with_higher_costs_are_kept() {
+        let hashing = hashing(8192, 1, 1);
+        for stored in [
+            "$argon2id$v=19$m=16384,t=1,p=1$gZiV/M1gPc22ElAH/Jh1Hw$\
+CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno",
+            "$argon2id$v=19$m=8192,t=3,p=1$gZiV/M1gPc22ElAH/Jh1Hw$\
+CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno",
+            "$argon2id$v=19$m=8192,t=1,p=2$gZiV/M1gPc22ElAH/Jh1Hw$\
+CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno",
+        ] {
+            let password_hash = PasswordHash::new(stored).unwrap();
+            assert!(!hashing.is_outdated(&password_hash), "{}", stored);
+        }
+    }
+
+    #[test]
+    fn the_dummy_hash_uses_the_current_parameters() {
+        let hashing = hashing(8192, 1, 1);
+        let dummy_hash = PasswordHash::new(hashing.dummy_hash.expose_secret()).unwrap();
+        assert!(!hashing.is_outdated(&dummy_hash));
+    }
+
+    #[test]
+    fn invalid_parameters_are_rejected() {
+        assert!(PasswordHashing::new(&PasswordHashingSettings {
+            memory_kib: 1,
+            iterations: 0,
+            parallelism: 1,
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,127 @@
+use anyhow::Context;
+use chrono::Utc;
+use rand::Rng;
+use secrecy::{ExposeSecret, Secret};
+use sha3::{Digest, Sha3_256};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// An account a password reset link can be sent for.
+pub struct ResettableAccount {
+    pub user_id: Uuid,
+    pub email: String,
+}
+
+/// Look up the account behind `username`, if it has an email to send the link to.
+#[tracing::instrument(name = "Find resettable account", skip(pool))]
+pub async fn find_resettable_account(
+    username: &str,
+    pool: &PgPool,
+) -> Result<Option<ResettableAccount>, anyhow::Error> {
+    let account = sqlx::query!(
+        r#"
+        SELECT user_id, email FROM users
+        WHERE username = $1 AND password_hash IS NOT NULL AND disabled_at IS NULL
+        "#,
+        username,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the account")?
+    .and_then(|row| {
+        row.email.map(|email| ResettableAccount {
+            user_id: row.user_id,
+            email,
+        })
+    });
+    Ok(account)
+}
+
+/// Issue a single-use reset token for `user_id`. Only its hash is stored.
+#[tracing::instrument(name = "Create password reset token", skip(transaction))]
+pub async fn create_password_reset_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+    validity: chrono::Duration,
+) -> Result<Secret<String>, anyhow::Error> {
+    let token = generate_emailed_token();
+    sqlx::query!(
+        r#"
+        INSERT INTO password_reset_tokens (token_hash, user_id, expires_at)
+        VALUES ($1, $2, $3)
+        "#,
+        hash_emailed_token(&token),
+        user_id,
+        Utc::now() + validity,
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to store the password reset token")?;
+    Ok(Secret::new(token))
+}
+
+/// Whether `token` could still be used, to warn early about a stale link.
+#[tracing::instrument(name = "Check password reset token", skip(token, pool))]
+pub async fn is_password_reset_token_valid(
+    token: &Secret<String>,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id FROM password_reset_tokens
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now()
+        "#,
+        hash_emailed_token(token.expose_secret()),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the password reset token")?;
+    Ok(row.is_some())
+}
+
+/// Burn `token` and return the user it was issued for, unless it is unknown,
+/// expired or already used. The other pending tokens of the user are burnt too.
+#[tracing::instrument(name = "Consume password reset token", skip(token, transaction))]
+pub async fn consume_password_reset_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    token: &Secret<String>,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let user_id = sqlx::query!(
+        r#"
+        UPDATE password_reset_tokens SET used_at = now()
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now()
+        RETURNING user_id
+        "#,
+        hash_emailed_token(token.expose_secret()),
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to consume the password reset token")?
+    .map(|row| row.user_id);
+    if let Some(user_id) = user_id {
+        sqlx::query!(
+            r#"
+            UPDATE password_reset_tokens SET used_at = now()
+            WHERE user_id = $1 AND used_at IS NULL
+            "#,
+            user_id,
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to invalidate the other password reset tokens")?;
+    }
+    Ok(user_id)
+}
+
+/// A token sent by email, to be used once. Shared with the invitations.
+pub(super) fn generate_emailed_token() -> String {
+    let mut rng = rand::thread_rng();
+    std::iter::repeat_with(|| rng.sample(rand::distributions::Alphanumeric))
+        .map(char::from)
+        .take(40)
+        .collect()
+}
+
+pub(super) fn hash_emailed_token(token: &str) -> String {
+    hex::encode(Sha3_256::digest(token.as_bytes()))
+}
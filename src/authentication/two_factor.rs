@@ -0,0 +1,363 @@
+//! RFC 6238 time-based one-time passwords, and the recovery codes that come with them.
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use secrecy::{ExposeSecret, Secret};
+use sha1::Sha1;
+use sha3::{Digest, Sha3_256};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::utils::constant_time_eq;
+
+const DIGITS: u32 = 6;
+const STEP_SECS: i64 = 30;
+/// Codes from the previous and next steps are accepted too, to absorb clock drift.
+const SKEW_STEPS: i64 = 1;
+const RECOVERY_CODES: usize = 10;
+
+const BASE32: base32::Alphabet = base32::Alphabet::RFC4648 { padding: false };
+
+/// A new random 160-bit secret, base32-encoded as authenticator apps expect it.
+pub fn generate_totp_secret() -> Secret<String> {
+    let bytes: [u8; 20] = rand::thread_rng().gen();
+    Secret::new(base32::encode(BASE32, &bytes))
+}
+
+/// The `otpauth://` URI authenticator apps enroll from, usually through a QR code.
+pub fn otpauth_uri(secret: &Secret<String>, issuer: &str, account: &str) -> String {
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}\
+        &algorithm=SHA1&digits={digits}&period={period}",
+        issuer = urlencoding::encode(issuer),
+        account = urlencoding::encode(account),
+        secret = secret.expose_secret(),
+        digits = DIGITS,
+        period = STEP_SECS,
+    )
+}
+
+pub fn qr_code_svg(data: &str) -> Result<String, anyhow::Error> {
+    let code = qrcode::QrCode::new(data.as_bytes()).context("Failed to encode the QR code")?;
+    Ok(code
+        .render::<qrcode::render::svg::Color>()
+        .min_dimensions(200, 200)
+        .build())
+}
+
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    // Dynamic truncation, RFC 4226 section 5.3.
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    binary % 10u32.pow(DIGITS)
+}
+
+/// The time step matched by `code`, if it is valid around `unix_time`.
+fn matching_step(secret: &Secret<String>, code: &str, unix_time: i64) -> Option<i64> {
+    let key = base32::decode(BASE32, secret.expose_secret())?;
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let current_step = unix_time / STEP_SECS;
+    (current_step - SKEW_STEPS..=current_step + SKEW_STEPS)
+        .filter(|step| *step >= 0)
+        .find(|step| {
+            let expected = format!("{:06}", hotp(&key, *step as u64));
+            constant_time_eq(expected.as_bytes(), code.as_bytes())
+        })
+}
+
+pub fn generate_recovery_codes() -> Vec<Secret<String>> {
+    let mut rng = rand::thread_rng();
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            let code: String =
+                std::iter::repeat_with(|| rng.sample(rand::distributions::Alphanumeric))
+                    .map(|c| char::from(c).to_ascii_lowercase())
+                    .take(10)
+                    .collect();
+            Secret::new(format!("{}-{}", &code[..5], &code[5..]))
+        })
+        .collect()
+}
+
+/// Recovery codes are compared case-insensitively, ignoring dashes and spaces.
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hex::encode(Sha3_256::digest(normalized.as_bytes()))
+}
+
+pub struct TwoFactorState {
+    pub secret: Option<Secret<String>>,
+    pub enabled: bool,
+}
+
+#[tracing::instrument(name = "Get two-factor state", skip(pool))]
+pub async fn get_two_factor_state(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<TwoFactorState, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT totp_secret, totp_enabled_at FROM users WHERE user_id = $1"#,
+        user_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to retrieve the two-factor state")?;
+    Ok(TwoFactorState {
+        secret: row.totp_secret.map(Secret::new),
+        enabled: row.totp_enabled_at.is_some(),
+    })
+}
+
+/// Store a new pending secret, unless two-factor authentication is already enabled.
+#[tracing::instrument(name = "Start two-factor enrollment", skip(pool))]
+pub async fn start_two_factor_enrollment(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Secret<String>, anyhow::Error> {
+    let secret = generate_totp_secret();
+    sqlx::query!(
+        r#"
+        UPDATE users SET totp_secret = $2, totp_last_step = NULL
+        WHERE user_id = $1 AND totp_enabled_at IS NULL
+        "#,
+        user_id,
+        secret.expose_secret(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the pending TOTP secret")?;
+    Ok(secret)
+}
+
+pub enum EnrollmentOutcome {
+    /// Holds the recovery codes to show the user, once.
+    Enabled(Vec<Secret<String>>),
+    InvalidCode,
+    /// Already enabled, by a previous submission for instance, or never started.
+    NothingPending,
+}
+
+/// Enable two-factor authentication if `code` matches the pending secret.
+#[tracing::instrument(name = "Confirm two-factor enrollment", skip(code, pool))]
+pub async fn confirm_two_factor_enrollment(
+    user_id: Uuid,
+    code: &str,
+    pool: &PgPool,
+) -> Result<EnrollmentOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let row = sqlx::query!(
+        r#"
+        SELECT totp_secret FROM users
+        WHERE user_id = $1 AND totp_enabled_at IS NULL
+        FOR UPDATE
+        "#,
+        user_id,
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to retrieve the pending TOTP secret")?;
+    let secret = match row.and_then(|r| r.totp_secret) {
+        Some(secret) => Secret::new(secret),
+        None => return Ok(EnrollmentOutcome::NothingPending),
+    };
+    let step = match matching_step(&secret, code, chrono::Utc::now().timestamp()) {
+        Some(step) => step,
+        None => return Ok(EnrollmentOutcome::InvalidCode),
+    };
+    sqlx::query!(
+        r#"UPDATE users SET totp_enabled_at = now(), totp_last_step = $2 WHERE user_id = $1"#,
+        user_id,
+        step,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to enable two-factor authentication")?;
+    let codes = replace_recovery_codes(&mut transaction, user_id).await?;
+    transaction.commit().await?;
+    Ok(EnrollmentOutcome::Enabled(codes))
+}
+
+/// Check a TOTP code, or else a recovery code, for a user with two-factor enabled.
+/// Both can only be used once.
+#[tracing::instrument(name = "Verify second factor", skip(code, pool))]
+pub async fn verify_second_factor(
+    user_id: Uuid,
+    code: &str,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let state = get_two_factor_state(user_id, pool).await?;
+    let secret = match state.secret {
+        Some(secret) if state.enabled => secret,
+        _ => return Ok(false),
+    };
+    if let Some(step) = matching_step(&secret, code, chrono::Utc::now().timestamp()) {
+        let result = sqlx::query!(
+            r#"
+            UPDATE users SET totp_last_step = $2
+            WHERE user_id = $1 AND (totp_last_step IS NULL OR totp_last_step < $2)
+            "#,
+            user_id,
+            step,
+        )
+        .execute(pool)
+        .await
+        .context("Failed to record the TOTP time step")?;
+        return Ok(result.rows_affected() == 1);
+    }
+    let result = sqlx::query!(
+        r#"
+        UPDATE recovery_codes SET used_at = now()
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+        "#,
+        user_id,
+        hash_recovery_code(code),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to consume the recovery code")?;
+    if result.rows_affected() == 1 {
+        tracing::warn!(%user_id, "A recovery code was used to log in");
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+#[tracing::instrument(name = "Regenerate recovery codes", skip(pool))]
+pub async fn regenerate_recovery_codes(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Vec<Secret<String>>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let codes = replace_recovery_codes(&mut transaction, user_id).await?;
+    transaction.commit().await?;
+    Ok(codes)
+}
+
+async fn replace_recovery_codes(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> Result<Vec<Secret<String>>, anyhow::Error> {
+    sqlx::query!(r#"DELETE FROM recovery_codes WHERE user_id = $1"#, user_id)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete the previous recovery codes")?;
+    let codes = generate_recovery_codes();
+    for code in &codes {
+        sqlx::query!(
+            r#"INSERT INTO recovery_codes (user_id, code_hash) VALUES ($1, $2)"#,
+            user_id,
+            hash_recovery_code(code.expose_secret()),
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to store a recovery code")?;
+    }
+    Ok(codes)
+}
+
+#[tracing::instrument(name = "Disable two-factor authentication", skip(pool))]
+pub async fn disable_two_factor(user_id: Uuid, pool: &PgPool) -> Result<(), anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        UPDATE users SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_step = NULL
+        WHERE user_id = $1
+        "#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to disable two-factor authentication")?;
+    sqlx::query!(r#"DELETE FROM recovery_codes WHERE user_id = $1"#, user_id)
+        .execute(&mut transaction)
+        .await
+        .context("Failed to delete the recovery codes")?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// The code for `secret` at `unix_time`, as an authenticator app would show it.
+pub fn totp_code(secret: &Secret<String>, unix_time: i64) -> Option<String> {
+    let key = base32::decode(BASE32, secret.expose_secret())?;
+    Some(format!("{:06}", hotp(&key, (unix_time / STEP_SECS) as u64)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The SHA-1 seed of the RFC 6238 test vectors.
+    fn rfc_secret() -> Secret<String> {
+        Secret::new(base32::encode(BASE32, b"12345678901234567890"))
+    }
+
+    #[test]
+    fn rfc_6238_test_vectors_are_matched() {
+        // The RFC lists 8-digit codes: we keep their last 6 digits.
+        for (time, code) in [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ] {
+            assert_eq!(
+                matching_step(&rfc_secret(), code, time),
+                Some(time / STEP_SECS),
+                "{}",
+                time
+            );
+        }
+    }
+
+    #[test]
+    fn codes_from_neighbouring_steps_are_accepted() {
+        assert_eq!(
+            matching_step(&rfc_secret(), "287082", 59 + 30),
+            Some(59 / 30)
+        );
+        assert_eq!(matching_step(&rfc_secret(), "287082", 59 + 90), None);
+    }
+
+    #[test]
+    fn malformed_codes_are_rejected() {
+        for code in ["", "28708", "2870822", "abcdef"] {
+            assert_eq!(matching_step(&rfc_secret(), code, 59), None, "{}", code);
+        }
+    }
+
+    #[test]
+    fn recovery_codes_are_normalized_before_hashing() {
+        assert_eq!(
+            hash_recovery_code("abcde-12345"),
+            hash_recovery_code(" ABCDE 12345 ")
+        );
+    }
+
+    #[test]
+    fn the_otpauth_uri_carries_the_secret_and_issuer() {
+        let secret = Secret::new("JBSWY3DPEHPK3PXP".to_string());
+        let uri = otpauth_uri(&secret, "zero2prod", "ursula le guin");
+        assert_eq!(
+            uri,
+            "otpauth://totp/zero2prod:ursula%20le%20guin?secret=JBSWY3DPEHPK3PXP\
+            &issuer=zero2prod&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+}
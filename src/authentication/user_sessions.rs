@@ -0,0 +1,164 @@
+use actix_web::{http::header, HttpRequest};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::utils::client_ip;
+
+use super::Role;
+
+const MAX_USER_AGENT_LENGTH: usize = 512;
+
+/// A logged-in session, as listed to its user.
+pub struct UserSession {
+    pub session_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub ip: String,
+    pub user_agent: String,
+}
+
+/// Record a new logged-in session for `user_id`, and return its id to store
+/// in the session state.
+#[tracing::instrument(name = "Record user session", skip(request, pool))]
+pub async fn record_user_session(
+    user_id: Uuid,
+    request: &HttpRequest,
+    pool: &PgPool,
+) -> Result<Uuid, anyhow::Error> {
+    let session_id = Uuid::new_v4();
+    let user_agent: String = request
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default()
+        .chars()
+        .take(MAX_USER_AGENT_LENGTH)
+        .collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO user_sessions (session_id, user_id, ip, user_agent)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        session_id,
+        user_id,
+        client_ip(request),
+        user_agent,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record the session")?;
+    Ok(session_id)
+}
+
+/// Mark the session as seen and return the current role of its user, or `None`
+/// if the session has been revoked, or the user disabled or deleted.
+#[tracing::instrument(name = "Touch user session", skip(pool))]
+pub async fn touch_user_session(
+    user_id: Uuid,
+    session_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<Role>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE user_sessions s SET last_seen_at = now()
+        FROM users u
+        WHERE s.session_id = $1 AND s.user_id = $2 AND s.revoked_at IS NULL
+            AND u.user_id = s.user_id AND u.disabled_at IS NULL
+        RETURNING u.role
+        "#,
+        session_id,
+        user_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to check the session")?;
+    row.map(|row| row.role.parse()).transpose()
+}
+
+/// The sessions of `user_id` that are still alive, most recently seen first.
+/// Sessions older than `max_age` are gone from Redis, whatever their record says.
+#[tracing::instrument(name = "List user sessions", skip(pool))]
+pub async fn list_user_sessions(
+    user_id: Uuid,
+    max_age: chrono::Duration,
+    pool: &PgPool,
+) -> Result<Vec<UserSession>, anyhow::Error> {
+    let sessions = sqlx::query_as!(
+        UserSession,
+        r#"
+        SELECT session_id, created_at, last_seen_at, ip, user_agent
+        FROM user_sessions
+        WHERE user_id = $1 AND revoked_at IS NULL AND created_at > $2
+        ORDER BY last_seen_at DESC
+        "#,
+        user_id,
+        Utc::now() - max_age,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to list the sessions")?;
+    Ok(sessions)
+}
+
+/// Revoke one session of `user_id`. Returns whether it was still active.
+#[tracing::instrument(name = "Revoke user session", skip(pool))]
+pub async fn revoke_user_session(
+    user_id: Uuid,
+    session_id: Uuid,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE user_sessions SET revoked_at = now()
+        WHERE session_id = $1 AND user_id = $2 AND revoked_at IS NULL
+        "#,
+        session_id,
+        user_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke the session")?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Revoke every session of `user_id` but `current_session_id`, and return how many there were.
+#[tracing::instrument(name = "Revoke other user sessions", skip(pool))]
+pub async fn revoke_other_user_sessions(
+    user_id: Uuid,
+    current_session_id: Uuid,
+    pool: &PgPool,
+) -> Result<u64, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE user_sessions SET revoked_at = now()
+        WHERE user_id = $1 AND session_id <> $2 AND revoked_at IS NULL
+        "#,
+        user_id,
+        current_session_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke the other sessions")?;
+    Ok(result.rows_affected())
+}
+
+/// Log `user_id` out everywhere, e.g. when their password changes.
+#[tracing::instrument(name = "Revoke all user sessions", skip(transaction))]
+pub async fn revoke_all_user_sessions(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE user_sessions SET revoked_at = now()
+        WHERE user_id = $1 AND revoked_at IS NULL
+        "#,
+        user_id,
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to revoke the sessions")?;
+    Ok(())
+}
@@ -1,5 +1,7 @@
-use std::time::Duration;
+use std::{net::IpAddr, time::Duration};
 
+use actix_session::SessionLength;
+use actix_web::cookie::{self, SameSite};
 use secrecy::{ExposeSecret, Secret};
 use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::{
@@ -7,7 +9,13 @@ use sqlx::{
     ConnectOptions,
 };
 
-use crate::domain::SubscriberEmail;
+use anyhow::Context;
+
+use crate::{
+    domain::{EmailPolicy, SubscriberEmail, SubscriberEmailError},
+    email_client::EmailClient,
+    i18n::Locale,
+};
 
 #[derive(serde::Deserialize, Clone, Debug)]
 pub struct Settings {
@@ -15,6 +23,232 @@ pub struct Settings {
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
     pub redis_uri: Secret<String>,
+    pub subscriptions: SubscriptionsSettings,
+    pub rate_limit: RateLimitSettings,
+    pub signup_challenge: SignupChallengeSettings,
+    pub email_policy: EmailPolicySettings,
+    pub two_factor: TwoFactorSettings,
+    pub password_reset: PasswordResetSettings,
+    pub invitations: InvitationSettings,
+    pub password_hashing: PasswordHashingSettings,
+    pub security_headers: SecurityHeadersSettings,
+    pub session_cookie: SessionCookieSettings,
+    pub templates: TemplateSettings,
+    pub i18n: I18nSettings,
+}
+
+impl Settings {
+    /// Reject the combinations of settings that cannot work together.
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if matches!(
+            self.signup_challenge,
+            SignupChallengeSettings::Remote { .. }
+        ) && !self.subscriptions.allowed_origins.is_empty()
+        {
+            anyhow::bail!(
+                "The signup widget cannot get a token from a remote signup challenge: \
+                use the proof of work, or leave `subscriptions.allowed_origins` empty"
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct TwoFactorSettings {
+    /// Force every admin to enroll a TOTP authenticator before using the admin area.
+    pub required: bool,
+    /// Shown as the account issuer in authenticator apps.
+    pub issuer: String,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct PasswordResetSettings {
+    /// How long a password reset link stays valid.
+    pub token_validity_minutes: i64,
+}
+
+impl PasswordResetSettings {
+    pub fn token_validity(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.token_validity_minutes)
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct InvitationSettings {
+    /// How long an invitation link stays valid.
+    pub token_validity_hours: i64,
+}
+
+impl InvitationSettings {
+    pub fn token_validity(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.token_validity_hours)
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct SecurityHeadersSettings {
+    pub content_security_policy: String,
+    pub referrer_policy: String,
+    /// Only set where the application is served over HTTPS exclusively.
+    pub hsts_max_age_secs: Option<u64>,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct TemplateSettings {
+    /// Read the templates from the source tree before every rendering, so that
+    /// they can be edited without restarting. Meant for local development.
+    pub hot_reload: bool,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct I18nSettings {
+    /// Used when neither the subscriber nor their browser asks for a supported locale.
+    pub default_locale: Locale,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct SessionCookieSettings {
+    pub secure: bool,
+    pub same_site: SameSitePolicy,
+    /// The cookie outlives the browser session if set, otherwise the session
+    /// ends with the browser. Either way, it expires after `max_age_hours`.
+    pub persistent: bool,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_age_hours: i64,
+}
+
+impl SessionCookieSettings {
+    pub fn max_age(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.max_age_hours)
+    }
+
+    pub fn session_length(&self) -> SessionLength {
+        let max_age = Some(cookie::time::Duration::hours(self.max_age_hours));
+        if self.persistent {
+            SessionLength::Predetermined {
+                max_session_length: max_age,
+            }
+        } else {
+            SessionLength::BrowserSession { state_ttl: max_age }
+        }
+    }
+}
+
+/// `none` requires `secure`, browsers drop the cookie otherwise.
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SameSitePolicy {
+    Strict,
+    Lax,
+    None,
+}
+
+impl From<SameSitePolicy> for SameSite {
+    fn from(policy: SameSitePolicy) -> Self {
+        match policy {
+            SameSitePolicy::Strict => SameSite::Strict,
+            SameSitePolicy::Lax => SameSite::Lax,
+            SameSitePolicy::None => SameSite::None,
+        }
+    }
+}
+
+/// Argon2id costs for new password hashes. Hashes made with other costs are
+/// upgraded on the next successful login.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct PasswordHashingSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub memory_kib: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub iterations: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub parallelism: u32,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct EmailPolicySettings {
+    pub strip_plus_tags: bool,
+    /// One domain per line, relative to the working directory.
+    pub disposable_domains_file: Option<String>,
+}
+
+impl EmailPolicySettings {
+    pub fn load(&self) -> Result<EmailPolicy, anyhow::Error> {
+        let policy = EmailPolicy {
+            strip_plus_tags: self.strip_plus_tags,
+            ..EmailPolicy::default()
+        };
+        match &self.disposable_domains_file {
+            Some(path) => {
+                let content = std::fs::read_to_string(path).with_context(|| {
+                    format!("Failed to read the disposable domains file `{}`", path)
+                })?;
+                Ok(policy.with_denied_domains(&content))
+            }
+            None => Ok(policy),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SignupChallengeSettings {
+    Disabled,
+    ProofOfWork {
+        difficulty_bits: u32,
+        validity_secs: i64,
+    },
+    Remote {
+        verification_url: String,
+        secret: Secret<String>,
+        timeout_ms: u64,
+    },
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct RateLimitSettings {
+    /// Namespace of the counters, for when several applications share a Redis instance.
+    pub key_prefix: String,
+    pub subscriptions_per_ip_per_hour: u64,
+    pub subscriptions_per_email_per_hour: u64,
+    /// Global ceiling on the confirmation emails sent in an hour.
+    pub confirmation_emails_per_hour: u64,
+    pub password_resets_per_ip_per_hour: u64,
+    /// Reset links sent to an account's email in an hour. Requests beyond are
+    /// dropped silently, so that they don't tell whether the account exists.
+    pub password_resets_per_email_per_hour: u64,
+    /// Failed logins after which a username is locked out.
+    pub login_failures_per_username: u64,
+    /// Failed logins after which an IP is locked out, whatever the usernames tried.
+    pub login_failures_per_ip: u64,
+    /// Invalid second factor codes after which a user is locked out, even
+    /// after entering their password again.
+    pub two_factor_failures_per_user: u64,
+    /// How long failures are remembered, and so how long a lockout lasts.
+    pub login_lockout_secs: usize,
+    /// Wait imposed after the second failure, doubled with each further failure.
+    pub login_delay_base_ms: u64,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct SubscriptionsSettings {
+    /// Send a welcome email once a subscriber has confirmed its subscription.
+    pub send_welcome_email: bool,
+    /// Where the confirmation landing page redirects the new subscriber.
+    pub confirmation_redirect_url: Option<String>,
+    /// How long a confirmation link stays valid.
+    pub token_validity_hours: i64,
+    /// Origins allowed to call the subscription endpoints from a browser,
+    /// e.g. the sites embedding the signup widget. Our own origin always is.
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+}
+
+impl SubscriptionsSettings {
+    pub fn token_validity(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.token_validity_hours)
+    }
 }
 
 #[derive(serde::Deserialize, Clone, Debug)]
@@ -24,6 +258,10 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub base_url: String,
     pub hmac_secret: Secret<String>,
+    /// Addresses of the reverse proxies in front of the application: only
+    /// their `X-Forwarded-For` header is trusted to tell the client's address.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
 }
 
 impl ApplicationSettings {
@@ -74,7 +312,17 @@ pub struct EmailClientSettings {
 }
 
 impl EmailClientSettings {
-    pub fn sender(&self) -> Result<SubscriberEmail, String> {
+    pub fn client(self) -> EmailClient {
+        let sender_email = self.sender().expect("Invalid sender email address.");
+        let timeout = self.timeout();
+        EmailClient::new(
+            self.base_url,
+            sender_email,
+            self.authorization_token,
+            timeout,
+        )
+    }
+    pub fn sender(&self) -> Result<SubscriberEmail, SubscriberEmailError> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
     pub fn timeout(&self) -> Duration {
@@ -83,7 +331,7 @@ impl EmailClientSettings {
 }
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
     let builder = config::Config::builder();
-    let base_path = std::env::current_dir().expect("Curdrent Directory non found");
+    let base_path = std::env::current_dir().expect("Failed to determine the current directory");
     let config_dir = base_path.join("configuration");
     let environment: Environment = std::env::var("APP_ENVIRONMENT")
         .unwrap_or_else(|_| "local".to_string())
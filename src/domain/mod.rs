@@ -1,7 +1,9 @@
 pub mod new_subscriber;
 pub mod subscriber_email;
 pub mod subscriber_name;
+pub mod subscription_source;
 
-pub use new_subscriber::NewSubscriber;
-pub use subscriber_email::SubscriberEmail;
-pub use subscriber_name::SubscriberName;
+pub use new_subscriber::{NewSubscriber, NewSubscriberError};
+pub use subscriber_email::{EmailPolicy, SubscriberEmail, SubscriberEmailError};
+pub use subscriber_name::{SubscriberName, SubscriberNameError};
+pub use subscription_source::{SubscriptionSource, SubscriptionSourceError};
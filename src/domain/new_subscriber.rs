@@ -1,19 +1,66 @@
 use crate::routes::FormData;
 
-use super::{SubscriberEmail, SubscriberName};
+use super::{
+    EmailPolicy, SubscriberEmail, SubscriberEmailError, SubscriberName, SubscriberNameError,
+    SubscriptionSource, SubscriptionSourceError,
+};
 
 #[derive(Debug)]
 pub struct NewSubscriber {
     pub email: SubscriberEmail,
     pub name: SubscriberName,
+    pub source: Option<SubscriptionSource>,
+}
+
+/// Every invalid field of a subscription, so that they can all be reported at once.
+#[derive(Debug, Default)]
+pub struct NewSubscriberError {
+    pub name: Option<SubscriberNameError>,
+    pub email: Option<SubscriberEmailError>,
+    pub source: Option<SubscriptionSourceError>,
+}
+
+impl std::fmt::Display for NewSubscriberError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let errors: Vec<String> = [
+            self.name.as_ref().map(|e| format!("name: {}", e)),
+            self.email.as_ref().map(|e| format!("email: {}", e)),
+            self.source.as_ref().map(|e| format!("source: {}", e)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        write!(f, "Invalid subscriber ({})", errors.join(", "))
+    }
+}
+
+impl std::error::Error for NewSubscriberError {}
+
+impl NewSubscriber {
+    pub fn parse(form: FormData, policy: &EmailPolicy) -> Result<Self, NewSubscriberError> {
+        match (
+            SubscriberName::parse(&form.name),
+            SubscriberEmail::parse_with_policy(form.email, policy),
+            SubscriptionSource::parse(form.source.as_deref().unwrap_or_default()),
+        ) {
+            (Ok(name), Ok(email), Ok(source)) => Ok(NewSubscriber {
+                name,
+                email,
+                source,
+            }),
+            (name, email, source) => Err(NewSubscriberError {
+                name: name.err(),
+                email: email.err(),
+                source: source.err(),
+            }),
+        }
+    }
 }
 
 impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+    type Error = NewSubscriberError;
 
     fn try_from(form: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(&form.name)?;
-        let email = SubscriberEmail::parse(form.email)?;
-        Ok(NewSubscriber { name, email })
+        Self::parse(form, &EmailPolicy::default())
     }
 }
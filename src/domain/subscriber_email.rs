@@ -1,27 +1,124 @@
+use std::collections::HashSet;
+
 use validator::validate_email;
 
-#[derive(Debug)]
-pub struct SubscriberEmail(String);
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum SubscriberEmailError {
+    #[error("The email address is empty.")]
+    Empty,
+    #[error("The email address is not valid.")]
+    InvalidSyntax,
+    #[error("The domain of the email address is not valid.")]
+    InvalidDomain,
+    #[error("Email addresses from `{0}` are not accepted.")]
+    DisposableDomain(String),
+}
+
+impl SubscriberEmailError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            SubscriberEmailError::Empty => "empty",
+            SubscriberEmailError::InvalidSyntax => "invalid_syntax",
+            SubscriberEmailError::InvalidDomain => "invalid_domain",
+            SubscriberEmailError::DisposableDomain(_) => "disposable_domain",
+        }
+    }
+}
+
+/// Rules applied on top of the syntax check when parsing an address.
+#[derive(Debug, Default, Clone)]
+pub struct EmailPolicy {
+    /// Ignore `+tag` suffixes of the local part when checking for duplicates.
+    pub strip_plus_tags: bool,
+    pub denied_domains: HashSet<String>,
+}
+
+impl EmailPolicy {
+    /// Build a deny-list from a file holding one domain per line.
+    /// Blank lines and lines starting with `#` are ignored.
+    pub fn with_denied_domains(mut self, content: &str) -> Self {
+        self.denied_domains = content
+            .lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(|l| l.to_lowercase())
+            .collect();
+        self
+    }
+
+    /// A domain is denied when it, or any of its parent domains, is listed.
+    fn is_denied(&self, domain: &str) -> bool {
+        let mut domain = domain;
+        loop {
+            if self.denied_domains.contains(domain) {
+                return true;
+            }
+            match domain.split_once('.') {
+                Some((_, parent)) => domain = parent,
+                None => return false,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SubscriberEmail {
+    /// The address we send emails to: the local part is kept as submitted,
+    /// the domain is lowercased and converted to its ASCII (punycode) form.
+    address: String,
+    /// Used to detect duplicates: lowercased, optionally without `+tag`.
+    canonical: String,
+}
 
 impl SubscriberEmail {
-    pub fn parse(s: String) -> Result<SubscriberEmail, String> {
-        if validate_email(&s) {
-            Ok(Self(s))
-        } else {
-            Err(format!("{} is not a valid email", s))
+    pub fn parse(s: String) -> Result<SubscriberEmail, SubscriberEmailError> {
+        Self::parse_with_policy(s, &EmailPolicy::default())
+    }
+
+    pub fn parse_with_policy(
+        s: String,
+        policy: &EmailPolicy,
+    ) -> Result<SubscriberEmail, SubscriberEmailError> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(SubscriberEmailError::Empty);
+        }
+        if !validate_email(s) {
+            return Err(SubscriberEmailError::InvalidSyntax);
         }
+        let (local, domain) = s
+            .rsplit_once('@')
+            .ok_or(SubscriberEmailError::InvalidSyntax)?;
+        let domain =
+            idna::domain_to_ascii(domain).map_err(|_| SubscriberEmailError::InvalidDomain)?;
+        if policy.is_denied(&domain) {
+            return Err(SubscriberEmailError::DisposableDomain(domain));
+        }
+        let canonical_local = local.to_lowercase();
+        let canonical_local = match canonical_local.split_once('+') {
+            Some((base, _)) if policy.strip_plus_tags && !base.is_empty() => base.to_string(),
+            _ => canonical_local,
+        };
+        Ok(Self {
+            address: format!("{}@{}", local, domain),
+            canonical: format!("{}@{}", canonical_local, domain),
+        })
+    }
+
+    pub fn canonical(&self) -> &str {
+        &self.canonical
     }
 }
 
 impl std::fmt::Display for SubscriberEmail {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0.fmt(f)
+        self.address.fmt(f)
     }
 }
 
 impl AsRef<str> for SubscriberEmail {
     fn as_ref(&self) -> &str {
-        &self.0
+        &self.address
     }
 }
 
@@ -29,7 +126,7 @@ impl AsRef<str> for SubscriberEmail {
 mod tests_email {
 
     use super::*;
-    use claim::assert_err;
+    use claim::{assert_err, assert_ok};
     use fake::faker::internet::fr_fr::SafeEmail;
     use fake::Fake;
     #[derive(Debug, Clone)]
@@ -52,13 +149,19 @@ mod tests_email {
     #[test]
     fn empty_string_is_rejected() {
         let name = "".to_string();
-        assert_err!(SubscriberEmail::parse(name));
+        assert_eq!(
+            SubscriberEmail::parse(name).unwrap_err(),
+            SubscriberEmailError::Empty
+        );
     }
 
     #[test]
     fn email_missing_symbol_is_rejected() {
         let name = "bla.com".to_string();
-        assert_err!(SubscriberEmail::parse(name));
+        assert_eq!(
+            SubscriberEmail::parse(name).unwrap_err(),
+            SubscriberEmailError::InvalidSyntax
+        );
     }
 
     #[test]
@@ -66,4 +169,49 @@ mod tests_email {
         let name = "@bla.com".to_string();
         assert_err!(SubscriberEmail::parse(name));
     }
+
+    #[test]
+    fn the_domain_is_lowercased_but_not_the_local_part() {
+        let email = SubscriberEmail::parse("Foo@Example.COM".into()).unwrap();
+        assert_eq!(email.as_ref(), "Foo@example.com");
+        assert_eq!(email.canonical(), "foo@example.com");
+    }
+
+    #[test]
+    fn internationalized_domains_are_converted_to_punycode() {
+        let email = SubscriberEmail::parse("ursula@bücher.example".into()).unwrap();
+        assert_eq!(email.as_ref(), "ursula@xn--bcher-kva.example");
+    }
+
+    #[test]
+    fn plus_tags_are_only_stripped_from_the_canonical_form_when_asked() {
+        let policy = EmailPolicy {
+            strip_plus_tags: true,
+            ..EmailPolicy::default()
+        };
+        let email =
+            SubscriberEmail::parse_with_policy("ursula+news@example.com".into(), &policy).unwrap();
+        assert_eq!(email.as_ref(), "ursula+news@example.com");
+        assert_eq!(email.canonical(), "ursula@example.com");
+
+        let email = SubscriberEmail::parse("ursula+news@example.com".into()).unwrap();
+        assert_eq!(email.canonical(), "ursula+news@example.com");
+    }
+
+    #[test]
+    fn disposable_domains_and_their_subdomains_are_rejected() {
+        let policy = EmailPolicy::default().with_denied_domains("# comment\n\nMailinator.com\n");
+        assert_eq!(
+            SubscriberEmail::parse_with_policy("a@mailinator.com".into(), &policy).unwrap_err(),
+            SubscriberEmailError::DisposableDomain("mailinator.com".into())
+        );
+        assert_err!(SubscriberEmail::parse_with_policy(
+            "a@eu.MAILINATOR.com".into(),
+            &policy
+        ));
+        assert_ok!(SubscriberEmail::parse_with_policy(
+            "a@notmailinator.com".into(),
+            &policy
+        ));
+    }
 }
@@ -1,22 +1,46 @@
 use unicode_segmentation::UnicodeSegmentation;
 
+const MAX_LENGTH: usize = 256;
+const FORBIDDEN_CHARACTERS: [char; 10] = ['<', '>', '[', ']', '(', ')', '"', '\'', '/', '\\'];
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum SubscriberNameError {
+    #[error("The name is empty.")]
+    Empty,
+    #[error("The name must be at most {max} characters long, got {actual}.")]
+    TooLong { max: usize, actual: usize },
+    #[error("The name contains the forbidden character `{0}`.")]
+    ForbiddenCharacter(char),
+}
+
+impl SubscriberNameError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            SubscriberNameError::Empty => "empty",
+            SubscriberNameError::TooLong { .. } => "too_long",
+            SubscriberNameError::ForbiddenCharacter(_) => "forbidden_character",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct SubscriberName(String);
 impl SubscriberName {
-    pub fn parse(s: &str) -> Result<SubscriberName, String> {
-        let is_empty = s.trim().is_empty();
-        let is_too_long = s.graphemes(true).count() > 256;
-        let forbidden_characters = ['<', '>', '[', ']', '(', ')', '"', '\'', '/', '\\'];
-        let has_invalid_chars = forbidden_characters.iter().any(|x| s.contains(*x));
-
-        if is_empty || is_too_long || has_invalid_chars {
-            Err(format!(
-                "`{}` est une valeure d'entrée inccorect pour le nom",
-                s
-            ))
-        } else {
-            Ok(Self(s.to_string()))
+    pub fn parse(s: &str) -> Result<SubscriberName, SubscriberNameError> {
+        if s.trim().is_empty() {
+            return Err(SubscriberNameError::Empty);
+        }
+        let length = s.graphemes(true).count();
+        if length > MAX_LENGTH {
+            return Err(SubscriberNameError::TooLong {
+                max: MAX_LENGTH,
+                actual: length,
+            });
+        }
+        if let Some(c) = s.chars().find(|c| FORBIDDEN_CHARACTERS.contains(c)) {
+            return Err(SubscriberNameError::ForbiddenCharacter(c));
         }
+        Ok(Self(s.to_string()))
     }
 }
 
@@ -63,6 +87,25 @@ mod tests {
         }
     }
 
+    #[test]
+    fn errors_tell_what_is_wrong() {
+        assert_eq!(
+            SubscriberName::parse("  ").unwrap_err(),
+            SubscriberNameError::Empty
+        );
+        assert_eq!(
+            SubscriberName::parse(&"a".repeat(300)).unwrap_err(),
+            SubscriberNameError::TooLong {
+                max: 256,
+                actual: 300
+            }
+        );
+        assert_eq!(
+            SubscriberName::parse("Ursula (le Guin)").unwrap_err(),
+            SubscriberNameError::ForbiddenCharacter('(')
+        );
+    }
+
     #[test]
     fn un_nom_valide_est_parse_crrectement() {
         let name = "Ursula le Guin".to_string();
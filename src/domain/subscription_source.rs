@@ -0,0 +1,88 @@
+const MAX_LENGTH: usize = 64;
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum SubscriptionSourceError {
+    #[error("The source tag must be at most {max} characters long, got {actual}.")]
+    TooLong { max: usize, actual: usize },
+    #[error("The source tag may only contain ASCII letters, digits, `-` and `_`.")]
+    InvalidCharacter,
+}
+
+impl SubscriptionSourceError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            SubscriptionSourceError::TooLong { .. } => "too_long",
+            SubscriptionSourceError::InvalidCharacter => "invalid_character",
+        }
+    }
+}
+
+/// Tag identifying the site a subscription form was embedded on.
+#[derive(Debug)]
+pub struct SubscriptionSource(String);
+
+impl SubscriptionSource {
+    /// An empty tag means the subscription has no source.
+    pub fn parse(s: &str) -> Result<Option<SubscriptionSource>, SubscriptionSourceError> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Ok(None);
+        }
+        if s.len() > MAX_LENGTH {
+            return Err(SubscriptionSourceError::TooLong {
+                max: MAX_LENGTH,
+                actual: s.len(),
+            });
+        }
+        if !s
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(SubscriptionSourceError::InvalidCharacter);
+        }
+        Ok(Some(Self(s.to_lowercase())))
+    }
+}
+
+impl AsRef<str> for SubscriptionSource {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_empty_tag_means_no_source() {
+        assert!(SubscriptionSource::parse("  ").unwrap().is_none());
+    }
+
+    #[test]
+    fn tags_are_lowercased() {
+        let source = SubscriptionSource::parse("Partner-Blog_2")
+            .unwrap()
+            .unwrap();
+        assert_eq!(source.as_ref(), "partner-blog_2");
+    }
+
+    #[test]
+    fn tags_with_other_characters_are_rejected() {
+        assert_eq!(
+            SubscriptionSource::parse("<script>").unwrap_err(),
+            SubscriptionSourceError::InvalidCharacter
+        );
+    }
+
+    #[test]
+    fn overly_long_tags_are_rejected() {
+        assert_eq!(
+            SubscriptionSource::parse(&"a".repeat(65)).unwrap_err(),
+            SubscriptionSourceError::TooLong {
+                max: 64,
+                actual: 65
+            }
+        );
+    }
+}
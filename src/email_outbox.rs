@@ -0,0 +1,196 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use sqlx::{postgres::PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::{
+    configuration::Settings, domain::SubscriberEmail, email_client::EmailClient,
+    startup::get_connection_pool,
+};
+
+/// Number of failed attempts after which an email is dropped from the outbox.
+const MAX_RETRIES: i16 = 10;
+
+pub struct OutgoingEmail<'a> {
+    pub recipient: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub html_body: &'a str,
+    pub text_body: &'a str,
+}
+
+/// Store an email in the outbox, as part of the caller's transaction.
+/// It will be delivered later on by the outbox worker.
+#[tracing::instrument(skip(transaction, email), fields(recipient = %email.recipient))]
+pub async fn enqueue_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: OutgoingEmail<'_>,
+) -> Result<Uuid, sqlx::Error> {
+    let email_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO email_outbox (id, recipient_email, subject, html_body, text_body, created_at)
+        VALUES ($1, $2, $3, $4, $5, now())
+        "#,
+        email_id,
+        email.recipient.as_ref(),
+        email.subject,
+        email.html_body,
+        email.text_body,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(email_id)
+}
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(email_id=tracing::field::Empty, recipient_email=tracing::field::Empty),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let task = dequeue_task(pool).await?;
+    if task.is_none() {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+    let (mut transaction, email) = task.unwrap();
+    Span::current()
+        .record("email_id", &display(email.id))
+        .record("recipient_email", &display(&email.recipient_email));
+    match SubscriberEmail::parse(email.recipient_email.clone()) {
+        Ok(recipient) => {
+            match email_client
+                .send_mail(
+                    &recipient,
+                    &email.subject,
+                    &email.html_body,
+                    &email.text_body,
+                )
+                .await
+            {
+                Ok(()) => delete_task(&mut transaction, email.id).await?,
+                Err(e) if email.n_retries + 1 >= MAX_RETRIES => {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to deliver an outbox email, giving up."
+                    );
+                    delete_task(&mut transaction, email.id).await?;
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to deliver an outbox email, it will be retried."
+                    );
+                    postpone_task(&mut transaction, email.id, email.n_retries + 1).await?;
+                }
+            }
+        }
+        Err(e) => {
+            tracing::error!(
+                error.message = %e,
+                "Skipping an outbox email with an invalid recipient."
+            );
+            delete_task(&mut transaction, email.id).await?;
+        }
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the outbox transaction")?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+struct QueuedEmail {
+    id: Uuid,
+    recipient_email: String,
+    subject: String,
+    html_body: String,
+    text_body: String,
+    n_retries: i16,
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(
+    pool: &PgPool,
+) -> Result<Option<(PgTransaction, QueuedEmail)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let r = sqlx::query_as!(
+        QueuedEmail,
+        r#"
+        SELECT id, recipient_email, subject, html_body, text_body, n_retries
+        FROM email_outbox
+        WHERE execute_after <= now()
+        ORDER BY created_at
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    Ok(r.map(|email| (transaction, email)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(transaction: &mut PgTransaction, email_id: Uuid) -> Result<(), anyhow::Error> {
+    sqlx::query!(r#"DELETE FROM email_outbox WHERE id = $1"#, email_id)
+        .execute(transaction)
+        .await?;
+    Ok(())
+}
+
+/// Schedule the next attempt with an exponential backoff, capped to one hour.
+#[tracing::instrument(skip_all)]
+async fn postpone_task(
+    transaction: &mut PgTransaction,
+    email_id: Uuid,
+    n_retries: i16,
+) -> Result<(), anyhow::Error> {
+    let delay_secs = 2f64.powi(n_retries.into()).min(3600.);
+    sqlx::query!(
+        r#"
+        UPDATE email_outbox
+        SET n_retries = $2, execute_after = now() + make_interval(secs => $3)
+        WHERE id = $1
+        "#,
+        email_id,
+        n_retries,
+        delay_secs,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+async fn worker_loop(pool: PgPool, email_client: EmailClient) -> Result<(), anyhow::Error> {
+    loop {
+        match try_execute_task(&pool, &email_client).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+pub async fn run_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let email_client = configuration.email_client.client();
+    worker_loop(connection_pool, email_client).await
+}
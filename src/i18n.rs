@@ -0,0 +1,260 @@
+//! Translations of the pages and emails meant for subscribers, from the
+//! catalogs in the `locales` directory. `en` is the reference catalog: the
+//! keys missing from another one fall back to their English text.
+use std::{collections::HashMap, str::FromStr};
+
+use actix_web::{http::header, HttpRequest};
+use anyhow::Context;
+use serde_json::Value;
+
+use crate::configuration::I18nSettings;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    En,
+    Fr,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 2] = [Locale::En, Locale::Fr];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::Fr => "fr",
+        }
+    }
+
+    /// The preferred supported locale of an `Accept-Language` header, such as
+    /// `fr-CH, fr;q=0.9, en;q=0.8`. Regional variants match their language.
+    pub fn negotiate(accept_language: &str) -> Option<Locale> {
+        let mut ranges: Vec<(&str, f32)> = accept_language
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';').map(str::trim);
+                let tag = parts.next().filter(|tag| !tag.is_empty())?;
+                let quality = parts
+                    .find_map(|param| param.strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+                Some((tag, quality))
+            })
+            .filter(|(_, quality)| *quality > 0.0)
+            .collect();
+        // Stable, so that equally weighted ranges keep their order.
+        ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        ranges.into_iter().find_map(|(tag, _)| {
+            let language = tag.split('-').next().unwrap_or_default();
+            Self::ALL
+                .into_iter()
+                .find(|locale| locale.as_str().eq_ignore_ascii_case(language))
+        })
+    }
+}
+
+impl FromStr for Locale {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|locale| locale.as_str() == s)
+            .ok_or_else(|| anyhow::anyhow!("`{}` is not a supported locale", s))
+    }
+}
+
+impl std::fmt::Display for Locale {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+const CATALOGS: [(Locale, &str); 2] = [
+    (Locale::En, include_str!("../locales/en.json")),
+    (Locale::Fr, include_str!("../locales/fr.json")),
+];
+
+pub struct I18n {
+    catalogs: HashMap<Locale, Value>,
+    default_locale: Locale,
+}
+
+impl I18n {
+    pub fn new(settings: &I18nSettings) -> Result<Self, anyhow::Error> {
+        let mut catalogs = HashMap::new();
+        for (locale, source) in CATALOGS {
+            let catalog: Value = serde_json::from_str(source)
+                .with_context(|| format!("Failed to parse the `{}` catalog", locale))?;
+            catalogs.insert(locale, catalog);
+        }
+        let reference = catalogs[&Locale::En].clone();
+        for catalog in catalogs.values_mut() {
+            fill_missing(catalog, &reference);
+        }
+        Ok(Self {
+            catalogs,
+            default_locale: settings.default_locale,
+        })
+    }
+
+    pub fn default_locale(&self) -> Locale {
+        self.default_locale
+    }
+
+    /// The locale asked for by the `Accept-Language` header of the request.
+    pub fn request_locale(&self, request: &HttpRequest) -> Locale {
+        request
+            .headers()
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|h| h.to_str().ok())
+            .and_then(Locale::negotiate)
+            .unwrap_or(self.default_locale)
+    }
+
+    /// The locale stored for a subscriber, which may have stopped being supported.
+    pub fn stored_locale(&self, locale: &str) -> Locale {
+        locale.parse().unwrap_or(self.default_locale)
+    }
+
+    pub fn translator(&self, locale: Locale) -> Translator<'_> {
+        Translator {
+            locale,
+            catalog: &self.catalogs[&locale],
+        }
+    }
+}
+
+fn fill_missing(catalog: &mut Value, reference: &Value) {
+    if let (Value::Object(catalog), Value::Object(reference)) = (catalog, reference) {
+        for (key, value) in reference {
+            match catalog.get_mut(key) {
+                Some(translated) => fill_missing(translated, value),
+                None => {
+                    catalog.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+}
+
+/// The messages of one locale. Keys are dotted paths in the catalog, such as
+/// `confirm.title`, and `{name}` placeholders are replaced by their argument.
+#[derive(Clone, Copy)]
+pub struct Translator<'a> {
+    locale: Locale,
+    catalog: &'a Value,
+}
+
+impl<'a> Translator<'a> {
+    pub fn locale(&self) -> Locale {
+        self.locale
+    }
+
+    pub fn t(&self, key: &str) -> String {
+        self.t_with(key, &[])
+    }
+
+    /// Unknown keys are rendered as themselves, so that they stand out.
+    pub fn t_with(&self, key: &str, arguments: &[(&str, String)]) -> String {
+        let pointer = format!("/{}", key.replace('.', "/"));
+        let message = match self.catalog.pointer(&pointer).and_then(Value::as_str) {
+            Some(message) => message,
+            None => {
+                tracing::warn!(key, locale = %self.locale, "Missing translation");
+                return key.to_string();
+            }
+        };
+        arguments
+            .iter()
+            .fold(message.to_string(), |message, (name, value)| {
+                message.replace(&format!("{{{}}}", name), value)
+            })
+    }
+
+    /// A template context holding the catalog as `t` and the `locale`.
+    /// Templates interpolate with `replace`, as in
+    /// `{{ t.confirm.thanks | replace(from="{name}", to=name) }}`.
+    pub fn context(&self) -> tera::Context {
+        let mut context = tera::Context::new();
+        context.insert("locale", self.locale.as_str());
+        context.insert("t", self.catalog);
+        context
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn i18n() -> I18n {
+        I18n::new(&I18nSettings {
+            default_locale: Locale::En,
+        })
+        .unwrap()
+    }
+
+    fn keys(value: &Value, prefix: &str, keys: &mut Vec<String>) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map {
+                    self::keys(value, &format!("{}{}.", prefix, key), keys);
+                }
+            }
+            _ => keys.push(prefix.trim_end_matches('.').to_string()),
+        }
+    }
+
+    #[test]
+    fn every_catalog_translates_every_message() {
+        let reference: Value = serde_json::from_str(CATALOGS[0].1).unwrap();
+        let mut expected = vec![];
+        keys(&reference, "", &mut expected);
+        for (locale, source) in CATALOGS {
+            let catalog: Value = serde_json::from_str(source).unwrap();
+            let mut actual = vec![];
+            keys(&catalog, "", &mut actual);
+            assert_eq!(actual, expected, "The `{}` catalog is not complete", locale);
+        }
+    }
+
+    #[test]
+    fn accept_language_is_negotiated_by_quality() {
+        assert_eq!(
+            Locale::negotiate("fr-CH, fr;q=0.9, en;q=0.8"),
+            Some(Locale::Fr)
+        );
+        assert_eq!(
+            Locale::negotiate("de, en;q=0.5, fr;q=0.7"),
+            Some(Locale::Fr)
+        );
+        assert_eq!(Locale::negotiate("FR"), Some(Locale::Fr));
+        assert_eq!(Locale::negotiate("fr;q=0, en"), Some(Locale::En));
+        assert_eq!(Locale::negotiate("de, *;q=0.1"), None);
+        assert_eq!(Locale::negotiate(""), None);
+    }
+
+    #[test]
+    fn messages_are_interpolated() {
+        let i18n = i18n();
+        assert_eq!(
+            i18n.translator(Locale::Fr)
+                .t_with("confirm.thanks", &[("name", "Ursula".into())]),
+            "Merci Ursula, votre abonnement est confirmé !"
+        );
+    }
+
+    #[test]
+    fn missing_messages_fall_back_to_english_then_to_their_key() {
+        let mut catalog = serde_json::json!({"confirm": {"title": "Abonnement confirmé"}});
+        let reference: Value = serde_json::from_str(CATALOGS[0].1).unwrap();
+        fill_missing(&mut catalog, &reference);
+        assert_eq!(catalog["confirm"]["title"], "Abonnement confirmé");
+        assert_eq!(catalog["confirm"]["continue"], "Continue to our website");
+
+        let i18n = i18n();
+        assert_eq!(
+            i18n.translator(Locale::En).t("confirm.nonexistent"),
+            "confirm.nonexistent"
+        );
+    }
+}
@@ -1,7 +1,16 @@
+pub mod audit;
 pub mod authentication;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
+pub mod email_outbox;
+pub mod i18n;
+pub mod rate_limit;
 pub mod routes;
+pub mod security_headers;
+pub mod session_state;
+pub mod signup_challenge;
 pub mod startup;
 pub mod telemetry;
+pub mod templates;
+pub mod utils;
@@ -1,5 +1,9 @@
+use std::fmt::{Debug, Display};
+
+use tokio::task::JoinError;
 use zero2prod::{
     configuration::get_configuration,
+    email_outbox::run_worker_until_stopped,
     startup::Application,
     telemetry::{get_subscriber, init_subscriber},
 };
@@ -13,11 +17,39 @@ async fn main() -> anyhow::Result<()> {
     ));
 
     //configuration + database
-    let configuration = get_configuration().expect("Failed to read configuration, désolé");
+    let configuration = get_configuration().expect("Failed to read configuration");
+
+    let application = Application::build(configuration.clone()).await?;
+    let application_task = tokio::spawn(application.run_until_stopped());
+    let worker_task = tokio::spawn(run_worker_until_stopped(configuration));
 
-    Application::build(configuration)
-        .await?
-        .run_until_stopped()
-        .await?;
+    tokio::select! {
+        o = application_task => report_exit("API", o),
+        o = worker_task => report_exit("Email outbox worker", o),
+    };
     Ok(())
 }
+
+fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name)
+        }
+        Ok(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            )
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "'{}' task failed to complete",
+                task_name
+            )
+        }
+    }
+}
@@ -0,0 +1,314 @@
+use redis::{aio::ConnectionManager, AsyncCommands};
+use secrecy::{ExposeSecret, Secret};
+use uuid::Uuid;
+
+use crate::configuration::RateLimitSettings;
+
+/// Length of the fixed windows the counters are kept for.
+const WINDOW_SECS: usize = 3600;
+
+#[derive(Debug, Clone, Copy)]
+pub enum RateLimitReason {
+    TooManySubscriptionsFromIp,
+    TooManySubscriptionsForEmail,
+    ConfirmationEmailCeilingReached,
+    TooManyPasswordResetsFromIp,
+    TooManyPasswordResetsForEmail,
+}
+
+impl std::fmt::Display for RateLimitReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            RateLimitReason::TooManySubscriptionsFromIp => "too many subscriptions from this IP",
+            RateLimitReason::TooManySubscriptionsForEmail => {
+                "too many subscriptions for this email"
+            }
+            RateLimitReason::ConfirmationEmailCeilingReached => {
+                "hourly ceiling of confirmation emails reached"
+            }
+            RateLimitReason::TooManyPasswordResetsFromIp => "too many password resets from this IP",
+            RateLimitReason::TooManyPasswordResetsForEmail => {
+                "too many password resets for this email"
+            }
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/// Why a login attempt is refused before the password is even checked.
+#[derive(Debug, Clone, Copy)]
+pub enum LoginBlock {
+    /// A previous failure was too recent.
+    Throttled,
+    /// Too many failures for this username or from this IP.
+    LockedOut,
+}
+
+impl std::fmt::Display for LoginBlock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoginBlock::Throttled => write!(f, "login attempted too soon after a failure"),
+            LoginBlock::LockedOut => write!(f, "too many failed logins"),
+        }
+    }
+}
+
+/// Fixed-window counters stored in Redis.
+#[derive(Clone)]
+pub struct RateLimiter {
+    connection: ConnectionManager,
+    settings: RateLimitSettings,
+}
+
+impl RateLimiter {
+    pub async fn new(
+        redis_uri: &Secret<String>,
+        settings: RateLimitSettings,
+    ) -> Result<Self, anyhow::Error> {
+        let client = redis::Client::open(redis_uri.expose_secret().as_str())?;
+        let connection = ConnectionManager::new(client).await?;
+        Ok(Self {
+            connection,
+            settings,
+        })
+    }
+
+    /// For other short-lived state kept in Redis.
+    pub fn connection(&self) -> ConnectionManager {
+        self.connection.clone()
+    }
+
+    /// Count a subscription attempt, and check it against the per-IP and
+    /// per-email limits.
+    #[tracing::instrument(name = "Check subscription rate limits", skip(self))]
+    pub async fn check_subscription(
+        &self,
+        ip: &str,
+        email: &str,
+    ) -> Result<Option<RateLimitReason>, redis::RedisError> {
+        let checks = [
+            (
+                format!("subscriptions:ip:{}", ip),
+                self.settings.subscriptions_per_ip_per_hour,
+                RateLimitReason::TooManySubscriptionsFromIp,
+            ),
+            (
+                format!("subscriptions:email:{}", email.to_lowercase()),
+                self.settings.subscriptions_per_email_per_hour,
+                RateLimitReason::TooManySubscriptionsForEmail,
+            ),
+        ];
+        for (key, limit, reason) in checks {
+            if self.hit(&key).await? > limit {
+                return Ok(Some(reason));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Count a confirmation email about to be queued against the global ceiling.
+    /// Called once the email is sure to be sent, so that rejected subscriptions
+    /// don't use up the ceiling.
+    #[tracing::instrument(name = "Check confirmation email ceiling", skip(self))]
+    pub async fn check_confirmation_email(
+        &self,
+    ) -> Result<Option<RateLimitReason>, redis::RedisError> {
+        if self.hit("confirmation_emails").await? > self.settings.confirmation_emails_per_hour {
+            return Ok(Some(RateLimitReason::ConfirmationEmailCeilingReached));
+        }
+        Ok(None)
+    }
+
+    /// Count a password reset request from `ip`, before looking up the account.
+    #[tracing::instrument(name = "Check password reset rate limit per IP", skip(self))]
+    pub async fn check_password_reset_from(
+        &self,
+        ip: &str,
+    ) -> Result<Option<RateLimitReason>, redis::RedisError> {
+        let resets = self.hit(&format!("password_resets:ip:{}", ip)).await?;
+        if resets > self.settings.password_resets_per_ip_per_hour {
+            return Ok(Some(RateLimitReason::TooManyPasswordResetsFromIp));
+        }
+        Ok(None)
+    }
+
+    /// Count a reset link about to be sent to `email`.
+    #[tracing::instrument(name = "Check password reset rate limit per email", skip(self))]
+    pub async fn check_password_reset_for(
+        &self,
+        email: &str,
+    ) -> Result<Option<RateLimitReason>, redis::RedisError> {
+        let resets = self
+            .hit(&format!("password_resets:email:{}", email.to_lowercase()))
+            .await?;
+        if resets > self.settings.password_resets_per_email_per_hour {
+            return Ok(Some(RateLimitReason::TooManyPasswordResetsForEmail));
+        }
+        Ok(None)
+    }
+
+    /// Check whether a login may be attempted for `username` from `ip`.
+    #[tracing::instrument(name = "Check login throttling", skip(self))]
+    pub async fn check_login(
+        &self,
+        username: &str,
+        ip: &str,
+    ) -> Result<Option<LoginBlock>, redis::RedisError> {
+        let mut connection = self.connection.clone();
+        for (subject, max_failures) in self.login_subjects(username, ip) {
+            let failures: Option<u64> = connection
+                .get(self.key(&format!("login_failures:{}", subject)))
+                .await?;
+            if failures.unwrap_or(0) >= max_failures {
+                return Ok(Some(LoginBlock::LockedOut));
+            }
+            let throttled: bool = connection
+                .exists(self.key(&format!("login_retry_after:{}", subject)))
+                .await?;
+            if throttled {
+                return Ok(Some(LoginBlock::Throttled));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Count a failed login, and make the next attempts wait progressively longer.
+    /// Returns `true` if the failure triggered a lockout.
+    #[tracing::instrument(name = "Record login failure", skip(self))]
+    pub async fn record_login_failure(
+        &self,
+        username: &str,
+        ip: &str,
+    ) -> Result<bool, redis::RedisError> {
+        let mut connection = self.connection.clone();
+        let mut locked_out = false;
+        for (subject, max_failures) in self.login_subjects(username, ip) {
+            let failures = self
+                .increment(
+                    &self.key(&format!("login_failures:{}", subject)),
+                    self.settings.login_lockout_secs,
+                )
+                .await?;
+            if failures == max_failures {
+                locked_out = true;
+                tracing::warn!(%subject, failures, "Login locked out");
+            }
+            if let Some(delay_ms) = self.login_delay_ms(failures) {
+                redis::cmd("SET")
+                    .arg(self.key(&format!("login_retry_after:{}", subject)))
+                    .arg(1)
+                    .arg("PX")
+                    .arg(delay_ms)
+                    .query_async::<_, ()>(&mut connection)
+                    .await?;
+            }
+        }
+        Ok(locked_out)
+    }
+
+    /// Forget the failures of `username`. Those of the IP are kept.
+    #[tracing::instrument(name = "Record login success", skip(self))]
+    pub async fn record_login_success(&self, username: &str) -> Result<(), redis::RedisError> {
+        let subject = format!("user:{}", username);
+        let mut connection = self.connection.clone();
+        connection
+            .del::<_, ()>(&[
+                self.key(&format!("login_failures:{}", subject)),
+                self.key(&format!("login_retry_after:{}", subject)),
+            ])
+            .await
+    }
+
+    /// Whether `user_id` made too many invalid second factor attempts. These are
+    /// counted apart from the password failures, so that logging in again does
+    /// not give more guesses.
+    #[tracing::instrument(name = "Check second factor lockout", skip(self))]
+    pub async fn is_two_factor_locked_out(&self, user_id: Uuid) -> Result<bool, redis::RedisError> {
+        let failures: Option<u64> = self
+            .connection
+            .clone()
+            .get(self.two_factor_key(user_id))
+            .await?;
+        Ok(failures.unwrap_or(0) >= self.settings.two_factor_failures_per_user)
+    }
+
+    /// Count an invalid second factor. Returns `true` if `user_id` is now locked out.
+    #[tracing::instrument(name = "Record second factor failure", skip(self))]
+    pub async fn record_two_factor_failure(
+        &self,
+        user_id: Uuid,
+    ) -> Result<bool, redis::RedisError> {
+        let failures = self
+            .increment(
+                &self.two_factor_key(user_id),
+                self.settings.login_lockout_secs,
+            )
+            .await?;
+        if failures == self.settings.two_factor_failures_per_user {
+            tracing::warn!(%user_id, failures, "Second factor locked out");
+        }
+        Ok(failures >= self.settings.two_factor_failures_per_user)
+    }
+
+    #[tracing::instrument(name = "Record second factor success", skip(self))]
+    pub async fn record_two_factor_success(&self, user_id: Uuid) -> Result<(), redis::RedisError> {
+        self.connection
+            .clone()
+            .del(self.two_factor_key(user_id))
+            .await
+    }
+
+    fn two_factor_key(&self, user_id: Uuid) -> String {
+        self.key(&format!("two_factor_failures:{}", user_id))
+    }
+
+    fn login_subjects(&self, username: &str, ip: &str) -> [(String, u64); 2] {
+        [
+            (
+                format!("user:{}", username),
+                self.settings.login_failures_per_username,
+            ),
+            (format!("ip:{}", ip), self.settings.login_failures_per_ip),
+        ]
+    }
+
+    /// No wait after a first failure, likely a typo; then doubling waits.
+    fn login_delay_ms(&self, failures: u64) -> Option<u64> {
+        if failures < 2 || self.settings.login_delay_base_ms == 0 {
+            return None;
+        }
+        let delay = self
+            .settings
+            .login_delay_base_ms
+            .saturating_mul(1 << (failures - 2).min(16));
+        Some(delay.min(self.settings.login_lockout_secs as u64 * 1000))
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}:rate_limit:{}", self.settings.key_prefix, key)
+    }
+
+    /// Increment the counter at `key`, which expires `expiry_secs` after its
+    /// first increment. The expiry is set along with the counter, so that a
+    /// crash in between cannot leave a counter that never expires.
+    async fn increment(&self, key: &str, expiry_secs: usize) -> Result<u64, redis::RedisError> {
+        let (count,): (u64,) = redis::pipe()
+            .atomic()
+            .cmd("SET")
+            .arg(key)
+            .arg(0)
+            .arg("EX")
+            .arg(expiry_secs)
+            .arg("NX")
+            .ignore()
+            .incr(key, 1)
+            .query_async(&mut self.connection.clone())
+            .await?;
+        Ok(count)
+    }
+
+    /// Increment a counter and return its new value for the current window.
+    async fn hit(&self, key: &str) -> Result<u64, redis::RedisError> {
+        self.increment(&self.key(key), WINDOW_SECS).await
+    }
+}
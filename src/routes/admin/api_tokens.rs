@@ -0,0 +1,309 @@
+use actix_web::{get, http::header::ContentType, post, web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::ExposeSecret;
+use serde_json::json;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    authentication::{generate_api_token, hash_api_token, ApiScope, Role, UserId},
+    session_state::TypedSession,
+    templates::{flash_message_views, Templates},
+    utils::{e500, see_other},
+};
+
+const MAX_NAME_LENGTH: usize = 100;
+
+#[derive(serde::Serialize)]
+struct ApiTokenRow {
+    id: Uuid,
+    name: String,
+    scopes: Vec<String>,
+    created_at: String,
+    expires_at: String,
+    last_used_at: String,
+    revoked: bool,
+}
+
+fn display_date(date: Option<DateTime<Utc>>) -> String {
+    date.map(|d| d.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_else(|| "never".into())
+}
+
+#[get("/api_tokens")]
+pub async fn api_tokens_page(
+    user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let tokens: Vec<ApiTokenRow> = sqlx::query!(
+        r#"
+        SELECT id, name, scopes, created_at, expires_at, last_used_at, revoked_at
+        FROM api_tokens
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        "#,
+        **user_id,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .map_err(e500)?
+    .into_iter()
+    .map(|r| ApiTokenRow {
+        id: r.id,
+        name: r.name,
+        scopes: r.scopes,
+        created_at: display_date(Some(r.created_at)),
+        expires_at: display_date(r.expires_at),
+        last_used_at: display_date(r.last_used_at),
+        revoked: r.revoked_at.is_some(),
+    })
+    .collect();
+    // Scopes beyond the role would be useless, they are not offered.
+    let scopes: Vec<&str> = ApiScope::ALL
+        .iter()
+        .filter(|s| role.permits(s.permission()))
+        .map(|s| s.as_str())
+        .collect();
+    let mut context = tera::Context::new();
+    context.insert("csrf_token", &session.csrf_token().map_err(e500)?);
+    context.insert("messages", &flash_message_views(&flash_messages));
+    context.insert("tokens", &tokens);
+    context.insert("scopes", &scopes);
+    let body = templates
+        .render("admin/api_tokens.html", &context)
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// A validated token creation form.
+struct NewApiToken {
+    name: String,
+    scopes: Vec<ApiScope>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl NewApiToken {
+    /// The form is read as a list of pairs, as checkboxes repeat the `scope` key.
+    fn parse(form: Vec<(String, String)>) -> Result<Self, String> {
+        let mut name = String::new();
+        let mut scopes = vec![];
+        let mut expires_at = None;
+        for (key, value) in form {
+            match key.as_str() {
+                "name" => name = value.trim().to_string(),
+                "scope" => scopes.push(value.parse::<ApiScope>().map_err(|e| e.to_string())?),
+                "expires_in_days" if !value.trim().is_empty() => {
+                    let days = value
+                        .trim()
+                        .parse::<i64>()
+                        .ok()
+                        .filter(|days| (1..=3650).contains(days))
+                        .ok_or("The expiry must be a number of days between 1 and 3650.")?;
+                    expires_at = Some(Utc::now() + chrono::Duration::days(days));
+                }
+                _ => {}
+            }
+        }
+        if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+            return Err(format!(
+                "The token name must be between 1 and {} characters long.",
+                MAX_NAME_LENGTH
+            ));
+        }
+        if scopes.is_empty() {
+            return Err("A token needs at least one scope.".into());
+        }
+        scopes.sort_unstable_by_key(|scope| scope.as_str());
+        scopes.dedup();
+        Ok(Self {
+            name,
+            scopes,
+            expires_at,
+        })
+    }
+}
+
+#[tracing::instrument(
+    name = "Create an API token",
+    skip(form, pool, request, templates),
+    fields(user_id = %*user_id, role = %*role)
+)]
+#[post("/api_tokens")]
+pub async fn create_api_token(
+    form: web::Form<Vec<(String, String)>>,
+    user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let new_token = match NewApiToken::parse(form.into_inner()) {
+        Ok(new_token) => new_token,
+        Err(message) => {
+            FlashMessage::error(message).send();
+            return Ok(see_other("/admin/api_tokens"));
+        }
+    };
+    if let Some(scope) = new_token
+        .scopes
+        .iter()
+        .find(|s| !role.permits(s.permission()))
+    {
+        FlashMessage::error(format!(
+            "Your role does not allow you to {}.",
+            scope.permission()
+        ))
+        .send();
+        return Ok(see_other("/admin/api_tokens"));
+    }
+    let token = generate_api_token();
+    let scopes: Vec<String> = new_token
+        .scopes
+        .iter()
+        .map(|s| s.as_str().to_string())
+        .collect();
+    let token_id = Uuid::new_v4();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    sqlx::query!(
+        r#"
+        INSERT INTO api_tokens (id, user_id, name, token_hash, scopes, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        token_id,
+        **user_id,
+        new_token.name,
+        hash_api_token(token.expose_secret()),
+        &scopes,
+        new_token.expires_at,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to store the API token")
+    .map_err(e500)?;
+    AuditEvent::new(AuditAction::ApiTokenCreated, &request)
+        .actor(**user_id)
+        .target(token_id)
+        .changes(json!({
+            "name": new_token.name,
+            "scopes": scopes,
+            "expires_at": new_token.expires_at,
+        }))
+        .record(&mut transaction)
+        .await
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the API token")
+        .map_err(e500)?;
+
+    let mut context = tera::Context::new();
+    context.insert("name", &new_token.name);
+    context.insert("token", token.expose_secret());
+    let body = templates
+        .render("admin/api_token_created.html", &context)
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[tracing::instrument(name = "Revoke an API token", skip(pool), fields(user_id = %*user_id))]
+#[post("/api_tokens/{token_id}/revoke")]
+pub async fn revoke_api_token(
+    token_id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let revoked = sqlx::query!(
+        r#"
+        UPDATE api_tokens SET revoked_at = now()
+        WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+        "#,
+        *token_id,
+        **user_id,
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to revoke the API token")
+    .map_err(e500)?;
+    if revoked.rows_affected() == 1 {
+        FlashMessage::info("The API token has been revoked.").send();
+    } else {
+        FlashMessage::error("There is no such API token.").send();
+    }
+    Ok(see_other("/admin/api_tokens"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn form(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn repeated_scopes_are_collected() {
+        let token = NewApiToken::parse(form(&[
+            ("name", "CI"),
+            ("scope", "newsletters:publish"),
+            ("scope", "subscribers:read"),
+            ("expires_in_days", ""),
+        ]))
+        .unwrap();
+        assert_eq!(
+            token.scopes,
+            vec![ApiScope::NewslettersPublish, ApiScope::SubscribersRead]
+        );
+        assert!(token.expires_at.is_none());
+    }
+
+    #[test]
+    fn a_scope_is_only_stored_once() {
+        let token = NewApiToken::parse(form(&[
+            ("name", "CI"),
+            ("scope", "subscribers:read"),
+            ("scope", "newsletters:publish"),
+            ("scope", "subscribers:read"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            token.scopes,
+            vec![ApiScope::NewslettersPublish, ApiScope::SubscribersRead]
+        );
+    }
+
+    #[test]
+    fn a_token_needs_a_name_and_a_scope() {
+        assert!(NewApiToken::parse(form(&[("name", "CI")])).is_err());
+        assert!(NewApiToken::parse(form(&[("scope", "subscribers:read")])).is_err());
+    }
+
+    #[test]
+    fn unknown_scopes_and_bogus_expiries_are_rejected() {
+        assert!(NewApiToken::parse(form(&[("name", "CI"), ("scope", "root")])).is_err());
+        assert!(NewApiToken::parse(form(&[
+            ("name", "CI"),
+            ("scope", "subscribers:read"),
+            ("expires_in_days", "-3"),
+        ]))
+        .is_err());
+    }
+}
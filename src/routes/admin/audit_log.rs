@@ -0,0 +1,86 @@
+use actix_web::{get, http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use sqlx::PgPool;
+
+use crate::{
+    audit::{list_audit_entries, AuditAction, AuditFilter},
+    templates::{flash_message_views, Templates},
+    utils::{e500, see_other},
+};
+
+const PAGE_SIZE: i64 = 50;
+
+#[derive(serde::Deserialize)]
+pub struct AuditLogQuery {
+    /// Only entries older than this id.
+    before: Option<i64>,
+    action: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct AuditRow {
+    occurred_at: String,
+    actor: String,
+    ip: String,
+    action: String,
+    target: String,
+    changes: String,
+}
+
+#[get("")]
+pub async fn audit_log_page(
+    query: web::Query<AuditLogQuery>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let query = query.into_inner();
+    let action = match query.action.as_deref().filter(|a| !a.is_empty()) {
+        Some(action) => match action.parse::<AuditAction>() {
+            Ok(action) => Some(action),
+            Err(e) => {
+                FlashMessage::error(e.to_string()).send();
+                return Ok(see_other("/admin/audit_log"));
+            }
+        },
+        None => None,
+    };
+    let filter = AuditFilter {
+        action,
+        ..Default::default()
+    };
+    // One extra row tells whether there are older entries.
+    let mut entries = list_audit_entries(&filter, query.before, PAGE_SIZE + 1, &pool)
+        .await
+        .map_err(e500)?;
+    let next_before = if entries.len() as i64 > PAGE_SIZE {
+        entries.truncate(PAGE_SIZE as usize);
+        entries.last().map(|e| e.id)
+    } else {
+        None
+    };
+    let rows: Vec<AuditRow> = entries
+        .into_iter()
+        .map(|e| AuditRow {
+            occurred_at: e.occurred_at.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+            actor: e.actor_username.unwrap_or_else(|| "-".into()),
+            ip: e.ip,
+            action: e.action,
+            target: e.target.unwrap_or_default(),
+            changes: e.changes.to_string(),
+        })
+        .collect();
+    let actions: Vec<&str> = AuditAction::ALL.iter().map(|a| a.as_str()).collect();
+    let mut context = tera::Context::new();
+    context.insert("messages", &flash_message_views(&flash_messages));
+    context.insert("entries", &rows);
+    context.insert("actions", &actions);
+    context.insert("action", &action.map(|a| a.as_str()));
+    context.insert("next_before", &next_before);
+    let body = templates
+        .render("admin/audit_log.html", &context)
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
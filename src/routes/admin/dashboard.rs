@@ -0,0 +1,46 @@
+use actix_web::{get, http::header::ContentType, web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    authentication::{Permission, Role, UserId},
+    session_state::TypedSession,
+    templates::Templates,
+    utils::e500,
+};
+
+#[get("/dashboard")]
+pub async fn admin_dashboard(
+    user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let username = get_username(**user_id, &pool).await.map_err(e500)?;
+    let mut context = tera::Context::new();
+    context.insert("csrf_token", &session.csrf_token().map_err(e500)?);
+    context.insert("username", &username);
+    context.insert("role", role.as_str());
+    context.insert("can_manage_users", &role.permits(Permission::ManageUsers));
+    context.insert(
+        "can_read_audit_log",
+        &role.permits(Permission::ReadAuditLog),
+    );
+    let body = templates
+        .render("admin/dashboard.html", &context)
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[tracing::instrument(name = "Get username", skip(pool))]
+pub async fn get_username(user_id: Uuid, pool: &PgPool) -> Result<String, anyhow::Error> {
+    let row = sqlx::query!(r#"SELECT username FROM users WHERE user_id = $1"#, user_id,)
+        .fetch_one(pool)
+        .await
+        .context("Failed to perform a query to retrieve a username.")?;
+    Ok(row.username)
+}
@@ -0,0 +1,25 @@
+use actix_web::{post, web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+
+use crate::{
+    authentication::{revoke_user_session, UserId},
+    session_state::TypedSession,
+    utils::{e500, see_other},
+};
+
+#[post("/logout")]
+pub async fn log_out(
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if let Some(session_id) = session.get_session_id().map_err(e500)? {
+        revoke_user_session(**user_id, session_id, &pool)
+            .await
+            .map_err(e500)?;
+    }
+    session.log_out();
+    FlashMessage::info("You have successfully logged out.").send();
+    Ok(see_other("/login"))
+}
@@ -0,0 +1,15 @@
+mod api_tokens;
+mod audit_log;
+mod dashboard;
+mod logout;
+mod sessions;
+mod two_factor;
+mod users;
+
+pub use api_tokens::*;
+pub use audit_log::audit_log_page;
+pub use dashboard::admin_dashboard;
+pub use logout::log_out;
+pub use sessions::*;
+pub use two_factor::*;
+pub use users::*;
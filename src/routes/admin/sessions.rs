@@ -0,0 +1,104 @@
+use actix_web::{get, http::header::ContentType, post, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    authentication::{list_user_sessions, revoke_other_user_sessions, revoke_user_session, UserId},
+    configuration::SessionCookieSettings,
+    session_state::TypedSession,
+    templates::{flash_message_views, Templates},
+    utils::{e500, see_other},
+};
+
+#[derive(serde::Serialize)]
+struct SessionRow {
+    id: Uuid,
+    created_at: String,
+    last_seen_at: String,
+    ip: String,
+    user_agent: String,
+    is_current: bool,
+}
+
+fn display_date(date: DateTime<Utc>) -> String {
+    date.format("%Y-%m-%d %H:%M UTC").to_string()
+}
+
+#[get("/sessions")]
+pub async fn sessions_page(
+    user_id: web::ReqData<UserId>,
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    settings: web::Data<SessionCookieSettings>,
+    flash_messages: IncomingFlashMessages,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let current_session_id = session.get_session_id().map_err(e500)?;
+    let sessions: Vec<SessionRow> = list_user_sessions(**user_id, settings.max_age(), &pool)
+        .await
+        .map_err(e500)?
+        .into_iter()
+        .map(|s| SessionRow {
+            id: s.session_id,
+            created_at: display_date(s.created_at),
+            last_seen_at: display_date(s.last_seen_at),
+            ip: s.ip,
+            user_agent: s.user_agent,
+            is_current: Some(s.session_id) == current_session_id,
+        })
+        .collect();
+    let mut context = tera::Context::new();
+    context.insert("csrf_token", &session.csrf_token().map_err(e500)?);
+    context.insert("messages", &flash_message_views(&flash_messages));
+    context.insert("sessions", &sessions);
+    let body = templates
+        .render("admin/sessions.html", &context)
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// Revoking the current session logs out on the next request, like any other.
+#[tracing::instrument(name = "Revoke a session", skip(pool), fields(user_id = %*user_id))]
+#[post("/sessions/{session_id}/revoke")]
+pub async fn revoke_session(
+    session_id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if revoke_user_session(**user_id, *session_id, &pool)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::info("The session has been revoked.").send();
+    } else {
+        FlashMessage::error("There is no such active session.").send();
+    }
+    Ok(see_other("/admin/sessions"))
+}
+
+#[tracing::instrument(
+    name = "Revoke the other sessions",
+    skip(session, pool),
+    fields(user_id = %*user_id)
+)]
+#[post("/sessions/revoke_others")]
+pub async fn revoke_other_sessions(
+    user_id: web::ReqData<UserId>,
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    // `RejectAnonymousUsers` only lets recorded sessions through.
+    let current_session_id = session
+        .get_session_id()
+        .map_err(e500)?
+        .ok_or_else(|| e500("The session has no id"))?;
+    let revoked = revoke_other_user_sessions(**user_id, current_session_id, &pool)
+        .await
+        .map_err(e500)?;
+    FlashMessage::info(format!("{} other session(s) revoked.", revoked)).send();
+    Ok(see_other("/admin/sessions"))
+}
@@ -0,0 +1,152 @@
+use actix_web::{get, http::header::ContentType, post, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+use crate::{
+    authentication::{
+        confirm_two_factor_enrollment, disable_two_factor, get_two_factor_state, otpauth_uri,
+        qr_code_svg, regenerate_recovery_codes, start_two_factor_enrollment, verify_second_factor,
+        EnrollmentOutcome, UserId,
+    },
+    configuration::TwoFactorSettings,
+    session_state::TypedSession,
+    templates::{flash_message_views, Templates},
+    utils::{e500, see_other},
+};
+
+use super::dashboard::get_username;
+
+#[get("/two_factor")]
+pub async fn two_factor_page(
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    settings: web::Data<TwoFactorSettings>,
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = **user_id;
+    let state = get_two_factor_state(user_id, &pool).await.map_err(e500)?;
+    let mut context = tera::Context::new();
+    context.insert("csrf_token", &session.csrf_token().map_err(e500)?);
+    context.insert("messages", &flash_message_views(&flash_messages));
+    context.insert("enabled", &state.enabled);
+    context.insert("required", &settings.required);
+    if state.enabled {
+        session.insert_two_factor_enrolled(true).map_err(e500)?;
+    } else {
+        let secret = match state.secret {
+            Some(secret) => secret,
+            None => start_two_factor_enrollment(user_id, &pool)
+                .await
+                .map_err(e500)?,
+        };
+        let username = get_username(user_id, &pool).await.map_err(e500)?;
+        let uri = otpauth_uri(&secret, &settings.issuer, &username);
+        context.insert("qr_code", &qr_code_svg(&uri).map_err(e500)?);
+        context.insert("secret", secret.expose_secret());
+        context.insert("otpauth_uri", &uri);
+    }
+    let body = templates
+        .render("admin/two_factor.html", &context)
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[derive(serde::Deserialize)]
+pub struct CodeForm {
+    code: Secret<String>,
+}
+
+#[tracing::instrument(name = "Enable two-factor authentication", skip(form, pool, session, templates), fields(user_id = %*user_id))]
+#[post("/two_factor/enable")]
+pub async fn enable_two_factor(
+    form: web::Form<CodeForm>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    match confirm_two_factor_enrollment(**user_id, form.code.expose_secret(), &pool)
+        .await
+        .map_err(e500)?
+    {
+        EnrollmentOutcome::Enabled(codes) => {
+            session.insert_two_factor_enrolled(true).map_err(e500)?;
+            recovery_codes_page(codes, &templates)
+        }
+        EnrollmentOutcome::InvalidCode => {
+            FlashMessage::error("Invalid authentication code.").send();
+            Ok(see_other("/admin/two_factor"))
+        }
+        EnrollmentOutcome::NothingPending => {
+            FlashMessage::info("There is no two-factor authentication setup to confirm.").send();
+            Ok(see_other("/admin/two_factor"))
+        }
+    }
+}
+
+#[tracing::instrument(name = "Replace recovery codes", skip(form, pool, templates), fields(user_id = %*user_id))]
+#[post("/two_factor/recovery_codes")]
+pub async fn replace_recovery_codes(
+    form: web::Form<CodeForm>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !verify_second_factor(**user_id, form.code.expose_secret(), &pool)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::error("Invalid authentication code.").send();
+        return Ok(see_other("/admin/two_factor"));
+    }
+    let codes = regenerate_recovery_codes(**user_id, &pool)
+        .await
+        .map_err(e500)?;
+    recovery_codes_page(codes, &templates)
+}
+
+#[tracing::instrument(name = "Disable two-factor authentication", skip(form, pool, settings, session), fields(user_id = %*user_id))]
+#[post("/two_factor/disable")]
+pub async fn disable_two_factor_authentication(
+    form: web::Form<CodeForm>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    settings: web::Data<TwoFactorSettings>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    if settings.required {
+        FlashMessage::error("Two-factor authentication is required for all admins.").send();
+        return Ok(see_other("/admin/two_factor"));
+    }
+    if !verify_second_factor(**user_id, form.code.expose_secret(), &pool)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::error("Invalid authentication code.").send();
+        return Ok(see_other("/admin/two_factor"));
+    }
+    disable_two_factor(**user_id, &pool).await.map_err(e500)?;
+    session.insert_two_factor_enrolled(false).map_err(e500)?;
+    FlashMessage::info("Two-factor authentication has been disabled.").send();
+    Ok(see_other("/admin/two_factor"))
+}
+
+fn recovery_codes_page(
+    codes: Vec<Secret<String>>,
+    templates: &Templates,
+) -> Result<HttpResponse, actix_web::Error> {
+    let codes: Vec<&str> = codes.iter().map(|c| c.expose_secret().as_str()).collect();
+    let mut context = tera::Context::new();
+    context.insert("codes", &codes);
+    let body = templates
+        .render("admin/recovery_codes.html", &context)
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
@@ -0,0 +1,378 @@
+use actix_web::{get, http::header::ContentType, post, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    authentication::{create_invitation, revoke_all_user_sessions, Role, UserId},
+    configuration::InvitationSettings,
+    domain::SubscriberEmail,
+    email_outbox::{enqueue_email, OutgoingEmail},
+    session_state::TypedSession,
+    startup::ApplicationBaseUrl,
+    templates::{flash_message_views, Templates},
+    utils::{e500, see_other},
+};
+
+use super::dashboard::get_username;
+
+const MAX_USERNAME_LENGTH: usize = 64;
+
+#[derive(serde::Serialize)]
+struct UserRow {
+    id: Uuid,
+    username: String,
+    email: String,
+    role: String,
+    status: &'static str,
+    created_at: String,
+    is_self: bool,
+}
+
+#[get("")]
+pub async fn users_page(
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let users: Vec<UserRow> = sqlx::query!(
+        r#"
+        SELECT user_id, username, email, role, password_hash IS NOT NULL AS "activated!",
+            disabled_at, created_at
+        FROM users
+        ORDER BY created_at, username
+        "#,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .map_err(e500)?
+    .into_iter()
+    .map(|r| UserRow {
+        id: r.user_id,
+        username: r.username,
+        email: r.email.unwrap_or_default(),
+        role: r.role,
+        status: match (r.disabled_at, r.activated) {
+            (Some(_), _) => "disabled",
+            (None, false) => "invited",
+            (None, true) => "active",
+        },
+        created_at: display_date(r.created_at),
+        is_self: r.user_id == **user_id,
+    })
+    .collect();
+    let roles: Vec<&str> = Role::ALL.iter().map(|r| r.as_str()).collect();
+    let mut context = tera::Context::new();
+    context.insert("csrf_token", &session.csrf_token().map_err(e500)?);
+    context.insert("messages", &flash_message_views(&flash_messages));
+    context.insert("users", &users);
+    context.insert("roles", &roles);
+    let body = templates
+        .render("admin/users.html", &context)
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+fn display_date(date: DateTime<Utc>) -> String {
+    date.format("%Y-%m-%d %H:%M UTC").to_string()
+}
+
+#[derive(serde::Deserialize)]
+pub struct InvitationForm {
+    username: String,
+    email: String,
+    role: String,
+}
+
+/// A validated invitation form.
+struct NewUser {
+    username: String,
+    email: SubscriberEmail,
+    role: Role,
+}
+
+impl NewUser {
+    fn parse(form: InvitationForm) -> Result<Self, String> {
+        let username = form.username.trim().to_string();
+        if username.is_empty()
+            || username.chars().count() > MAX_USERNAME_LENGTH
+            || username
+                .chars()
+                .any(|c| c.is_whitespace() || c.is_control())
+        {
+            return Err(format!(
+                "The username must be between 1 and {} characters long, without spaces.",
+                MAX_USERNAME_LENGTH
+            ));
+        }
+        let email = SubscriberEmail::parse(form.email.trim().to_string())
+            .map_err(|_| format!("`{}` is not a valid email address.", form.email.trim()))?;
+        let role = form.role.parse::<Role>().map_err(|e| e.to_string())?;
+        Ok(Self {
+            username,
+            email,
+            role,
+        })
+    }
+}
+
+/// Create the account without a password, and email the invitee a link to set one.
+#[tracing::instrument(
+    name = "Invite a user",
+    skip(form, pool, base_url, settings, templates),
+    fields(user_id = %*user_id)
+)]
+#[post("")]
+pub async fn invite_user(
+    form: web::Form<InvitationForm>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    settings: web::Data<InvitationSettings>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let new_user = match NewUser::parse(form.0) {
+        Ok(new_user) => new_user,
+        Err(message) => {
+            FlashMessage::error(message).send();
+            return Ok(see_other("/admin/users"));
+        }
+    };
+    let inviter = get_username(**user_id, &pool).await.map_err(e500)?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let invitee_id = Uuid::new_v4();
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, email, role)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (username) DO NOTHING
+        "#,
+        invitee_id,
+        new_user.username,
+        new_user.email.as_ref(),
+        new_user.role.as_str(),
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to store the invited user")
+    .map_err(e500)?;
+    if inserted.rows_affected() == 0 {
+        FlashMessage::error(format!(
+            "The username `{}` is already taken.",
+            new_user.username
+        ))
+        .send();
+        return Ok(see_other("/admin/users"));
+    }
+    let token = create_invitation(
+        &mut transaction,
+        invitee_id,
+        **user_id,
+        settings.token_validity(),
+    )
+    .await
+    .map_err(e500)?;
+    let invitation_link = format!(
+        "{}/invitations/accept?token={}",
+        base_url.as_ref(),
+        token.expose_secret()
+    );
+    let mut context = tera::Context::new();
+    context.insert("inviter", &inviter);
+    context.insert("role", new_user.role.as_str());
+    context.insert("username", &new_user.username);
+    context.insert("invitation_link", &invitation_link);
+    context.insert("hours", &settings.token_validity_hours);
+    let html_body = templates
+        .render("emails/invitation.html", &context)
+        .map_err(e500)?;
+    let text_body = templates
+        .render("emails/invitation.txt", &context)
+        .map_err(e500)?;
+    enqueue_email(
+        &mut transaction,
+        OutgoingEmail {
+            recipient: &new_user.email,
+            subject: "You have been invited",
+            html_body: &html_body,
+            text_body: &text_body,
+        },
+    )
+    .await
+    .context("Failed to enqueue the invitation email")
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the invitation")
+        .map_err(e500)?;
+    FlashMessage::info(format!(
+        "An invitation has been sent to {}.",
+        new_user.email.as_ref()
+    ))
+    .send();
+    Ok(see_other("/admin/users"))
+}
+
+/// Disabling also revokes the sessions, so that enabling back does not revive them.
+#[tracing::instrument(name = "Disable a user", skip(pool), fields(user_id = %*user_id))]
+#[post("/{target_id}/disable")]
+pub async fn disable_user(
+    target_id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if *target_id == **user_id {
+        FlashMessage::error("You cannot disable your own account.").send();
+        return Ok(see_other("/admin/users"));
+    }
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let row = sqlx::query!(
+        r#"
+        UPDATE users SET disabled_at = now()
+        WHERE user_id = $1 AND disabled_at IS NULL
+        RETURNING username
+        "#,
+        *target_id,
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to disable the user")
+    .map_err(e500)?;
+    revoke_all_user_sessions(&mut transaction, *target_id)
+        .await
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the user deactivation")
+        .map_err(e500)?;
+    match row {
+        Some(row) => FlashMessage::info(format!("{} has been disabled.", row.username)).send(),
+        None => FlashMessage::error("There is no such active user.").send(),
+    }
+    Ok(see_other("/admin/users"))
+}
+
+#[tracing::instrument(name = "Enable a user", skip(pool), fields(user_id = %*user_id))]
+#[post("/{target_id}/enable")]
+pub async fn enable_user(
+    target_id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE users SET disabled_at = NULL
+        WHERE user_id = $1 AND disabled_at IS NOT NULL
+        RETURNING username
+        "#,
+        *target_id,
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to enable the user")
+    .map_err(e500)?;
+    match row {
+        Some(row) => FlashMessage::info(format!("{} has been enabled.", row.username)).send(),
+        None => FlashMessage::error("There is no such disabled user.").send(),
+    }
+    Ok(see_other("/admin/users"))
+}
+
+#[tracing::instrument(name = "Delete a user", skip(pool), fields(user_id = %*user_id))]
+#[post("/{target_id}/delete")]
+pub async fn delete_user(
+    target_id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if *target_id == **user_id {
+        FlashMessage::error("You cannot delete your own account.").send();
+        return Ok(see_other("/admin/users"));
+    }
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    for query in [
+        sqlx::query!(r#"DELETE FROM api_tokens WHERE user_id = $1"#, *target_id),
+        sqlx::query!(
+            r#"DELETE FROM recovery_codes WHERE user_id = $1"#,
+            *target_id
+        ),
+        sqlx::query!(
+            r#"DELETE FROM password_reset_tokens WHERE user_id = $1"#,
+            *target_id
+        ),
+    ] {
+        query
+            .execute(&mut transaction)
+            .await
+            .context("Failed to delete the user's credentials")
+            .map_err(e500)?;
+    }
+    let row = sqlx::query!(
+        r#"DELETE FROM users WHERE user_id = $1 RETURNING username"#,
+        *target_id
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to delete the user")
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the user deletion")
+        .map_err(e500)?;
+    match row {
+        Some(row) => FlashMessage::info(format!("{} has been deleted.", row.username)).send(),
+        None => FlashMessage::error("There is no such user.").send(),
+    }
+    Ok(see_other("/admin/users"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn form(username: &str, email: &str, role: &str) -> InvitationForm {
+        InvitationForm {
+            username: username.into(),
+            email: email.into(),
+            role: role.into(),
+        }
+    }
+
+    #[test]
+    fn a_valid_invitation_is_parsed() {
+        let new_user = NewUser::parse(form(" ursula ", "ursula@example.com", "editor")).unwrap();
+        assert_eq!(new_user.username, "ursula");
+        assert_eq!(new_user.role, Role::Editor);
+    }
+
+    #[test]
+    fn invalid_usernames_emails_and_roles_are_rejected() {
+        assert!(NewUser::parse(form("", "ursula@example.com", "editor")).is_err());
+        assert!(NewUser::parse(form("ursula le guin", "ursula@example.com", "editor")).is_err());
+        assert!(NewUser::parse(form(&"a".repeat(65), "ursula@example.com", "editor")).is_err());
+        assert!(NewUser::parse(form("ursula", "not-an-email", "editor")).is_err());
+        assert!(NewUser::parse(form("ursula", "ursula@example.com", "admin")).is_err());
+    }
+}
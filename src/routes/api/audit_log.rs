@@ -0,0 +1,89 @@
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use sqlx::PgPool;
+
+use crate::{
+    audit::{self, AuditAction, AuditEntry, AuditFilter},
+    authentication::{ApiScope, PasswordHashing},
+    rate_limit::RateLimiter,
+};
+
+use super::{authenticate, ApiError};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(Debug, serde::Deserialize)]
+pub struct AuditLogParameters {
+    /// e.g. `login.failed`.
+    action: Option<String>,
+    /// Username of the actor, or attempted by a failed login.
+    actor: Option<String>,
+    limit: Option<i64>,
+    /// `next_cursor` of the previous page.
+    cursor: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct AuditPage {
+    data: Vec<AuditEntry>,
+    /// `null` on the last page.
+    next_cursor: Option<String>,
+}
+
+/// The audit log, newest entries first.
+#[tracing::instrument(
+    name = "List audit entries",
+    skip(pool, rate_limiter, hashing, request)
+)]
+#[get("/audit_log")]
+pub async fn list_audit_entries(
+    parameters: web::Query<AuditLogParameters>,
+    pool: web::Data<PgPool>,
+    rate_limiter: web::Data<RateLimiter>,
+    hashing: web::Data<PasswordHashing>,
+    request: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    authenticate(
+        &request,
+        &pool,
+        &rate_limiter,
+        &hashing,
+        ApiScope::AuditLogRead,
+    )
+    .await?;
+    let parameters = parameters.into_inner();
+    let limit = parameters.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(ApiError::InvalidParameter(format!(
+            "`limit` must be between 1 and {}.",
+            MAX_PAGE_SIZE
+        )));
+    }
+    let action = parameters
+        .action
+        .as_deref()
+        .map(str::parse::<AuditAction>)
+        .transpose()
+        .map_err(|e| ApiError::InvalidParameter(e.to_string()))?;
+    // Entry ids only decrease along the listing, the last one is the cursor.
+    let before = parameters
+        .cursor
+        .as_deref()
+        .map(str::parse::<i64>)
+        .transpose()
+        .map_err(|_| ApiError::InvalidParameter("`cursor` is not valid.".into()))?;
+    let filter = AuditFilter {
+        action,
+        actor_username: parameters.actor,
+    };
+
+    // One extra row tells whether there is a next page.
+    let mut data = audit::list_audit_entries(&filter, before, limit + 1, &pool).await?;
+    let next_cursor = if data.len() as i64 > limit {
+        data.truncate(limit as usize);
+        data.last().map(|last| last.id.to_string())
+    } else {
+        None
+    };
+    Ok(HttpResponse::Ok().json(AuditPage { data, next_cursor }))
+}
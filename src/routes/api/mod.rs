@@ -0,0 +1,133 @@
+//! Versioned JSON API, for machine clients and admin tooling.
+use actix_web::{
+    http::{header, header::HeaderValue, StatusCode},
+    HttpRequest, HttpResponse, ResponseError,
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    authentication::{authenticate_api_caller, ApiScope, AuthError, PasswordHashing, Permission},
+    rate_limit::RateLimiter,
+};
+
+use super::{error_chain_fmt, Problem};
+
+mod audit_log;
+mod subscribers;
+
+pub use audit_log::*;
+pub use subscribers::*;
+
+/// Authenticate the caller of an API route, and check it was granted `scope`.
+pub async fn authenticate(
+    request: &HttpRequest,
+    pool: &PgPool,
+    rate_limiter: &RateLimiter,
+    hashing: &PasswordHashing,
+    scope: ApiScope,
+) -> Result<Uuid, ApiError> {
+    let caller = authenticate_api_caller(request, pool, rate_limiter, hashing)
+        .await
+        .map_err(|e| match e {
+            AuthError::InvalidCredentials(_) => ApiError::AuthError(e.into()),
+            AuthError::UnexpectedError(_) => ApiError::UnexpectedError(e.into()),
+        })?;
+    if !caller.role.permits(scope.permission()) {
+        return Err(ApiError::Forbidden(scope.permission()));
+    }
+    if !caller.has_scope(scope) {
+        return Err(ApiError::InsufficientScope(scope));
+    }
+    Ok(caller.user_id)
+}
+
+#[derive(thiserror::Error)]
+pub enum ApiError {
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+    #[error("The API token lacks the `{0}` scope.")]
+    InsufficientScope(ApiScope),
+    #[error("Your role does not allow you to {0}.")]
+    Forbidden(Permission),
+    #[error("{0}")]
+    InvalidParameter(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            ApiError::InsufficientScope(_) | ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::InvalidParameter(_) => StatusCode::BAD_REQUEST,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let problem = match self {
+            ApiError::AuthError(_) => Problem::new(
+                self.status_code(),
+                "unauthorized",
+                "Unauthorized",
+                self.to_string(),
+            ),
+            ApiError::InsufficientScope(scope) => Problem::new(
+                self.status_code(),
+                "insufficient-scope",
+                "Insufficient scope",
+                self.to_string(),
+            )
+            .with_extension("required_scope", scope.as_str()),
+            ApiError::Forbidden(_) => Problem::new(
+                self.status_code(),
+                "forbidden",
+                "Forbidden",
+                self.to_string(),
+            ),
+            ApiError::InvalidParameter(_) => Problem::new(
+                self.status_code(),
+                "invalid-parameter",
+                "Invalid parameter",
+                self.to_string(),
+            ),
+            ApiError::NotFound(_) => Problem::new(
+                self.status_code(),
+                "not-found",
+                "Not found",
+                self.to_string(),
+            ),
+            ApiError::Conflict(_) => {
+                Problem::new(self.status_code(), "conflict", "Conflict", self.to_string())
+            }
+            ApiError::UnexpectedError(_) => Problem::internal_error(),
+        };
+        let mut response = problem.response();
+        if let ApiError::AuthError(_) = self {
+            let headers = response.headers_mut();
+            headers.insert(
+                header::WWW_AUTHENTICATE,
+                HeaderValue::from_static(r#"Basic realm="api""#),
+            );
+            headers.append(
+                header::WWW_AUTHENTICATE,
+                HeaderValue::from_static(r#"Bearer realm="api""#),
+            );
+        }
+        response
+    }
+}
@@ -0,0 +1,391 @@
+use actix_web::{delete, get, patch, web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    audit::{diff, AuditAction, AuditEvent},
+    authentication::{ApiScope, PasswordHashing},
+    domain::{EmailPolicy, SubscriberEmail, SubscriberName},
+    i18n::Locale,
+    rate_limit::RateLimiter,
+};
+
+use super::{authenticate, ApiError};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionStatus {
+    PendingConfirmation,
+    Confirmed,
+}
+
+impl SubscriptionStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionStatus::PendingConfirmation => "pending_confirmation",
+            SubscriptionStatus::Confirmed => "confirmed",
+        }
+    }
+}
+
+/// A row of the `subscriptions` table.
+#[derive(Debug, serde::Serialize)]
+pub struct Subscriber {
+    pub id: Uuid,
+    pub email: String,
+    pub canonical_email: String,
+    pub name: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub status: String,
+    pub source: Option<String>,
+    pub locale: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ListParameters {
+    status: Option<SubscriptionStatus>,
+    subscribed_after: Option<DateTime<Utc>>,
+    subscribed_before: Option<DateTime<Utc>>,
+    /// Case-insensitive substring of the email address.
+    email: Option<String>,
+    limit: Option<i64>,
+    /// `next_cursor` of the previous page.
+    cursor: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriberPage {
+    data: Vec<Subscriber>,
+    /// `null` on the last page.
+    next_cursor: Option<String>,
+}
+
+/// Position in the listing, which is ordered by subscription date then id.
+struct Cursor {
+    subscribed_at: DateTime<Utc>,
+    id: Uuid,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        base64::encode_config(
+            format!("{}|{}", self.subscribed_at.to_rfc3339(), self.id),
+            base64::URL_SAFE_NO_PAD,
+        )
+    }
+
+    fn decode(s: &str) -> Result<Self, anyhow::Error> {
+        let decoded = String::from_utf8(base64::decode_config(s, base64::URL_SAFE_NO_PAD)?)?;
+        let (subscribed_at, id) = decoded
+            .split_once('|')
+            .context("Missing cursor separator")?;
+        Ok(Self {
+            subscribed_at: DateTime::parse_from_rfc3339(subscribed_at)?.with_timezone(&Utc),
+            id: id.parse()?,
+        })
+    }
+}
+
+#[tracing::instrument(name = "List subscribers", skip(pool, rate_limiter, hashing, request))]
+#[get("/subscribers")]
+pub async fn list_subscribers(
+    parameters: web::Query<ListParameters>,
+    pool: web::Data<PgPool>,
+    rate_limiter: web::Data<RateLimiter>,
+    hashing: web::Data<PasswordHashing>,
+    request: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = authenticate(
+        &request,
+        &pool,
+        &rate_limiter,
+        &hashing,
+        ApiScope::SubscribersRead,
+    )
+    .await?;
+    let limit = parameters.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(ApiError::InvalidParameter(format!(
+            "`limit` must be between 1 and {}.",
+            MAX_PAGE_SIZE
+        )));
+    }
+    let cursor = parameters
+        .cursor
+        .as_deref()
+        .map(Cursor::decode)
+        .transpose()
+        .map_err(|_| ApiError::InvalidParameter("`cursor` is not valid.".into()))?;
+
+    // One extra row tells whether there is a next page.
+    let mut data = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, canonical_email, name, subscribed_at, status, source, locale
+        FROM subscriptions
+        WHERE ($1::text IS NULL OR status = $1)
+            AND ($2::timestamptz IS NULL OR subscribed_at >= $2)
+            AND ($3::timestamptz IS NULL OR subscribed_at < $3)
+            AND ($4::text IS NULL OR strpos(lower(email), lower($4)) > 0)
+            AND ($5::timestamptz IS NULL OR (subscribed_at, id) > ($5, $6))
+        ORDER BY subscribed_at, id
+        LIMIT $7
+        "#,
+        parameters.status.map(|s| s.as_str()),
+        parameters.subscribed_after,
+        parameters.subscribed_before,
+        parameters.email.as_deref(),
+        cursor.as_ref().map(|c| c.subscribed_at),
+        cursor.as_ref().map(|c| c.id),
+        limit + 1,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to list subscribers")?;
+
+    let next_cursor = if data.len() as i64 > limit {
+        data.truncate(limit as usize);
+        data.last().map(|last| {
+            Cursor {
+                subscribed_at: last.subscribed_at,
+                id: last.id,
+            }
+            .encode()
+        })
+    } else {
+        None
+    };
+    AuditEvent::new(AuditAction::SubscribersExported, &request)
+        .actor(user_id)
+        .changes(json!({
+            "filters": {
+                "status": parameters.status.map(|s| s.as_str()),
+                "subscribed_after": parameters.subscribed_after,
+                "subscribed_before": parameters.subscribed_before,
+                "email": parameters.email.as_deref(),
+                "cursor": parameters.cursor.as_deref(),
+            },
+            "count": data.len(),
+        }))
+        .record(pool.get_ref())
+        .await?;
+    Ok(HttpResponse::Ok().json(SubscriberPage { data, next_cursor }))
+}
+
+#[tracing::instrument(name = "Get a subscriber", skip(pool, rate_limiter, hashing, request))]
+#[get("/subscribers/{subscriber_id}")]
+pub async fn get_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    rate_limiter: web::Data<RateLimiter>,
+    hashing: web::Data<PasswordHashing>,
+    request: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    authenticate(
+        &request,
+        &pool,
+        &rate_limiter,
+        &hashing,
+        ApiScope::SubscribersRead,
+    )
+    .await?;
+    let subscriber = fetch_subscriber(pool.get_ref(), *subscriber_id).await?;
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SubscriberPatch {
+    name: Option<String>,
+    email: Option<String>,
+    status: Option<SubscriptionStatus>,
+    locale: Option<String>,
+}
+
+#[tracing::instrument(
+    name = "Update a subscriber",
+    skip(pool, rate_limiter, hashing, email_policy, request)
+)]
+#[patch("/subscribers/{subscriber_id}")]
+pub async fn update_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    patch: web::Json<SubscriberPatch>,
+    pool: web::Data<PgPool>,
+    rate_limiter: web::Data<RateLimiter>,
+    hashing: web::Data<PasswordHashing>,
+    email_policy: web::Data<EmailPolicy>,
+    request: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = authenticate(
+        &request,
+        &pool,
+        &rate_limiter,
+        &hashing,
+        ApiScope::SubscribersWrite,
+    )
+    .await?;
+    let subscriber_id = subscriber_id.into_inner();
+    let patch = patch.into_inner();
+    let name = patch
+        .name
+        .map(|name| SubscriberName::parse(&name))
+        .transpose()
+        .map_err(|e| ApiError::InvalidParameter(format!("name: {}", e)))?;
+    let email = patch
+        .email
+        .map(|email| SubscriberEmail::parse_with_policy(email, &email_policy))
+        .transpose()
+        .map_err(|e| ApiError::InvalidParameter(format!("email: {}", e)))?;
+    let locale = patch
+        .locale
+        .as_deref()
+        .map(str::parse::<Locale>)
+        .transpose()
+        .map_err(|e| ApiError::InvalidParameter(format!("locale: {}", e)))?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let before = fetch_subscriber(&mut transaction, subscriber_id).await?;
+    if let Some(email) = &email {
+        let taken = sqlx::query!(
+            r#"SELECT id FROM subscriptions
+            WHERE (email = $1 OR canonical_email = $2) AND id <> $3"#,
+            email.as_ref(),
+            email.canonical(),
+            subscriber_id,
+        )
+        .fetch_optional(&mut transaction)
+        .await
+        .context("Failed to look for an existing subscription")?;
+        if taken.is_some() {
+            return Err(ApiError::Conflict(
+                "Another subscriber already uses this email address.".into(),
+            ));
+        }
+    }
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET
+            name = COALESCE($2, name),
+            email = COALESCE($3, email),
+            canonical_email = COALESCE($4, canonical_email),
+            status = COALESCE($5, status),
+            locale = COALESCE($6, locale)
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        name.as_ref().map(|n| n.as_ref()),
+        email.as_ref().map(|e| e.as_ref()),
+        email.as_ref().map(|e| e.canonical()),
+        patch.status.map(|s| s.as_str()),
+        locale.map(|l| l.as_str()),
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to update the subscriber")?;
+    let after = fetch_subscriber(&mut transaction, subscriber_id).await?;
+    AuditEvent::new(AuditAction::SubscriberUpdated, &request)
+        .actor(user_id)
+        .target(subscriber_id)
+        .changes(diff(&to_json(&before)?, &to_json(&after)?))
+        .record(&mut transaction)
+        .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the subscriber update")?;
+    Ok(HttpResponse::Ok().json(after))
+}
+
+#[tracing::instrument(
+    name = "Delete a subscriber",
+    skip(pool, rate_limiter, hashing, request)
+)]
+#[delete("/subscribers/{subscriber_id}")]
+pub async fn delete_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    rate_limiter: web::Data<RateLimiter>,
+    hashing: web::Data<PasswordHashing>,
+    request: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = authenticate(
+        &request,
+        &pool,
+        &rate_limiter,
+        &hashing,
+        ApiScope::SubscribersWrite,
+    )
+    .await?;
+    let subscriber_id = subscriber_id.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let before = fetch_subscriber(&mut transaction, subscriber_id).await?;
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscription_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the subscription tokens")?;
+    // The emails still waiting in the outbox must not reach a deleted subscriber.
+    sqlx::query!(
+        r#"DELETE FROM email_outbox WHERE recipient_email = $1"#,
+        before.email
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the queued emails of the subscriber")?;
+    sqlx::query!(r#"DELETE FROM subscriptions WHERE id = $1"#, subscriber_id)
+        .execute(&mut transaction)
+        .await
+        .context("Failed to delete the subscriber")?;
+    AuditEvent::new(AuditAction::SubscriberDeleted, &request)
+        .actor(user_id)
+        .target(subscriber_id)
+        .changes(diff(&to_json(&before)?, &Value::Null))
+        .record(&mut transaction)
+        .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the subscriber deletion")?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn fetch_subscriber<'e>(
+    executor: impl PgExecutor<'e>,
+    subscriber_id: Uuid,
+) -> Result<Subscriber, ApiError> {
+    sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, canonical_email, name, subscribed_at, status, source, locale
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id,
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to retrieve the subscriber")?
+    .ok_or_else(|| not_found(subscriber_id))
+}
+
+fn to_json(subscriber: &Subscriber) -> Result<Value, ApiError> {
+    Ok(serde_json::to_value(subscriber).context("Failed to serialize the subscriber")?)
+}
+
+fn not_found(subscriber_id: Uuid) -> ApiError {
+    ApiError::NotFound(format!("There is no subscriber with id {}.", subscriber_id))
+}
@@ -1,8 +1,13 @@
-use actix_web::{get, http::header::ContentType, HttpResponse};
+use actix_web::{get, http::header::ContentType, web, HttpResponse};
+
+use crate::{templates::Templates, utils::e500};
 
 #[get("/")]
-pub async fn home() -> HttpResponse {
-    HttpResponse::Ok()
+pub async fn home(templates: web::Data<Templates>) -> Result<HttpResponse, actix_web::Error> {
+    let body = templates
+        .render("home.html", &tera::Context::new())
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
-        .body(include_str!("home.html"))
+        .body(body))
 }
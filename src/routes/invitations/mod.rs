@@ -0,0 +1,126 @@
+//! Where invited users set their password, from the link they were emailed.
+use actix_web::{
+    get,
+    http::header::ContentType,
+    post,
+    web::{Data, Form, Query},
+    HttpRequest, HttpResponse,
+};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    authentication::{
+        accept_invitation, change_password, compute_password_hash, get_pending_invitation,
+        validate_new_password, PasswordHashing,
+    },
+    session_state::TypedSession,
+    templates::{flash_message_views, Templates},
+    utils::{e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct InvitationParameters {
+    token: Secret<String>,
+}
+
+#[get("/invitations/accept")]
+pub async fn invitation_form(
+    parameters: Query<InvitationParameters>,
+    pool: Data<PgPool>,
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+    templates: Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let username = match get_pending_invitation(&parameters.token, &pool)
+        .await
+        .map_err(e500)?
+    {
+        Some(username) => username,
+        None => {
+            FlashMessage::error("This invitation is invalid or has expired.").send();
+            return Ok(see_other("/login"));
+        }
+    };
+    let mut context = tera::Context::new();
+    context.insert("csrf_token", &session.csrf_token().map_err(e500)?);
+    context.insert("messages", &flash_message_views(&flash_messages));
+    context.insert("username", &username);
+    context.insert("token", parameters.token.expose_secret());
+    let body = templates
+        .render("invitations/accept.html", &context)
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[derive(serde::Deserialize)]
+pub struct InvitationData {
+    token: Secret<String>,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+/// Set the invitee's password. The invitation is checked before the password
+/// is hashed, so that invalid tokens cost no hashing.
+#[tracing::instrument(skip(form, pool, hashing, session, request), fields(user_id = tracing::field::Empty))]
+#[post("/invitations/accept")]
+pub async fn complete_invitation(
+    form: Form<InvitationData>,
+    pool: Data<PgPool>,
+    hashing: Data<PasswordHashing>,
+    session: TypedSession,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let form = form.0;
+    if let Err(message) = validate_new_password(&form.new_password, &form.new_password_check) {
+        FlashMessage::error(message).send();
+        return Ok(see_other(&format!(
+            "/invitations/accept?token={}",
+            form.token.expose_secret()
+        )));
+    }
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let user_id = match accept_invitation(&mut transaction, &form.token)
+        .await
+        .map_err(e500)?
+    {
+        Some(user_id) => user_id,
+        None => {
+            FlashMessage::error("This invitation is invalid or has expired.").send();
+            return Ok(see_other("/login"));
+        }
+    };
+    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+    let password_hash = compute_password_hash(form.new_password, &hashing)
+        .await
+        .map_err(e500)?;
+    change_password(&mut transaction, user_id, password_hash)
+        .await
+        .map_err(e500)?;
+    AuditEvent::new(AuditAction::PasswordChanged, &request)
+        .actor(user_id)
+        .target(user_id)
+        .changes(json!({ "via": "invitation" }))
+        .record(&mut transaction)
+        .await
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the invitation")
+        .map_err(e500)?;
+    // Whoever was logged in on this browser, it is the invitee's turn now.
+    session.log_out();
+    FlashMessage::info("Your account is ready, you can now log in.").send();
+    Ok(see_other("/login"))
+}
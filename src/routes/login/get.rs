@@ -1,48 +1,29 @@
-use actix_web::{cookie::Cookie, get, http::header::ContentType, HttpResponse};
-use actix_web_flash_messages::{IncomingFlashMessages, Level};
-use std::fmt::Write;
+use actix_web::{cookie::Cookie, get, http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
 
-#[get("/login")]
-pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
-    let mut error_html = String::new();
-    for m in flash_messages.iter().filter(|m| m.level() == Level::Error) {
-        writeln!(error_html, "<p><i>{}</i></p>", m.content()).unwrap();
-    }
+use crate::{
+    session_state::TypedSession,
+    templates::{flash_message_views, Templates},
+    utils::e500,
+};
 
+#[get("/login")]
+pub async fn login_form(
+    flash_messages: IncomingFlashMessages,
+    session: TypedSession,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut context = tera::Context::new();
+    context.insert("csrf_token", &session.csrf_token().map_err(e500)?);
+    context.insert("messages", &flash_message_views(&flash_messages));
+    let body = templates
+        .render("login/login.html", &context)
+        .map_err(e500)?;
     let mut response = HttpResponse::Ok()
         .content_type(ContentType::html())
-        // .cookie(Cookie::build("_flash", "").max_age(Duration::ZERO).finish())
-        .body(format!(
-            r#"<!DOCTYPE html>
-        <html lang="en">
-        <head>
-        <meta http-equiv="content-type" content="text/html; charset=utf-8">
-        <title>Login</title>
-        </head>
-        <body>
-        {error_html}
-        <form action="/login" method="post">
-        <label>Username
-        <input
-        type="text"
-        placeholder="Enter Username"
-        name="username"
->
-</label>
-<label>Password
-<input
-type="password"
-placeholder="Enter Password"
-name="password"
->
-</label>
-<button type="submit">Login</button>
-</form>
-</body>
-</html>"#,
-        ));
+        .body(body);
     response
         .add_removal_cookie(&Cookie::new("_flash", ""))
         .unwrap();
-    response
+    Ok(response)
 }
@@ -0,0 +1,249 @@
+use actix_web::{
+    get,
+    http::header::ContentType,
+    post,
+    web::{Data, Form, Query},
+    HttpRequest, HttpResponse,
+};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    authentication::{
+        change_password, compute_password_hash, consume_password_reset_token,
+        create_password_reset_token, find_resettable_account, is_password_reset_token_valid,
+        validate_new_password, PasswordHashing,
+    },
+    configuration::PasswordResetSettings,
+    domain::SubscriberEmail,
+    email_outbox::{enqueue_email, OutgoingEmail},
+    rate_limit::RateLimiter,
+    session_state::TypedSession,
+    startup::ApplicationBaseUrl,
+    templates::{flash_message_views, Templates},
+    utils::{client_ip, e500, see_other},
+};
+
+#[get("/login/forgot_password")]
+pub async fn forgot_password_form(
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+    templates: Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut context = tera::Context::new();
+    context.insert("csrf_token", &session.csrf_token().map_err(e500)?);
+    context.insert("messages", &flash_message_views(&flash_messages));
+    let body = templates
+        .render("login/forgot_password.html", &context)
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ForgotPasswordData {
+    username: String,
+}
+
+/// Email a reset link to the account, if there is one. The response is the
+/// same either way, so that it cannot be used to probe for usernames.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    skip(form, pool, base_url, settings, rate_limiter, templates, request),
+    fields(username = %form.username)
+)]
+#[post("/login/forgot_password")]
+pub async fn forgot_password(
+    form: Form<ForgotPasswordData>,
+    pool: Data<PgPool>,
+    base_url: Data<ApplicationBaseUrl>,
+    settings: Data<PasswordResetSettings>,
+    rate_limiter: Data<RateLimiter>,
+    templates: Data<Templates>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let ip = client_ip(&request);
+    if let Some(reason) = rate_limiter
+        .check_password_reset_from(&ip)
+        .await
+        .context("Failed to check the password reset rate limits")
+        .map_err(e500)?
+    {
+        tracing::warn!(%reason, %ip, "Rejected a password reset");
+        FlashMessage::error("Too many password reset requests, please try again later.").send();
+        return Ok(see_other("/login/forgot_password"));
+    }
+    match find_resettable_account(&form.username, &pool)
+        .await
+        .map_err(e500)?
+    {
+        Some(account) => {
+            let recipient = SubscriberEmail::parse(account.email)
+                .context("The account's email is invalid")
+                .map_err(e500)?;
+            if let Some(reason) = rate_limiter
+                .check_password_reset_for(recipient.as_ref())
+                .await
+                .context("Failed to check the password reset rate limits")
+                .map_err(e500)?
+            {
+                // Answered as usual: the limit must not reveal the account.
+                tracing::warn!(%reason, "Dropped a password reset");
+                return Ok(reset_link_sent());
+            }
+            let mut transaction = pool
+                .begin()
+                .await
+                .context("Failed to acquire a Postgres connection from the pool")
+                .map_err(e500)?;
+            let token = create_password_reset_token(
+                &mut transaction,
+                account.user_id,
+                settings.token_validity(),
+            )
+            .await
+            .map_err(e500)?;
+            let reset_link = format!(
+                "{}/login/reset_password?token={}",
+                base_url.as_ref(),
+                token.expose_secret()
+            );
+            let mut context = tera::Context::new();
+            context.insert("reset_link", &reset_link);
+            context.insert("minutes", &settings.token_validity_minutes);
+            let html_body = templates
+                .render("emails/password_reset.html", &context)
+                .map_err(e500)?;
+            let text_body = templates
+                .render("emails/password_reset.txt", &context)
+                .map_err(e500)?;
+            enqueue_email(
+                &mut transaction,
+                OutgoingEmail {
+                    recipient: &recipient,
+                    subject: "Reset your password",
+                    html_body: &html_body,
+                    text_body: &text_body,
+                },
+            )
+            .await
+            .context("Failed to enqueue the password reset email")
+            .map_err(e500)?;
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit the password reset")
+                .map_err(e500)?;
+        }
+        None => tracing::info!("Password reset asked for an unknown account"),
+    }
+    Ok(reset_link_sent())
+}
+
+fn reset_link_sent() -> HttpResponse {
+    FlashMessage::info(
+        "If this account exists, a link to reset its password has been sent to its email address.",
+    )
+    .send();
+    see_other("/login/forgot_password")
+}
+
+#[derive(serde::Deserialize)]
+pub struct ResetPasswordParameters {
+    token: Secret<String>,
+}
+
+#[get("/login/reset_password")]
+pub async fn reset_password_form(
+    parameters: Query<ResetPasswordParameters>,
+    pool: Data<PgPool>,
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+    templates: Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !is_password_reset_token_valid(&parameters.token, &pool)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::error("This password reset link is invalid or has expired.").send();
+        return Ok(see_other("/login/forgot_password"));
+    }
+    let mut context = tera::Context::new();
+    context.insert("csrf_token", &session.csrf_token().map_err(e500)?);
+    context.insert("messages", &flash_message_views(&flash_messages));
+    context.insert("token", parameters.token.expose_secret());
+    let body = templates
+        .render("login/reset_password.html", &context)
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ResetPasswordData {
+    token: Secret<String>,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+/// Set the new password, then log the user out everywhere. The token is
+/// checked before the password is hashed, so that invalid links cost no hashing.
+#[tracing::instrument(skip(form, pool, hashing, session, request), fields(user_id = tracing::field::Empty))]
+#[post("/login/reset_password")]
+pub async fn reset_password(
+    form: Form<ResetPasswordData>,
+    pool: Data<PgPool>,
+    hashing: Data<PasswordHashing>,
+    session: TypedSession,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let form = form.0;
+    let retry_location = format!("/login/reset_password?token={}", form.token.expose_secret());
+    if let Err(message) = validate_new_password(&form.new_password, &form.new_password_check) {
+        FlashMessage::error(message).send();
+        return Ok(see_other(&retry_location));
+    }
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let user_id = match consume_password_reset_token(&mut transaction, &form.token)
+        .await
+        .map_err(e500)?
+    {
+        Some(user_id) => user_id,
+        None => {
+            FlashMessage::error("This password reset link is invalid or has expired.").send();
+            return Ok(see_other("/login/forgot_password"));
+        }
+    };
+    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+    let password_hash = compute_password_hash(form.new_password, &hashing)
+        .await
+        .map_err(e500)?;
+    change_password(&mut transaction, user_id, password_hash)
+        .await
+        .map_err(e500)?;
+    AuditEvent::new(AuditAction::PasswordChanged, &request)
+        .actor(user_id)
+        .target(user_id)
+        .changes(json!({ "via": "reset" }))
+        .record(&mut transaction)
+        .await
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the password reset")
+        .map_err(e500)?;
+    session.log_out();
+    FlashMessage::info("Your password has been reset, you can now log in.").send();
+    Ok(see_other("/login"))
+}
@@ -1,17 +1,24 @@
 use actix_web::{
     error::InternalError,
-    http::header::LOCATION,
     post,
     web::{Data, Form},
-    HttpResponse,
+    HttpRequest, HttpResponse,
 };
 use actix_web_flash_messages::FlashMessage;
 use secrecy::Secret;
+use serde_json::json;
 use sqlx::PgPool;
 
 use crate::{
-    authentication::{validate_credentials, AuthError, Credentials},
+    audit::{AuditAction, AuditEvent},
+    authentication::{
+        get_two_factor_state, record_user_session, validate_credentials_throttled, AuthError,
+        Credentials, PasswordHashing,
+    },
+    rate_limit::RateLimiter,
     routes::error_chain_fmt,
+    session_state::TypedSession,
+    utils::{client_ip, see_other},
 };
 
 #[derive(serde::Deserialize)]
@@ -20,38 +27,82 @@ pub struct FormData {
     password: Secret<String>,
 }
 
-#[tracing::instrument(skip(form, pool), fields(username=tracing::field::Empty, user_id=tracing::field::Empty))]
+#[tracing::instrument(skip(form, pool, rate_limiter, hashing, session, request), fields(username=tracing::field::Empty, user_id=tracing::field::Empty))]
 #[post("/login")]
 pub async fn login(
     form: Form<FormData>,
     pool: Data<PgPool>,
+    rate_limiter: Data<RateLimiter>,
+    hashing: Data<PasswordHashing>,
+    session: TypedSession,
+    request: HttpRequest,
 ) -> Result<HttpResponse, InternalError<LoginError>> {
     let credentials = Credentials {
         username: form.0.username,
         password: form.0.password,
     };
     tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
-    match validate_credentials(credentials, &pool).await {
+    let username = credentials.username.clone();
+    match validate_credentials_throttled(
+        credentials,
+        &client_ip(&request),
+        &pool,
+        &rate_limiter,
+        &hashing,
+    )
+    .await
+    {
         Ok(user_id) => {
             tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
-            Ok(HttpResponse::SeeOther()
-                .insert_header((LOCATION, "/"))
-                .finish())
+            session.renew();
+            let two_factor = get_two_factor_state(user_id, &pool)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            if two_factor.enabled {
+                // The session only becomes an admin one after the second factor.
+                session
+                    .insert_pending_user_id(user_id)
+                    .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+                return Ok(see_other("/login/two_factor"));
+            }
+            let session_id = record_user_session(user_id, &request, &pool)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            AuditEvent::new(AuditAction::LoginSucceeded, &request)
+                .actor(user_id)
+                .record(pool.get_ref())
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            session
+                .insert_user_id(user_id, session_id)
+                .and_then(|_| session.insert_two_factor_enrolled(false))
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+            Ok(see_other("/admin/dashboard"))
         }
         Err(e) => {
+            if let AuthError::InvalidCredentials(reason) = &e {
+                AuditEvent::new(AuditAction::LoginFailed, &request)
+                    .attempted_username(&username)
+                    .changes(json!({ "reason": reason.to_string() }))
+                    .record(pool.get_ref())
+                    .await
+                    .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            }
             let e = match e {
                 AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
                 AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
             };
-            FlashMessage::error(e.to_string()).send();
-            let response = HttpResponse::SeeOther()
-                .insert_header((LOCATION, "/login"))
-                .finish();
-            Err(InternalError::from_response(e, response))
+            Err(login_redirect(e))
         }
     }
 }
 
+/// Back to the login form, with the error as a flash message.
+pub fn login_redirect(e: LoginError) -> InternalError<LoginError> {
+    FlashMessage::error(e.to_string()).send();
+    InternalError::from_response(e, see_other("/login"))
+}
+
 #[derive(thiserror::Error)]
 pub enum LoginError {
     #[error("Authentication login Failed")]
@@ -0,0 +1,128 @@
+use actix_web::{
+    error::InternalError,
+    get,
+    http::header::ContentType,
+    post,
+    web::{Data, Form},
+    HttpRequest, HttpResponse,
+};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use secrecy::{ExposeSecret, Secret};
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    authentication::{record_user_session, verify_second_factor},
+    rate_limit::RateLimiter,
+    session_state::TypedSession,
+    templates::{flash_message_views, Templates},
+    utils::{e500, see_other},
+};
+
+use super::post::{login_redirect, LoginError};
+
+#[get("/login/two_factor")]
+pub async fn two_factor_form(
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+    templates: Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_pending_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+    let mut context = tera::Context::new();
+    context.insert("csrf_token", &session.csrf_token().map_err(e500)?);
+    context.insert("messages", &flash_message_views(&flash_messages));
+    let body = templates
+        .render("login/two_factor.html", &context)
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    code: Secret<String>,
+}
+
+#[tracing::instrument(
+    skip(form, pool, rate_limiter, session, request),
+    fields(user_id=tracing::field::Empty)
+)]
+#[post("/login/two_factor")]
+pub async fn two_factor_login(
+    form: Form<FormData>,
+    pool: Data<PgPool>,
+    rate_limiter: Data<RateLimiter>,
+    session: TypedSession,
+    request: HttpRequest,
+) -> Result<HttpResponse, InternalError<LoginError>> {
+    let unexpected = |e: anyhow::Error| login_redirect(LoginError::UnexpectedError(e));
+    let user_id = match session
+        .get_pending_user_id()
+        .map_err(|e| unexpected(e.into()))?
+    {
+        Some(user_id) => user_id,
+        None => return Ok(see_other("/login")),
+    };
+    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+    if rate_limiter
+        .is_two_factor_locked_out(user_id)
+        .await
+        .map_err(|e| unexpected(e.into()))?
+    {
+        return Err(too_many_codes(session));
+    }
+    if verify_second_factor(user_id, form.code.expose_secret(), &pool)
+        .await
+        .map_err(unexpected)?
+    {
+        rate_limiter
+            .record_two_factor_success(user_id)
+            .await
+            .map_err(|e| unexpected(e.into()))?;
+        let session_id = record_user_session(user_id, &request, &pool)
+            .await
+            .map_err(unexpected)?;
+        AuditEvent::new(AuditAction::LoginSucceeded, &request)
+            .actor(user_id)
+            .changes(json!({ "two_factor": true }))
+            .record(pool.get_ref())
+            .await
+            .map_err(unexpected)?;
+        session.renew();
+        session.remove_pending_user_id();
+        session
+            .insert_user_id(user_id, session_id)
+            .and_then(|_| session.insert_two_factor_enrolled(true))
+            .map_err(|e| unexpected(e.into()))?;
+        return Ok(see_other("/admin/dashboard"));
+    }
+
+    let locked_out = rate_limiter
+        .record_two_factor_failure(user_id)
+        .await
+        .map_err(|e| unexpected(e.into()))?;
+    tracing::warn!(locked_out, "Invalid second factor");
+    AuditEvent::new(AuditAction::LoginFailed, &request)
+        .actor(user_id)
+        .changes(json!({ "reason": "Invalid authentication code.", "locked_out": locked_out }))
+        .record(pool.get_ref())
+        .await
+        .map_err(unexpected)?;
+    if locked_out {
+        return Err(too_many_codes(session));
+    }
+    FlashMessage::error("Invalid authentication code.").send();
+    Ok(see_other("/login/two_factor"))
+}
+
+/// The password has to be entered again, and is not enough until the lockout ends.
+fn too_many_codes(session: TypedSession) -> InternalError<LoginError> {
+    session.log_out();
+    login_redirect(LoginError::AuthError(anyhow::anyhow!(
+        "Too many invalid authentication codes."
+    )))
+}
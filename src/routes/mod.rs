@@ -1,13 +1,21 @@
+pub mod admin;
+pub mod api;
 pub mod health_check;
 pub mod home;
+pub mod invitations;
+pub mod login;
 pub mod newsletters;
+pub mod problem;
 pub mod subscriptions;
 pub mod subscriptions_confirm;
-pub mod login;
+pub mod widget;
 
 pub use health_check::*;
 pub use home::*;
+pub use invitations::*;
+pub use login::*;
 pub use newsletters::*;
+pub use problem::Problem;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
-pub use login::*;
\ No newline at end of file
+pub use widget::*;
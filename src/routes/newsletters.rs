@@ -1,28 +1,37 @@
 use actix_web::{
-    http::{
-        header::{HeaderMap, HeaderValue},
-        StatusCode,
-    },
     post,
     web::{Data, Json},
-    HttpRequest, HttpResponse, ResponseError,
+    HttpRequest, HttpResponse,
 };
-use reqwest::header;
+use serde_json::json;
 use sqlx::PgPool;
+use std::collections::BTreeMap;
 
 use crate::{
-    authentication::{AuthError, Credentials, validate_credentials},
+    audit::{AuditAction, AuditEvent},
+    authentication::{ApiScope, PasswordHashing},
     domain::SubscriberEmail,
-    email_client::EmailClient,
+    email_outbox::{enqueue_email, OutgoingEmail},
+    i18n::{I18n, Locale},
+    rate_limit::RateLimiter,
 };
 
-use std::str::FromStr;
-
-use super::error_chain_fmt;
+use super::api::{authenticate, ApiError};
 use anyhow::Context;
 
 #[derive(Debug, serde::Deserialize)]
 pub struct BodyData {
+    /// The issue in the default locale, sent to the subscribers whose locale
+    /// has no variant of its own.
+    #[serde(flatten)]
+    default: Variant,
+    /// The other localized variants of the issue, by locale.
+    #[serde(default)]
+    variants: BTreeMap<String, Variant>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct Variant {
     title: String,
     content: Content,
 }
@@ -33,99 +42,135 @@ pub struct Content {
     text: String,
 }
 
-#[derive(thiserror::Error)]
-pub enum PublishError {
-    #[error("Authentication failed.")]
-    AuthError(#[source] anyhow::Error),
-    #[error(transparent)]
-    UnexpectedError(#[from] anyhow::Error),
-}
-
-impl std::fmt::Debug for PublishError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        error_chain_fmt(self, f)
-    }
-}
-
-impl ResponseError for PublishError {
-    fn error_response(&self) -> HttpResponse {
-        match self {
-            PublishError::UnexpectedError(_) => {
-                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
-            }
-            PublishError::AuthError(_) => {
-                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
-                let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
-                response
-                    .headers_mut()
-                    .insert(header::WWW_AUTHENTICATE, header_value);
-                response
-            }
-        }
-    }
+/// How many subscribers each variant of the issue was queued for.
+#[derive(serde::Serialize)]
+pub struct PublicationReport {
+    variants: Vec<VariantReport>,
 }
-fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
-    let header_value = headers
-        .get("Authorization")
-        .context("The Authorization header is missing")?
-        .to_str()
-        .context("The auth header wasn't a valid utf8 string")?;
-    let base64encoded_segment = header_value
-        .strip_prefix("Basic ")
-        .context("The authscheme wasnt Basic")?;
-    let decoded_bytes = base64::decode_config(base64encoded_segment, base64::STANDARD)
-        .context("Failed to decode base64")?;
-    let decoded_credentials =
-        String::from_utf8(decoded_bytes).context("the decoded wasn't a valid utf8")?;
 
-    Credentials::from_str(&decoded_credentials)
+#[derive(serde::Serialize)]
+struct VariantReport {
+    locale: Locale,
+    title: String,
+    recipients: usize,
 }
 
 #[post("/newsletters")]
 #[tracing::instrument(
     name = "Publish a newsletter issue",
-    skip(body, pool, email_client, request),
-    fields(username, user_id)
+    skip(body, pool, rate_limiter, hashing, i18n, request),
+    fields(user_id)
 )]
 pub async fn publish_newsletter(
     body: Json<BodyData>,
     pool: Data<PgPool>,
-    email_client: Data<EmailClient>,
+    rate_limiter: Data<RateLimiter>,
+    hashing: Data<PasswordHashing>,
+    i18n: Data<I18n>,
     request: HttpRequest,
-) -> Result<HttpResponse, PublishError> {
-    let credentials = basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
-    let user_id = validate_credentials(credentials, &pool)
-        .await
-        .map_err(|e| match e {
-            AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
-            AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
-        })?;
+) -> Result<HttpResponse, ApiError> {
+    let user_id = authenticate(
+        &request,
+        &pool,
+        &rate_limiter,
+        &hashing,
+        ApiScope::NewslettersPublish,
+    )
+    .await?;
     tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+    let variants = localized_variants(body.into_inner(), i18n.default_locale())?;
     let subscribers = get_confirmed_subscribers(&pool).await?;
+    let locales: Vec<Locale> = variants.iter().map(|(locale, _)| *locale).collect();
+
+    // The issue is queued in the outbox: a delivery failure is retried by
+    // the outbox worker instead of aborting the publication halfway through.
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let mut recipients = vec![0; variants.len()];
     for subscriber in subscribers {
         match subscriber {
-            Ok(subscriber) => email_client
-                .send_mail(
-                    &subscriber.email,
-                    &body.title,
-                    &body.content.html,
-                    &body.content.text,
+            Ok(subscriber) => {
+                let locale = i18n.stored_locale(&subscriber.locale);
+                let index = locales.iter().position(|l| *l == locale).unwrap_or(0);
+                let variant = &variants[index].1;
+                enqueue_email(
+                    &mut transaction,
+                    OutgoingEmail {
+                        recipient: &subscriber.email,
+                        subject: &variant.title,
+                        html_body: &variant.content.html,
+                        text_body: &variant.content.text,
+                    },
                 )
                 .await
                 .with_context(|| {
-                    format!("FAiled to send neews letter issu to {}", subscriber.email)
-                })?,
+                    format!("Failed to queue newsletter issue for {}", subscriber.email)
+                })?;
+                recipients[index] += 1;
+            }
             Err(error) => {
                 tracing::warn!(error.cause_chain=?error, "Error with valid invalid mail stored.")
             }
         }
     }
-    Ok(HttpResponse::Ok().finish())
+    AuditEvent::new(AuditAction::NewsletterPublished, &request)
+        .actor(user_id)
+        .target(&variants[0].1.title)
+        .changes(json!({ "recipients": recipients.iter().sum::<usize>(), "variants": locales }))
+        .record(&mut transaction)
+        .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to publish a newsletter issue")?;
+
+    let report = PublicationReport {
+        variants: variants
+            .into_iter()
+            .zip(recipients)
+            .map(|((locale, variant), recipients)| VariantReport {
+                locale,
+                title: variant.title,
+                recipients,
+            })
+            .collect(),
+    };
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// Every variant of the issue with its locale, the default one first.
+fn localized_variants(
+    body: BodyData,
+    default_locale: Locale,
+) -> Result<Vec<(Locale, Variant)>, ApiError> {
+    let mut variants = vec![(default_locale, body.default)];
+    for (locale, variant) in body.variants {
+        let locale = locale
+            .parse::<Locale>()
+            .map_err(|e| ApiError::InvalidParameter(format!("variants: {}.", e)))?;
+        if locale == default_locale {
+            return Err(ApiError::InvalidParameter(format!(
+                "variants: `{}` is the default locale, its variant is the top-level \
+                `title` and `content`.",
+                locale
+            )));
+        }
+        if variants.iter().any(|(l, _)| *l == locale) {
+            return Err(ApiError::InvalidParameter(format!(
+                "variants: `{}` has more than one variant.",
+                locale
+            )));
+        }
+        variants.push((locale, variant));
+    }
+    Ok(variants)
 }
 
 struct ConfirmedSubscriber {
     email: SubscriberEmail,
+    locale: String,
 }
 
 #[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
@@ -134,7 +179,7 @@ async fn get_confirmed_subscribers(
 ) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
     let confirmed = sqlx::query!(
         r#"
-        SELECT email
+        SELECT email, locale
         FROM subscriptions
         WHERE status = 'confirmed'
         "#
@@ -143,7 +188,10 @@ async fn get_confirmed_subscribers(
     .await?
     .into_iter()
     .map(|r| match SubscriberEmail::parse(r.email) {
-        Ok(email) => Ok(ConfirmedSubscriber { email }),
+        Ok(email) => Ok(ConfirmedSubscriber {
+            email,
+            locale: r.locale,
+        }),
         Err(error) => Err(anyhow::anyhow!(error)),
     })
     .collect();
@@ -0,0 +1,109 @@
+use std::future::Future;
+
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse},
+    error::InternalError,
+    http::StatusCode,
+    HttpMessage, HttpRequest, HttpResponse,
+};
+use serde::Serialize;
+use tracing_actix_web::RequestId;
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+tokio::task_local! {
+    static REQUEST_ID: Option<String>;
+}
+
+/// Make the id of the current request available to the error responses, which
+/// are built without access to the request itself.
+pub fn scope_request_id<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let request_id = req.extensions().get::<RequestId>().map(|id| id.to_string());
+    REQUEST_ID.scope(request_id, srv.call(req))
+}
+
+/// An RFC 7807 `application/problem+json` error body.
+///
+/// `detail` is always written for the client: internal error chains are only
+/// meant for the logs and must never end up in there.
+#[derive(Debug, Serialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    problem_type: String,
+    title: String,
+    status: u16,
+    detail: String,
+    request_id: Option<String>,
+    #[serde(flatten)]
+    extensions: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Problem {
+    /// `slug` identifies the kind of problem, as the last segment of its type URI.
+    pub fn new(status: StatusCode, slug: &str, title: &str, detail: impl Into<String>) -> Self {
+        Self {
+            problem_type: format!("/problems/{}", slug),
+            title: title.to_string(),
+            status: status.as_u16(),
+            detail: detail.into(),
+            request_id: REQUEST_ID.try_with(|id| id.clone()).ok().flatten(),
+            extensions: serde_json::Map::new(),
+        }
+    }
+
+    pub fn internal_error() -> Self {
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal-error",
+            "Internal server error",
+            "Something went wrong on our side, please try again later.",
+        )
+    }
+
+    pub fn with_extension(mut self, key: &str, value: impl Serialize) -> Self {
+        self.extensions.insert(
+            key.to_string(),
+            serde_json::to_value(value).expect("Failed to serialize a problem extension"),
+        );
+        self
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn detail(&self) -> &str {
+        &self.detail
+    }
+
+    pub fn status(&self) -> StatusCode {
+        StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    pub fn response(&self) -> HttpResponse {
+        HttpResponse::build(self.status())
+            .content_type(PROBLEM_JSON)
+            .json(self)
+    }
+}
+
+/// Used for the errors of the `Form`, `Json` and `Query` extractors.
+pub fn payload_error_handler<E: std::fmt::Display + std::fmt::Debug + 'static>(
+    error: E,
+    _: &HttpRequest,
+) -> actix_web::Error {
+    let response = Problem::new(
+        StatusCode::BAD_REQUEST,
+        "invalid-payload",
+        "Invalid request payload",
+        error.to_string(),
+    )
+    .response();
+    InternalError::from_response(error, response).into()
+}
@@ -1,183 +0,0 @@
-use std::fmt::Display;
-
-use actix_web::{post, web, HttpResponse, ResponseError};
-use anyhow::Context;
-use rand::Rng;
-use reqwest::StatusCode;
-use sqlx::{query, PgPool, Postgres, Transaction};
-use uuid::Uuid;
-
-use crate::{domain::NewSubscriber, email_client::EmailClient, startup::ApplicationBaseUrl};
-#[derive(serde::Deserialize, Debug)]
-pub struct FormData {
-    pub name: String,
-    pub email: String,
-}
-#[tracing::instrument(name = "Adding new subscriber")]
-#[post("/subscriptions")]
-async fn subscribe(
-    form: web::Form<FormData>,
-    pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
-    base_url: web::Data<ApplicationBaseUrl>,
-) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
-    let mut transaction = pool
-        .begin()
-        .await
-        .context("Aquisition de la transaction a échouée")?;
-    let subscriber_id = insert_subscriber(&new_subscriber, &mut transaction)
-        .await
-        .context("insert subscriber in db failed")?;
-    let subscription_token = generate_subscription_token();
-    store_token(&mut transaction, subscriber_id, &subscription_token)
-        .await
-        .context("store token in db failed")?;
-    transaction
-        .commit()
-        .await
-        .context("transction commiting failed du to some errre")?;
-    send_confirmation_email(
-        &email_client,
-        new_subscriber,
-        &base_url.to_string(),
-        &subscription_token,
-    )
-    .await
-    .context("Et c l'echec du sendmail, aï, aïe, Aïe")?;
-    Ok(HttpResponse::Created().finish())
-}
-
-#[tracing::instrument(skip(email_client, new_subscriber))]
-pub async fn send_confirmation_email(
-    email_client: &EmailClient,
-    new_subscriber: NewSubscriber,
-    base_url: &str,
-    subscription_token: &str,
-) -> Result<(), reqwest::Error> {
-    let confirmation_link = format!(
-        "{}/subscriptions/confirm?subscription_token={}",
-        base_url, subscription_token
-    );
-    let plain_body = format!(
-        "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
-        confirmation_link
-    );
-    let html_body = format!(
-        "Welcome to our newsletter!<br />\
-222Click <a href=\"{}\">here</a> to confirm your subscription.",
-        confirmation_link
-    );
-    email_client
-        .send_mail(&new_subscriber.email, "Welcome!", &html_body, &plain_body)
-        .await
-}
-
-#[tracing::instrument]
-async fn insert_subscriber(
-    new_sub: &NewSubscriber,
-    transaction: &mut Transaction<'_, Postgres>,
-) -> Result<Uuid, sqlx::Error> {
-    let subscriber_id = Uuid::new_v4();
-    query!(
-        r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-        VALUES ($1, $2, $3, $4, 'pending_confirmation')
-        "#,
-        subscriber_id,
-        new_sub.email.as_ref(),
-        new_sub.name.as_ref(),
-        chrono::Utc::now()
-    )
-    .execute(transaction)
-    .await?;
-    Ok(subscriber_id)
-}
-
-fn generate_subscription_token() -> String {
-    let mut rng = rand::thread_rng();
-    std::iter::repeat_with(|| rng.sample(rand::distributions::Alphanumeric))
-        .map(char::from)
-        .take(25)
-        .collect()
-}
-
-#[tracing::instrument(
-    name = "Store subscription token in the database",
-    skip(subscription_token, transaction)
-)]
-pub async fn store_token(
-    transaction: &mut Transaction<'_, Postgres>,
-    subscriber_id: Uuid,
-    subscription_token: &str,
-) -> Result<(), StoreTokenError> {
-    sqlx::query!(
-        r#"INSERT INTO subscription_tokens (subscription_token, subscription_id)
-VALUES ($1, $2)"#,
-        subscription_token,
-        subscriber_id
-    )
-    .execute(transaction)
-    .await
-    .map_err(|e| StoreTokenError(e))?;
-    Ok(())
-}
-
-pub struct StoreTokenError(sqlx::Error);
-
-impl ResponseError for StoreTokenError {}
-
-impl Display for StoreTokenError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Erreur lors de la création d'un token dans la db")
-    }
-}
-
-impl std::error::Error for StoreTokenError {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        Some(&self.0)
-    }
-}
-
-impl std::fmt::Debug for StoreTokenError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        error_chain_fmt(self, f)
-    }
-}
-
-pub fn error_chain_fmt(
-    e: &impl std::error::Error,
-    f: &mut std::fmt::Formatter<'_>,
-) -> std::fmt::Result {
-    writeln!(f, "{}\n", e)?;
-    let mut current = e.source();
-    while let Some(cause) = current {
-        writeln!(f, "Caused by:\n\t{}", cause)?;
-        current = cause.source();
-    }
-    Ok(())
-}
-
-#[derive(thiserror::Error)]
-pub enum SubscribeError {
-    #[error("{0}")]
-    ValidationError(String),
-
-    #[error(transparent)]
-    UnexpectedError(#[from] anyhow::Error),
-}
-
-impl std::fmt::Debug for SubscribeError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        error_chain_fmt(self, f)
-    }
-}
-
-impl ResponseError for SubscribeError {
-    fn status_code(&self) -> StatusCode {
-        match self {
-            SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
-            SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-        }
-    }
-}
@@ -0,0 +1,62 @@
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Strip the `+tag` from the canonical emails stored before the email policy
+/// asked for it, merging the subscriptions that turn out to be duplicates:
+/// the confirmed one is kept if any, the oldest otherwise.
+#[tracing::instrument(name = "Strip plus tags from canonical emails", skip(pool))]
+pub async fn strip_plus_tags_from_canonical_emails(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    // The regular expression strips the tag the way `SubscriberEmail` does.
+    let duplicates: Vec<Uuid> = sqlx::query_scalar!(
+        r#"
+        SELECT id AS "id!" FROM (
+            SELECT id, row_number() OVER (
+                PARTITION BY regexp_replace(canonical_email, '^([^+@]+)\+[^@]*@', '\1@')
+                ORDER BY status = 'confirmed' DESC, subscribed_at, id
+            ) AS rank
+            FROM subscriptions
+        ) AS ranked
+        WHERE rank > 1
+        "#
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("Failed to look for duplicate subscriptions")?;
+    if !duplicates.is_empty() {
+        tracing::warn!(
+            count = duplicates.len(),
+            "Merging subscriptions that only differ by their `+tag`"
+        );
+    }
+    sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscription_id = ANY($1)",
+        &duplicates
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the tokens of duplicate subscriptions")?;
+    sqlx::query!("DELETE FROM subscriptions WHERE id = ANY($1)", &duplicates)
+        .execute(&mut transaction)
+        .await
+        .context("Failed to delete duplicate subscriptions")?;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET canonical_email = regexp_replace(canonical_email, '^([^+@]+)\+[^@]*@', '\1@')
+        WHERE canonical_email ~ '^[^+@]+\+'
+        "#
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to strip the plus tags from canonical emails")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the canonical emails")?;
+    Ok(())
+}
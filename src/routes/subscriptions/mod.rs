@@ -0,0 +1,457 @@
+use std::fmt::Display;
+
+use actix_web::{get, post, web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use rand::Rng;
+use reqwest::StatusCode;
+use sha3::{Digest, Sha3_256};
+use sqlx::{query, query_as, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+mod canonical_email;
+mod negotiation;
+
+pub use canonical_email::strip_plus_tags_from_canonical_emails;
+pub use negotiation::{ResponseFormat, SubscriptionPayload};
+
+use crate::{
+    domain::{
+        EmailPolicy, NewSubscriber, NewSubscriberError, SubscriberEmailError, SubscriberNameError,
+        SubscriptionSourceError,
+    },
+    email_outbox::{enqueue_email, OutgoingEmail},
+    i18n::{I18n, Locale, Translator},
+    rate_limit::{RateLimitReason, RateLimiter},
+    routes::Problem,
+    signup_challenge::SignupChallenge,
+    startup::ApplicationBaseUrl,
+    templates::Templates,
+    utils::client_ip,
+};
+#[derive(serde::Deserialize, Debug)]
+pub struct FormData {
+    pub name: String,
+    pub email: String,
+    /// Honeypot: hidden from humans by the form, only bots fill it in.
+    #[serde(default)]
+    pub website: String,
+    /// Response to the signup challenge, when one is configured.
+    pub challenge_response: Option<String>,
+    /// Tag of the site the form is embedded on, for attribution.
+    pub source: Option<String>,
+    /// Language the subscriber wants to be written to in, such as `fr`. The
+    /// `Accept-Language` header is used when it is missing or not supported.
+    pub locale: Option<String>,
+}
+
+/// Hand out a challenge to solve before posting the subscription form.
+#[get("/challenge")]
+pub async fn get_signup_challenge(challenge: web::Data<dyn SignupChallenge>) -> HttpResponse {
+    match challenge.new_challenge() {
+        Some(challenge) => HttpResponse::Ok().json(challenge),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Adding new subscriber",
+    skip(request, rate_limiter, challenge, email_policy, templates, i18n)
+)]
+#[post("")]
+async fn subscribe(
+    payload: SubscriptionPayload,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    rate_limiter: web::Data<RateLimiter>,
+    challenge: web::Data<dyn SignupChallenge>,
+    email_policy: web::Data<EmailPolicy>,
+    templates: web::Data<Templates>,
+    i18n: web::Data<I18n>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let format = payload.format;
+    let locale = payload
+        .form
+        .locale
+        .as_deref()
+        .and_then(Locale::negotiate)
+        .unwrap_or_else(|| i18n.request_locale(&request));
+    let translator = i18n.translator(locale);
+    match add_subscriber(
+        payload.form,
+        &pool,
+        &base_url,
+        &rate_limiter,
+        challenge.as_ref(),
+        &email_policy,
+        &templates,
+        translator,
+        &request,
+    )
+    .await
+    {
+        Ok(()) => format.created(&templates, translator),
+        Err(e) => Err(format.error(e, &templates, translator)),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn add_subscriber(
+    form: FormData,
+    pool: &PgPool,
+    base_url: &ApplicationBaseUrl,
+    rate_limiter: &RateLimiter,
+    challenge: &dyn SignupChallenge,
+    email_policy: &EmailPolicy,
+    templates: &Templates,
+    translator: Translator<'_>,
+    request: &HttpRequest,
+) -> Result<(), SubscribeError> {
+    if !form.website.is_empty() {
+        tracing::warn!(reason = "honeypot field filled", "Rejected a subscription");
+        // Bots must not be able to tell they've been caught.
+        return Ok(());
+    }
+    let challenge_response = form.challenge_response.clone();
+    let new_subscriber =
+        NewSubscriber::parse(form, email_policy).map_err(SubscribeError::ValidationError)?;
+    let ip = client_ip(request);
+    if !challenge
+        .verify(challenge_response.as_deref(), &ip)
+        .await
+        .context("Failed to verify the signup challenge")?
+    {
+        tracing::warn!(reason = "signup challenge failed", %ip, "Rejected a subscription");
+        return Err(SubscribeError::ChallengeFailed);
+    }
+    if let Some(reason) = rate_limiter
+        .check_subscription(&ip, new_subscriber.email.canonical())
+        .await
+        .context("Failed to check the subscription rate limits")?
+    {
+        tracing::warn!(%reason, %ip, "Rejected a subscription");
+        return Err(SubscribeError::RateLimited(reason));
+    }
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let subscriber_id = match existing_subscription(&new_subscriber, &mut transaction)
+        .await
+        .context("Failed to look for an existing subscription")?
+    {
+        Some(existing) if existing.status != "pending_confirmation" => {
+            // Answered as a new subscription: the response must not tell
+            // whether an address is on the list.
+            tracing::info!("The address is already subscribed");
+            return Ok(());
+        }
+        // Subscribing again is how a lost or expired confirmation link is
+        // replaced: the subscriber gets a new one.
+        Some(existing) => existing.id,
+        None => insert_subscriber(&new_subscriber, translator.locale(), &mut transaction)
+            .await
+            .context("Failed to insert the new subscriber in the database")?,
+    };
+    if let Some(reason) = rate_limiter
+        .check_confirmation_email()
+        .await
+        .context("Failed to check the confirmation email ceiling")?
+    {
+        tracing::warn!(%reason, %ip, "Rejected a subscription");
+        return Err(SubscribeError::RateLimited(reason));
+    }
+    let subscription_token = generate_subscription_token();
+    store_token(&mut transaction, subscriber_id, &subscription_token)
+        .await
+        .context("Failed to store the confirmation token for a new subscriber")?;
+    enqueue_confirmation_email(
+        &mut transaction,
+        &new_subscriber,
+        &base_url.to_string(),
+        &subscription_token,
+        templates,
+        translator,
+    )
+    .await
+    .context("Failed to store the confirmation email in the outbox")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the new subscriber")?;
+    Ok(())
+}
+
+#[tracing::instrument(skip(
+    transaction,
+    new_subscriber,
+    subscription_token,
+    templates,
+    translator
+))]
+pub async fn enqueue_confirmation_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    new_subscriber: &NewSubscriber,
+    base_url: &str,
+    subscription_token: &str,
+    templates: &Templates,
+    translator: Translator<'_>,
+) -> Result<(), anyhow::Error> {
+    let confirmation_link = format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        base_url, subscription_token
+    );
+    let mut context = translator.context();
+    context.insert("confirmation_link", &confirmation_link);
+    let html_body = templates.render("emails/confirmation.html", &context)?;
+    let text_body = templates.render("emails/confirmation.txt", &context)?;
+    enqueue_email(
+        transaction,
+        OutgoingEmail {
+            recipient: &new_subscriber.email,
+            subject: &translator.t("emails.confirmation.subject"),
+            html_body: &html_body,
+            text_body: &text_body,
+        },
+    )
+    .await?;
+    Ok(())
+}
+
+struct ExistingSubscription {
+    id: Uuid,
+    status: String,
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn existing_subscription(
+    new_sub: &NewSubscriber,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Option<ExistingSubscription>, sqlx::Error> {
+    query_as!(
+        ExistingSubscription,
+        r#"SELECT id, status FROM subscriptions WHERE canonical_email = $1"#,
+        new_sub.email.canonical(),
+    )
+    .fetch_optional(transaction)
+    .await
+}
+
+#[tracing::instrument]
+async fn insert_subscriber(
+    new_sub: &NewSubscriber,
+    locale: Locale,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Uuid, sqlx::Error> {
+    let subscriber_id = Uuid::new_v4();
+    query!(
+        r#"
+        INSERT INTO subscriptions
+            (id, email, canonical_email, name, subscribed_at, status, source, locale)
+        VALUES ($1, $2, $3, $4, $5, 'pending_confirmation', $6, $7)
+        "#,
+        subscriber_id,
+        new_sub.email.as_ref(),
+        new_sub.email.canonical(),
+        new_sub.name.as_ref(),
+        chrono::Utc::now(),
+        new_sub.source.as_ref().map(|s| s.as_ref()),
+        locale.as_str(),
+    )
+    .execute(transaction)
+    .await?;
+    Ok(subscriber_id)
+}
+
+/// Tokens are only stored hashed: the lookup is done on the hash, so neither
+/// the database content nor the query timing leaks a usable token.
+pub fn hash_subscription_token(subscription_token: &str) -> String {
+    hex::encode(Sha3_256::digest(subscription_token.as_bytes()))
+}
+
+fn generate_subscription_token() -> String {
+    let mut rng = rand::thread_rng();
+    std::iter::repeat_with(|| rng.sample(rand::distributions::Alphanumeric))
+        .map(char::from)
+        .take(25)
+        .collect()
+}
+
+#[tracing::instrument(
+    name = "Store subscription token in the database",
+    skip(subscription_token, transaction)
+)]
+pub async fn store_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    subscription_token: &str,
+) -> Result<(), StoreTokenError> {
+    sqlx::query!(
+        r#"INSERT INTO subscription_tokens (subscription_token_hash, subscription_id, created_at)
+VALUES ($1, $2, now())"#,
+        hash_subscription_token(subscription_token),
+        subscriber_id
+    )
+    .execute(transaction)
+    .await
+    .map_err(StoreTokenError)?;
+    Ok(())
+}
+
+pub struct StoreTokenError(sqlx::Error);
+
+impl ResponseError for StoreTokenError {
+    fn error_response(&self) -> HttpResponse {
+        Problem::internal_error().response()
+    }
+}
+
+impl Display for StoreTokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "A database error was encountered while trying to store a subscription token"
+        )
+    }
+}
+
+impl std::error::Error for StoreTokenError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+impl std::fmt::Debug for StoreTokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+pub fn error_chain_fmt(
+    e: &impl std::error::Error,
+    f: &mut std::fmt::Formatter<'_>,
+) -> std::fmt::Result {
+    writeln!(f, "{}\n", e)?;
+    let mut current = e.source();
+    while let Some(cause) = current {
+        writeln!(f, "Caused by:\n\t{}", cause)?;
+        current = cause.source();
+    }
+    Ok(())
+}
+
+#[derive(thiserror::Error)]
+pub enum SubscribeError {
+    #[error("{0}")]
+    ValidationError(NewSubscriberError),
+
+    #[error("The signup challenge failed.")]
+    ChallengeFailed,
+
+    #[error("Subscription rejected: {0}")]
+    RateLimited(RateLimitReason),
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::ChallengeFailed => StatusCode::FORBIDDEN,
+            SubscribeError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl SubscribeError {
+    /// Written in the language of the subscriber.
+    fn problem(&self, translator: Translator<'_>) -> Problem {
+        let (slug, key) = match self {
+            SubscribeError::ValidationError(_) => ("validation-error", "validation"),
+            SubscribeError::ChallengeFailed => ("challenge-failed", "challenge_failed"),
+            SubscribeError::RateLimited(_) => ("rate-limited", "rate_limited"),
+            SubscribeError::UnexpectedError(_) => ("internal-error", "unexpected"),
+        };
+        let problem = Problem::new(
+            self.status_code(),
+            slug,
+            &translator.t(&format!("subscribe_errors.{}.title", key)),
+            translator.t(&format!("subscribe_errors.{}.detail", key)),
+        );
+        match self {
+            SubscribeError::ValidationError(_) => {
+                problem.with_extension("errors", self.field_errors(translator))
+            }
+            _ => problem,
+        }
+    }
+
+    fn field_errors(&self, translator: Translator<'_>) -> Vec<FieldError> {
+        match self {
+            SubscribeError::ValidationError(e) => FieldError::from_validation_error(e, translator),
+            _ => vec![],
+        }
+    }
+}
+
+/// Field-level validation error, for the frontend to map it onto the form.
+#[derive(serde::Serialize)]
+struct FieldError {
+    field: &'static str,
+    code: &'static str,
+    message: String,
+}
+
+impl FieldError {
+    fn new(
+        field: &'static str,
+        code: &'static str,
+        arguments: &[(&str, String)],
+        translator: Translator<'_>,
+    ) -> Self {
+        Self {
+            field,
+            code,
+            message: translator.t_with(&format!("validation.{}.{}", field, code), arguments),
+        }
+    }
+
+    fn from_validation_error(e: &NewSubscriberError, translator: Translator<'_>) -> Vec<Self> {
+        let name = e.name.as_ref().map(|e| {
+            let arguments = match e {
+                SubscriberNameError::Empty => vec![],
+                SubscriberNameError::TooLong { max, actual } => {
+                    vec![("max", max.to_string()), ("actual", actual.to_string())]
+                }
+                SubscriberNameError::ForbiddenCharacter(c) => vec![("character", c.to_string())],
+            };
+            FieldError::new("name", e.code(), &arguments, translator)
+        });
+        let email = e.email.as_ref().map(|e| {
+            let arguments = match e {
+                SubscriberEmailError::DisposableDomain(domain) => vec![("domain", domain.clone())],
+                _ => vec![],
+            };
+            FieldError::new("email", e.code(), &arguments, translator)
+        });
+        let source = e.source.as_ref().map(|e| {
+            let arguments = match e {
+                SubscriptionSourceError::TooLong { max, actual } => {
+                    vec![("max", max.to_string()), ("actual", actual.to_string())]
+                }
+                SubscriptionSourceError::InvalidCharacter => vec![],
+            };
+            FieldError::new("source", e.code(), &arguments, translator)
+        });
+        name.into_iter().chain(email).chain(source).collect()
+    }
+}
@@ -0,0 +1,135 @@
+use std::{future::Future, pin::Pin};
+
+use actix_web::{
+    dev::Payload,
+    error::InternalError,
+    http::header::{self, ContentType},
+    web, FromRequest, HttpRequest, HttpResponse, ResponseError,
+};
+
+use crate::{i18n::Translator, templates::Templates, utils::e500};
+
+use super::{FormData, SubscribeError};
+
+/// How the client expects to be answered, deduced from its request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResponseFormat {
+    /// SPA and mobile clients posting JSON, or asking for it.
+    Json,
+    /// Browsers posting the HTML form.
+    Html,
+    /// Anything else gets the bare status code.
+    Plain,
+}
+
+impl ResponseFormat {
+    fn negotiate(request: &HttpRequest, json_payload: bool) -> Self {
+        let accept = request
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default();
+        if json_payload || accept.contains("application/json") {
+            ResponseFormat::Json
+        } else if accept.contains("text/html") {
+            ResponseFormat::Html
+        } else {
+            ResponseFormat::Plain
+        }
+    }
+
+    pub fn created(
+        self,
+        templates: &Templates,
+        translator: Translator<'_>,
+    ) -> Result<HttpResponse, actix_web::Error> {
+        Ok(match self {
+            ResponseFormat::Json => HttpResponse::Created().json(SubscriptionCreated {
+                status: "pending_confirmation",
+            }),
+            ResponseFormat::Html => HttpResponse::Created()
+                .content_type(ContentType::html())
+                .body(
+                    templates
+                        .render("subscriptions/subscribed.html", &translator.context())
+                        .map_err(e500)?,
+                ),
+            ResponseFormat::Plain => HttpResponse::Created().finish(),
+        })
+    }
+
+    pub fn error(
+        self,
+        e: SubscribeError,
+        templates: &Templates,
+        translator: Translator<'_>,
+    ) -> actix_web::Error {
+        let response = match self {
+            ResponseFormat::Html => error_page(&e, templates, translator),
+            ResponseFormat::Json | ResponseFormat::Plain => e.problem(translator).response(),
+        };
+        InternalError::from_response(e, response).into()
+    }
+}
+
+#[derive(serde::Serialize)]
+struct SubscriptionCreated {
+    status: &'static str,
+}
+
+fn error_page(
+    e: &SubscribeError,
+    templates: &Templates,
+    translator: Translator<'_>,
+) -> HttpResponse {
+    let problem = e.problem(translator);
+    let mut context = translator.context();
+    context.insert("title", problem.title());
+    context.insert("detail", problem.detail());
+    context.insert("errors", &e.field_errors(translator));
+    let body = templates
+        .render("subscriptions/error.html", &context)
+        .unwrap_or_else(|_| problem.detail().to_string());
+    HttpResponse::build(e.status_code())
+        .content_type(ContentType::html())
+        .body(body)
+}
+
+/// The subscription form, either url-encoded or JSON.
+#[derive(Debug)]
+pub struct SubscriptionPayload {
+    pub form: FormData,
+    pub format: ResponseFormat,
+}
+
+impl FromRequest for SubscriptionPayload {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let request = req.clone();
+        let is_json = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|h| h.to_str().ok())
+            .map(|content_type| content_type.starts_with("application/json"))
+            .unwrap_or(false);
+        if is_json {
+            let json = web::Json::<FormData>::from_request(req, payload);
+            Box::pin(async move {
+                Ok(Self {
+                    form: json.await?.into_inner(),
+                    format: ResponseFormat::negotiate(&request, true),
+                })
+            })
+        } else {
+            let form = web::Form::<FormData>::from_request(req, payload);
+            Box::pin(async move {
+                Ok(Self {
+                    form: form.await?.into_inner(),
+                    format: ResponseFormat::negotiate(&request, false),
+                })
+            })
+        }
+    }
+}
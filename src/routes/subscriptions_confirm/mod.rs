@@ -0,0 +1,276 @@
+use actix_web::{
+    error::InternalError, get, http::header::ContentType, http::StatusCode, web, HttpRequest,
+    HttpResponse, ResponseError,
+};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    configuration::SubscriptionsSettings,
+    domain::SubscriberEmail,
+    email_outbox::{enqueue_email, OutgoingEmail},
+    i18n::{I18n, Translator},
+    routes::hash_subscription_token,
+    templates::Templates,
+};
+
+use super::error_chain_fmt;
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    subscription_token: String,
+}
+
+#[tracing::instrument(
+    name = "Confirm a pending subscriber",
+    skip(parameters, pool, settings, templates, i18n, request)
+)]
+#[get("/confirm")]
+pub async fn confirm(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    settings: web::Data<SubscriptionsSettings>,
+    templates: web::Data<Templates>,
+    i18n: web::Data<I18n>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    match confirm_subscription(
+        &parameters.subscription_token,
+        &pool,
+        &settings,
+        &templates,
+        &i18n,
+    )
+    .await
+    {
+        Ok(response) => Ok(response),
+        Err(e) => {
+            // The subscriber is not known for sure, their browser tells the language.
+            let translator = i18n.translator(i18n.request_locale(&request));
+            let response = e.error_page(&templates, translator);
+            Err(InternalError::from_response(e, response).into())
+        }
+    }
+}
+
+async fn confirm_subscription(
+    subscription_token: &str,
+    pool: &PgPool,
+    settings: &SubscriptionsSettings,
+    templates: &Templates,
+    i18n: &I18n,
+) -> Result<HttpResponse, ConfirmError> {
+    let token_hash = hash_subscription_token(subscription_token);
+    let subscriber = get_subscriber_from_token(pool, &token_hash)
+        .await
+        .context("Failed to retrieve the subscriber associated with the token")?
+        .ok_or(ConfirmError::UnknownToken)?;
+    if subscriber.token_consumed_at.is_some() {
+        return Err(ConfirmError::ConsumedToken);
+    }
+    if subscriber.token_created_at + settings.token_validity() < Utc::now() {
+        return Err(ConfirmError::ExpiredToken);
+    }
+    let translator = i18n.translator(i18n.stored_locale(&subscriber.locale));
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    if !consume_token(&mut transaction, &token_hash)
+        .await
+        .context("Failed to mark the token as consumed")?
+    {
+        // Another request used the same token in the meantime.
+        return Err(ConfirmError::ConsumedToken);
+    }
+    let newly_confirmed = confirm_subscriber(&mut transaction, subscriber.id)
+        .await
+        .context("Failed to mark the subscriber as confirmed")?;
+    if newly_confirmed && settings.send_welcome_email {
+        enqueue_welcome_email(&mut transaction, &subscriber, templates, translator)
+            .await
+            .context("Failed to store the welcome email in the outbox")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the subscriber confirmation")?;
+    landing_page(
+        &subscriber.name,
+        !newly_confirmed,
+        settings,
+        templates,
+        translator,
+    )
+}
+
+fn landing_page(
+    name: &str,
+    already_confirmed: bool,
+    settings: &SubscriptionsSettings,
+    templates: &Templates,
+    translator: Translator<'_>,
+) -> Result<HttpResponse, ConfirmError> {
+    let mut context = translator.context();
+    context.insert("name", name);
+    context.insert("already_confirmed", &already_confirmed);
+    context.insert("redirect_url", &settings.confirmation_redirect_url);
+    let body = templates.render("subscriptions_confirm/confirmed.html", &context)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[tracing::instrument(
+    name = "Store the welcome email",
+    skip(transaction, subscriber, templates, translator)
+)]
+async fn enqueue_welcome_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber: &PendingSubscriber,
+    templates: &Templates,
+    translator: Translator<'_>,
+) -> Result<(), anyhow::Error> {
+    let recipient =
+        SubscriberEmail::parse(subscriber.email.clone()).map_err(|e| anyhow::anyhow!(e))?;
+    let mut context = translator.context();
+    context.insert("name", &subscriber.name);
+    let html_body = templates.render("emails/welcome.html", &context)?;
+    let text_body = templates.render("emails/welcome.txt", &context)?;
+    enqueue_email(
+        transaction,
+        OutgoingEmail {
+            recipient: &recipient,
+            subject: &translator.t("emails.welcome.subject"),
+            html_body: &html_body,
+            text_body: &text_body,
+        },
+    )
+    .await?;
+    Ok(())
+}
+
+/// Returns `false` if the subscriber had already been confirmed in the meantime.
+#[tracing::instrument(
+    name = "Mark subscriber as confirmed",
+    skip(subscriber_id, transaction)
+)]
+pub async fn confirm_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'confirmed'
+        WHERE id = $1 AND status = 'pending_confirmation'"#,
+        subscriber_id,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Returns `false` if the token had already been consumed.
+#[tracing::instrument(name = "Consume subscription token", skip(transaction, token_hash))]
+async fn consume_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    token_hash: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"UPDATE subscription_tokens SET consumed_at = now()
+        WHERE subscription_token_hash = $1 AND consumed_at IS NULL"#,
+        token_hash,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+pub struct PendingSubscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    locale: String,
+    token_created_at: DateTime<Utc>,
+    token_consumed_at: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(name = "Get subscriber from token", skip(token_hash, pool))]
+pub async fn get_subscriber_from_token(
+    pool: &PgPool,
+    token_hash: &str,
+) -> Result<Option<PendingSubscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        PendingSubscriber,
+        r#"
+        SELECT s.id, s.email, s.name, s.locale,
+            t.created_at AS token_created_at, t.consumed_at AS token_consumed_at
+        FROM subscription_tokens t
+        JOIN subscriptions s ON s.id = t.subscription_id
+        WHERE t.subscription_token_hash = $1
+        "#,
+        token_hash,
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+#[derive(thiserror::Error)]
+pub enum ConfirmError {
+    #[error("There is no subscriber associated with the provided token.")]
+    UnknownToken,
+    #[error("The provided token has expired.")]
+    ExpiredToken,
+    #[error("The provided token has already been used.")]
+    ConsumedToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ConfirmError {
+    /// Key of the page content in the `confirm_errors` section of the catalogs.
+    fn catalog_key(&self) -> &'static str {
+        match self {
+            ConfirmError::UnknownToken => "unknown_token",
+            ConfirmError::ExpiredToken => "expired_token",
+            ConfirmError::ConsumedToken => "consumed_token",
+            ConfirmError::UnexpectedError(_) => "unexpected",
+        }
+    }
+
+    fn error_page(&self, templates: &Templates, translator: Translator<'_>) -> HttpResponse {
+        let key = self.catalog_key();
+        let message = translator.t(&format!("confirm_errors.{}.message", key));
+        let mut context = translator.context();
+        context.insert(
+            "title",
+            &translator.t(&format!("confirm_errors.{}.title", key)),
+        );
+        context.insert("message", &message);
+        let body = templates
+            .render("subscriptions_confirm/error.html", &context)
+            .unwrap_or(message);
+        HttpResponse::build(self.status_code())
+            .content_type(ContentType::html())
+            .body(body)
+    }
+}
+
+impl std::fmt::Debug for ConfirmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ConfirmError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ConfirmError::UnknownToken => StatusCode::UNAUTHORIZED,
+            ConfirmError::ExpiredToken => StatusCode::GONE,
+            ConfirmError::ConsumedToken => StatusCode::CONFLICT,
+            ConfirmError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
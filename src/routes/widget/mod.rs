@@ -0,0 +1,78 @@
+use actix_web::{get, http::header::ContentType, web, HttpRequest, HttpResponse};
+
+use crate::{
+    i18n::{I18n, Locale},
+    startup::ApplicationBaseUrl,
+    templates::Templates,
+    utils::e500,
+};
+
+#[derive(serde::Deserialize)]
+pub struct WidgetParameters {
+    /// Tag of the embedding site, posted along with the form.
+    source: Option<String>,
+    /// Language of the form, and of the emails sent to those who fill it in.
+    /// Defaults to the one asked for by the browser.
+    locale: Option<String>,
+}
+
+/// Self-contained subscription form, meant to be embedded in an iframe.
+/// It carries no CSRF token, as the session cookie is not sent from another
+/// site: `CsrfProtection` checks its `Origin` instead. `widget_form_js` solves
+/// the signup challenge, if any, before the form is posted.
+#[get("/widget")]
+pub async fn widget(
+    parameters: web::Query<WidgetParameters>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    templates: web::Data<Templates>,
+    i18n: web::Data<I18n>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let locale = parameters
+        .locale
+        .as_deref()
+        .and_then(Locale::negotiate)
+        .unwrap_or_else(|| i18n.request_locale(&request));
+    let mut context = i18n.translator(locale).context();
+    context.insert("base_url", &base_url.to_string());
+    context.insert("source", parameters.source.as_deref().unwrap_or_default());
+    let body = templates
+        .render("widget/widget.html", &context)
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// Script rendering the subscription form in the page that includes it.
+/// The form posts JSON across origins, which requires the embedding site to
+/// be listed in `subscriptions.allowed_origins`.
+#[get("/widget.js")]
+pub async fn widget_js(
+    base_url: web::Data<ApplicationBaseUrl>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    script("widget/widget.js", &base_url, &templates)
+}
+
+/// Script of the iframe widget, which cannot be inlined in its page.
+#[get("/widget/form.js")]
+pub async fn widget_form_js(
+    base_url: web::Data<ApplicationBaseUrl>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    script("widget/form.js", &base_url, &templates)
+}
+
+fn script(
+    template: &str,
+    base_url: &ApplicationBaseUrl,
+    templates: &Templates,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut context = tera::Context::new();
+    context.insert("base_url", &base_url.to_string());
+    let body = templates.render(template, &context).map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/javascript; charset=utf-8")
+        .body(body))
+}
@@ -0,0 +1,155 @@
+use std::{
+    future::{ready, Future, Ready},
+    pin::Pin,
+    sync::Arc,
+};
+
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{
+        HeaderName, HeaderValue, CONTENT_SECURITY_POLICY, REFERRER_POLICY,
+        STRICT_TRANSPORT_SECURITY, X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+    },
+};
+use anyhow::Context;
+
+use crate::configuration::SecurityHeadersSettings;
+
+/// Pages meant to be embedded in other sites.
+const FRAMEABLE_PATHS: &[&str] = &["/widget"];
+
+/// Add the configured security headers to every response, unless the handler
+/// set them itself. Frameable pages get neither `X-Frame-Options` nor the
+/// `frame-ancestors` directive of the Content-Security-Policy.
+#[derive(Clone)]
+pub struct SecurityHeaders {
+    headers: Arc<Vec<(HeaderName, HeaderValue)>>,
+    frameable_headers: Arc<Vec<(HeaderName, HeaderValue)>>,
+}
+
+impl SecurityHeaders {
+    pub fn new(settings: &SecurityHeadersSettings) -> Result<Self, anyhow::Error> {
+        let header_value = |value: &str| {
+            HeaderValue::from_str(value)
+                .with_context(|| format!("`{}` is not a valid header value", value))
+        };
+        let mut common = vec![
+            (X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff")),
+            (REFERRER_POLICY, header_value(&settings.referrer_policy)?),
+        ];
+        if let Some(max_age) = settings.hsts_max_age_secs {
+            common.push((
+                STRICT_TRANSPORT_SECURITY,
+                header_value(&format!("max-age={}; includeSubDomains", max_age))?,
+            ));
+        }
+
+        let mut headers = common.clone();
+        headers.push((
+            CONTENT_SECURITY_POLICY,
+            header_value(&settings.content_security_policy)?,
+        ));
+        headers.push((X_FRAME_OPTIONS, HeaderValue::from_static("DENY")));
+
+        let mut frameable_headers = common;
+        frameable_headers.push((
+            CONTENT_SECURITY_POLICY,
+            header_value(&without_frame_ancestors(&settings.content_security_policy))?,
+        ));
+        Ok(Self {
+            headers: Arc::new(headers),
+            frameable_headers: Arc::new(frameable_headers),
+        })
+    }
+}
+
+fn without_frame_ancestors(policy: &str) -> String {
+    policy
+        .split(';')
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty() && !directive.starts_with("frame-ancestors"))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+impl<S, B> Transform<S, ServiceRequest> for SecurityHeaders
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = SecurityHeadersMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(SecurityHeadersMiddleware {
+            service,
+            headers: self.clone(),
+        }))
+    }
+}
+
+pub struct SecurityHeadersMiddleware<S> {
+    service: S,
+    headers: SecurityHeaders,
+}
+
+impl<S, B> Service<ServiceRequest> for SecurityHeadersMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<ServiceResponse<B>, actix_web::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let headers = if FRAMEABLE_PATHS.contains(&req.path()) {
+            self.headers.frameable_headers.clone()
+        } else {
+            self.headers.headers.clone()
+        };
+        let response = self.service.call(req);
+        Box::pin(async move {
+            let mut response = response.await?;
+            let response_headers = response.headers_mut();
+            for (name, value) in headers.iter() {
+                if !response_headers.contains_key(name) {
+                    response_headers.insert(name.clone(), value.clone());
+                }
+            }
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_ancestors_are_dropped_from_the_policy() {
+        assert_eq!(
+            without_frame_ancestors("default-src 'self'; frame-ancestors 'none'; img-src data:"),
+            "default-src 'self'; img-src data:"
+        );
+        assert_eq!(
+            without_frame_ancestors("default-src 'self';"),
+            "default-src 'self'"
+        );
+    }
+
+    #[test]
+    fn invalid_header_values_are_rejected() {
+        let settings = SecurityHeadersSettings {
+            content_security_policy: "default-src 'self'\n".into(),
+            referrer_policy: "no-referrer".into(),
+            hsts_max_age_secs: None,
+        };
+        assert!(SecurityHeaders::new(&settings).is_err());
+    }
+}
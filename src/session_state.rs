@@ -0,0 +1,92 @@
+use actix_session::{Session, SessionExt};
+use actix_web::{dev::Payload, FromRequest, HttpRequest};
+use rand::Rng;
+use std::future::{ready, Ready};
+use uuid::Uuid;
+
+/// Typed access to the values we keep in the session of an admin.
+pub struct TypedSession(Session);
+
+impl TypedSession {
+    const USER_ID_KEY: &'static str = "user_id";
+    const SESSION_ID_KEY: &'static str = "session_id";
+    const CSRF_TOKEN_KEY: &'static str = "csrf_token";
+    const PENDING_USER_ID_KEY: &'static str = "pending_user_id";
+    const TWO_FACTOR_ENROLLED_KEY: &'static str = "two_factor_enrolled";
+
+    pub fn renew(&self) {
+        self.0.renew();
+    }
+
+    /// `session_id` identifies the record of the session, see `record_user_session`.
+    pub fn insert_user_id(&self, user_id: Uuid, session_id: Uuid) -> Result<(), serde_json::Error> {
+        self.0.insert(Self::USER_ID_KEY, user_id)?;
+        self.0.insert(Self::SESSION_ID_KEY, session_id)
+    }
+
+    pub fn get_user_id(&self) -> Result<Option<Uuid>, serde_json::Error> {
+        self.0.get(Self::USER_ID_KEY)
+    }
+
+    pub fn get_session_id(&self) -> Result<Option<Uuid>, serde_json::Error> {
+        self.0.get(Self::SESSION_ID_KEY)
+    }
+
+    /// The user who passed the password check and still has to provide a second factor.
+    pub fn insert_pending_user_id(&self, user_id: Uuid) -> Result<(), serde_json::Error> {
+        self.0.insert(Self::PENDING_USER_ID_KEY, user_id)
+    }
+
+    pub fn get_pending_user_id(&self) -> Result<Option<Uuid>, serde_json::Error> {
+        self.0.get(Self::PENDING_USER_ID_KEY)
+    }
+
+    pub fn remove_pending_user_id(&self) {
+        self.0.remove(Self::PENDING_USER_ID_KEY);
+    }
+
+    pub fn insert_two_factor_enrolled(&self, enrolled: bool) -> Result<(), serde_json::Error> {
+        self.0.insert(Self::TWO_FACTOR_ENROLLED_KEY, enrolled)
+    }
+
+    pub fn is_two_factor_enrolled(&self) -> Result<bool, serde_json::Error> {
+        Ok(self.0.get(Self::TWO_FACTOR_ENROLLED_KEY)?.unwrap_or(false))
+    }
+
+    /// The token that forms must submit, see `CsrfProtection`. Created on first use.
+    pub fn csrf_token(&self) -> Result<String, serde_json::Error> {
+        if let Some(token) = self.get_csrf_token()? {
+            return Ok(token);
+        }
+        let token: String =
+            std::iter::repeat_with(|| rand::thread_rng().sample(rand::distributions::Alphanumeric))
+                .map(char::from)
+                .take(32)
+                .collect();
+        self.0.insert(Self::CSRF_TOKEN_KEY, &token)?;
+        Ok(token)
+    }
+
+    pub fn get_csrf_token(&self) -> Result<Option<String>, serde_json::Error> {
+        self.0.get(Self::CSRF_TOKEN_KEY)
+    }
+
+    pub fn log_out(self) {
+        self.0.purge()
+    }
+}
+
+impl From<Session> for TypedSession {
+    fn from(session: Session) -> Self {
+        Self(session)
+    }
+}
+
+impl FromRequest for TypedSession {
+    type Error = <Session as FromRequest>::Error;
+    type Future = Ready<Result<TypedSession, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(Ok(TypedSession(req.get_session())))
+    }
+}
@@ -0,0 +1,327 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use redis::aio::ConnectionManager;
+use reqwest::Client;
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sha3::Sha3_256;
+
+use crate::configuration::SignupChallengeSettings;
+
+/// An optional verification step proving that a subscription is not scripted.
+#[async_trait::async_trait]
+pub trait SignupChallenge: Send + Sync {
+    /// A challenge to hand over to the client before it submits the form, if the
+    /// implementation needs one.
+    fn new_challenge(&self) -> Option<Challenge> {
+        None
+    }
+
+    /// Check the response submitted by the client along with the form.
+    async fn verify(&self, response: Option<&str>, remote_ip: &str) -> Result<bool, anyhow::Error>;
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct Challenge {
+    pub challenge: String,
+    pub difficulty_bits: u32,
+}
+
+impl SignupChallengeSettings {
+    /// `redis` and `key_prefix` hold the proofs of work already spent.
+    pub fn build(
+        &self,
+        hmac_secret: &Secret<String>,
+        redis: ConnectionManager,
+        key_prefix: &str,
+    ) -> Result<Arc<dyn SignupChallenge>, anyhow::Error> {
+        let challenge: Arc<dyn SignupChallenge> = match self {
+            SignupChallengeSettings::Disabled => Arc::new(NoChallenge),
+            SignupChallengeSettings::ProofOfWork {
+                difficulty_bits,
+                validity_secs,
+            } => Arc::new(SingleUseProofOfWork {
+                proof_of_work: ProofOfWork {
+                    secret: hmac_secret.clone(),
+                    difficulty_bits: *difficulty_bits,
+                    validity: chrono::Duration::seconds(*validity_secs),
+                },
+                connection: redis,
+                key_prefix: format!("{}:signup_challenge", key_prefix),
+            }),
+            SignupChallengeSettings::Remote {
+                verification_url,
+                secret,
+                timeout_ms,
+            } => Arc::new(RemoteVerification {
+                http_client: Client::builder()
+                    .timeout(Duration::from_millis(*timeout_ms))
+                    .build()
+                    .context("Failed to build the signup challenge HTTP client")?,
+                verification_url: verification_url.clone(),
+                secret: secret.clone(),
+            }),
+        };
+        Ok(challenge)
+    }
+}
+
+pub struct NoChallenge;
+
+#[async_trait::async_trait]
+impl SignupChallenge for NoChallenge {
+    async fn verify(&self, _: Option<&str>, _: &str) -> Result<bool, anyhow::Error> {
+        Ok(true)
+    }
+}
+
+/// Self-hosted hashcash-like proof of work.
+///
+/// The server hands out a signed, timestamped challenge. The client must find a
+/// nonce such that `SHA-256("<challenge>:<nonce>")` starts with `difficulty_bits`
+/// zero bits, and submits `<challenge>:<nonce>`. SHA-256 is what browsers can
+/// compute, for the widgets to solve it.
+/// The signature and the timestamp are all this checks: see
+/// [`SingleUseProofOfWork`] for the protection against replays.
+pub struct ProofOfWork {
+    secret: Secret<String>,
+    difficulty_bits: u32,
+    validity: chrono::Duration,
+}
+
+impl ProofOfWork {
+    fn mac(&self, payload: &str) -> Hmac<Sha3_256> {
+        let mut mac = Hmac::<Sha3_256>::new_from_slice(self.secret.expose_secret().as_bytes())
+            .expect("HMAC accepts keys of any size");
+        mac.update(payload.as_bytes());
+        mac
+    }
+
+    fn check_challenge(&self, challenge: &str) -> bool {
+        let mut parts = challenge.rsplitn(2, '.');
+        let (signature, payload) = match (parts.next(), parts.next()) {
+            (Some(signature), Some(payload)) => (signature, payload),
+            _ => return false,
+        };
+        let signature = match hex::decode(signature) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+        // In constant time.
+        if self.mac(payload).verify_slice(&signature).is_err() {
+            return false;
+        }
+        match payload.split('.').next().map(str::parse::<i64>) {
+            Some(Ok(issued_at)) => {
+                issued_at + self.validity.num_seconds() >= chrono::Utc::now().timestamp()
+            }
+            _ => false,
+        }
+    }
+
+    fn issue(&self) -> Challenge {
+        let salt: u64 = rand::thread_rng().gen();
+        let payload = format!("{}.{:x}", chrono::Utc::now().timestamp(), salt);
+        let signature = hex::encode(self.mac(&payload).finalize().into_bytes());
+        Challenge {
+            challenge: format!("{}.{}", payload, signature),
+            difficulty_bits: self.difficulty_bits,
+        }
+    }
+
+    /// The challenge solved by `response`, if it is genuine, unexpired and solved.
+    fn solved_challenge<'a>(&self, response: Option<&'a str>) -> Option<&'a str> {
+        let (challenge, nonce) = response?.rsplit_once(':')?;
+        if self.check_challenge(challenge)
+            && leading_zero_bits(&pow_hash(challenge, nonce)) >= self.difficulty_bits
+        {
+            Some(challenge)
+        } else {
+            None
+        }
+    }
+}
+
+/// A [`ProofOfWork`] whose challenges can only be used once, so that a single
+/// solution cannot be replayed to post many forms. Spent challenges are kept in
+/// Redis until they would have expired anyway.
+pub struct SingleUseProofOfWork {
+    proof_of_work: ProofOfWork,
+    connection: ConnectionManager,
+    key_prefix: String,
+}
+
+#[async_trait::async_trait]
+impl SignupChallenge for SingleUseProofOfWork {
+    fn new_challenge(&self) -> Option<Challenge> {
+        Some(self.proof_of_work.issue())
+    }
+
+    #[tracing::instrument(name = "Verify proof of work", skip(self, response))]
+    async fn verify(&self, response: Option<&str>, _: &str) -> Result<bool, anyhow::Error> {
+        let challenge = match self.proof_of_work.solved_challenge(response) {
+            Some(challenge) => challenge,
+            None => return Ok(false),
+        };
+        let first_use: Option<String> = redis::cmd("SET")
+            .arg(format!("{}:{}", self.key_prefix, challenge))
+            .arg(1)
+            .arg("EX")
+            .arg(self.proof_of_work.validity.num_seconds().max(1))
+            .arg("NX")
+            .query_async(&mut self.connection.clone())
+            .await
+            .context("Failed to record the spent signup challenge")?;
+        if first_use.is_none() {
+            tracing::warn!("Replayed proof of work");
+        }
+        Ok(first_use.is_some())
+    }
+}
+
+fn pow_hash(challenge: &str, nonce: &str) -> Vec<u8> {
+    Sha256::digest(format!("{}:{}", challenge, nonce).as_bytes()).to_vec()
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        if *byte == 0 {
+            bits += 8;
+        } else {
+            return bits + byte.leading_zeros();
+        }
+    }
+    bits
+}
+
+/// Reference solver, as the signup widget runs it: returns the response to submit.
+pub fn solve_proof_of_work(challenge: &Challenge) -> String {
+    (0u64..)
+        .map(|nonce| nonce.to_string())
+        .find(|nonce| {
+            leading_zero_bits(&pow_hash(&challenge.challenge, nonce)) >= challenge.difficulty_bits
+        })
+        .map(|nonce| format!("{}:{}", challenge.challenge, nonce))
+        .expect("A nonce is found long before the u64 range runs out")
+}
+
+/// CAPTCHA-like verification delegated to an external endpoint, using the
+/// `secret` / `response` / `remoteip` form most providers share.
+pub struct RemoteVerification {
+    http_client: Client,
+    verification_url: String,
+    secret: Secret<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct RemoteVerificationResponse {
+    success: bool,
+}
+
+#[async_trait::async_trait]
+impl SignupChallenge for RemoteVerification {
+    #[tracing::instrument(name = "Verify signup challenge remotely", skip(self, response))]
+    async fn verify(&self, response: Option<&str>, remote_ip: &str) -> Result<bool, anyhow::Error> {
+        let response = match response {
+            Some(response) => response,
+            None => return Ok(false),
+        };
+        let outcome: RemoteVerificationResponse = self
+            .http_client
+            .post(&self.verification_url)
+            .form(&[
+                ("secret", self.secret.expose_secret().as_str()),
+                ("response", response),
+                ("remoteip", remote_ip),
+            ])
+            .send()
+            .await
+            .context("Failed to reach the challenge verification endpoint")?
+            .error_for_status()
+            .context("The challenge verification endpoint returned an error")?
+            .json()
+            .await
+            .context("Invalid response from the challenge verification endpoint")?;
+        Ok(outcome.success)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proof_of_work(validity_secs: i64) -> ProofOfWork {
+        ProofOfWork {
+            secret: Secret::new("secret".into()),
+            difficulty_bits: 8,
+            validity: chrono::Duration::seconds(validity_secs),
+        }
+    }
+
+    #[test]
+    fn a_solved_challenge_is_accepted() {
+        let pow = proof_of_work(60);
+        let challenge = pow.issue();
+        let response = solve_proof_of_work(&challenge);
+        assert_eq!(
+            pow.solved_challenge(Some(&response)),
+            Some(challenge.challenge.as_str())
+        );
+    }
+
+    #[test]
+    fn a_wrong_nonce_is_rejected() {
+        let pow = proof_of_work(60);
+        let challenge = pow.issue();
+        let nonce = (0u64..)
+            .find(|n| leading_zero_bits(&pow_hash(&challenge.challenge, &n.to_string())) < 8)
+            .unwrap();
+        let response = format!("{}:{}", challenge.challenge, nonce);
+        assert_eq!(pow.solved_challenge(Some(&response)), None);
+    }
+
+    #[test]
+    fn a_forged_challenge_is_rejected() {
+        let pow = proof_of_work(60);
+        let forged = Challenge {
+            challenge: format!("{}.abc.deadbeef", chrono::Utc::now().timestamp()),
+            difficulty_bits: 8,
+        };
+        let response = solve_proof_of_work(&forged);
+        assert_eq!(pow.solved_challenge(Some(&response)), None);
+    }
+
+    #[test]
+    fn a_challenge_signed_with_another_secret_is_rejected() {
+        let other = ProofOfWork {
+            secret: Secret::new("other secret".into()),
+            ..proof_of_work(60)
+        };
+        let response = solve_proof_of_work(&other.issue());
+        assert_eq!(proof_of_work(60).solved_challenge(Some(&response)), None);
+    }
+
+    #[test]
+    fn an_expired_challenge_is_rejected() {
+        let pow = proof_of_work(-1);
+        let response = solve_proof_of_work(&pow.issue());
+        assert_eq!(pow.solved_challenge(Some(&response)), None);
+    }
+
+    #[test]
+    fn a_missing_response_is_rejected() {
+        let pow = proof_of_work(60);
+        assert_eq!(pow.solved_challenge(None), None);
+    }
+
+    #[test]
+    fn leading_zero_bits_are_counted_across_bytes() {
+        assert_eq!(leading_zero_bits(&[0, 0b0001_0000]), 11);
+        assert_eq!(leading_zero_bits(&[0xff]), 0);
+        assert_eq!(leading_zero_bits(&[0, 0]), 16);
+    }
+}
@@ -1,12 +1,30 @@
 use std::{fmt::Display, net::TcpListener};
 
 use crate::{
+    authentication::{
+        require_two_factor_enrollment, CsrfProtection, PasswordHashing, Permission,
+        RejectAnonymousUsers, RequirePermission,
+    },
     configuration::{DatabaseSettings, Settings},
     email_client::EmailClient,
-    routes::{confirm, health_check, home, login, login_form, publish_newsletter, subscribe},
+    i18n::I18n,
+    rate_limit::RateLimiter,
+    routes::{
+        admin, api, complete_invitation, confirm, forgot_password, forgot_password_form,
+        get_signup_challenge, health_check, home, invitation_form, login, login_form,
+        problem::{payload_error_handler, scope_request_id},
+        publish_newsletter, reset_password, reset_password_form,
+        strip_plus_tags_from_canonical_emails, subscribe, two_factor_form, two_factor_login,
+        widget, widget_form_js, widget_js,
+    },
+    security_headers::SecurityHeaders,
+    signup_challenge::SignupChallenge,
+    templates::Templates,
+    utils::TrustedProxies,
 };
+use actix_cors::Cors;
 use actix_session::{storage::RedisSessionStore, SessionMiddleware};
-use actix_web::{cookie::Key, dev::Server, web, App, HttpServer};
+use actix_web::{cookie::Key, dev::Server, http::header, web, App, HttpServer};
 use actix_web_flash_messages::{storage::CookieMessageStore, FlashMessagesFramework};
 use secrecy::{ExposeSecret, Secret};
 use sqlx::{postgres::PgPoolOptions, PgPool};
@@ -16,37 +34,141 @@ pub async fn run(
     listener: TcpListener,
     connection: PgPool,
     email_client: EmailClient,
-    base_url: String,
-    hmac_secret: Secret<String>,
-    redis_uri: Secret<String>,
+    rate_limiter: RateLimiter,
+    configuration: Settings,
 ) -> Result<Server, anyhow::Error> {
+    configuration.validate()?;
+    let email_policy = web::Data::new(configuration.email_policy.load()?);
+    if email_policy.strip_plus_tags {
+        strip_plus_tags_from_canonical_emails(&connection).await?;
+    }
+    let signup_challenge: web::Data<dyn SignupChallenge> =
+        web::Data::from(configuration.signup_challenge.build(
+            &configuration.application.hmac_secret,
+            rate_limiter.connection(),
+            &configuration.rate_limit.key_prefix,
+        )?);
+    let rate_limiter = web::Data::new(rate_limiter);
     let db_pool = web::Data::new(connection);
+    let allowed_origins = allowed_origins(&configuration);
+    let subscriptions_settings = web::Data::new(configuration.subscriptions);
+    let two_factor_settings = web::Data::new(configuration.two_factor);
+    let password_reset_settings = web::Data::new(configuration.password_reset);
+    let invitation_settings = web::Data::new(configuration.invitations);
+    let password_hashing = web::Data::new(PasswordHashing::new(&configuration.password_hashing)?);
     let email_client = web::Data::new(email_client);
-    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let trusted_proxies = web::Data::new(TrustedProxies::new(
+        configuration.application.trusted_proxies,
+    ));
+    let csrf_protection = CsrfProtection::new(&configuration.application.base_url);
+    let base_url = web::Data::new(ApplicationBaseUrl(configuration.application.base_url));
+    let hmac_secret = configuration.application.hmac_secret;
+    let redis_uri = configuration.redis_uri;
     // let hmac_secret = web::Data::new(HmacSecret(hmac_secret));
     let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
     let message_store = CookieMessageStore::builder(secret_key.clone()).build();
     let message_framework = FlashMessagesFramework::builder(message_store).build();
     let redis_store = RedisSessionStore::new(redis_uri.expose_secret()).await?;
+    let security_headers = SecurityHeaders::new(&configuration.security_headers)?;
+    let session_cookie = web::Data::new(configuration.session_cookie);
+    let templates = web::Data::new(Templates::new(&configuration.templates)?);
+    let i18n = web::Data::new(I18n::new(&configuration.i18n)?);
     let server = HttpServer::new(
         move || {
             App::new()
+                .wrap_fn(scope_request_id)
+                .wrap(csrf_protection.clone())
                 .wrap(message_framework.clone())
                 .wrap(TracingLogger::default())
-                .wrap(SessionMiddleware::new(
-                    redis_store.clone(),
-                    secret_key.clone(),
-                ))
+                .wrap(
+                    SessionMiddleware::builder(redis_store.clone(), secret_key.clone())
+                        .cookie_secure(session_cookie.secure)
+                        .cookie_same_site(session_cookie.same_site.into())
+                        .session_length(session_cookie.session_length())
+                        .build(),
+                )
+                .wrap(security_headers.clone())
                 .service(health_check)
-                .service(subscribe)
-                .service(confirm)
+                .service(
+                    web::scope("/subscriptions")
+                        .wrap(subscriptions_cors(&allowed_origins))
+                        .service(subscribe)
+                        .service(get_signup_challenge)
+                        .service(confirm),
+                )
+                .service(
+                    web::scope("/api/v1")
+                        .service(api::list_subscribers)
+                        .service(api::get_subscriber)
+                        .service(api::update_subscriber)
+                        .service(api::delete_subscriber)
+                        .service(api::list_audit_entries),
+                )
+                .service(
+                    web::scope("/admin")
+                        .wrap_fn(require_two_factor_enrollment)
+                        .wrap(RejectAnonymousUsers)
+                        .service(admin::admin_dashboard)
+                        .service(admin::api_tokens_page)
+                        .service(admin::create_api_token)
+                        .service(admin::revoke_api_token)
+                        .service(admin::two_factor_page)
+                        .service(admin::enable_two_factor)
+                        .service(admin::replace_recovery_codes)
+                        .service(admin::disable_two_factor_authentication)
+                        .service(admin::sessions_page)
+                        .service(admin::revoke_session)
+                        .service(admin::revoke_other_sessions)
+                        .service(
+                            web::scope("/users")
+                                .wrap(RequirePermission(Permission::ManageUsers))
+                                .service(admin::users_page)
+                                .service(admin::invite_user)
+                                .service(admin::disable_user)
+                                .service(admin::enable_user)
+                                .service(admin::delete_user),
+                        )
+                        .service(
+                            web::scope("/audit_log")
+                                .wrap(RequirePermission(Permission::ReadAuditLog))
+                                .service(admin::audit_log_page),
+                        )
+                        .service(admin::log_out),
+                )
+                .service(widget)
+                .service(widget_js)
+                .service(widget_form_js)
                 .service(publish_newsletter)
                 .service(home)
                 .service(login_form)
                 .service(login)
+                .service(two_factor_form)
+                .service(two_factor_login)
+                .service(forgot_password_form)
+                .service(forgot_password)
+                .service(reset_password_form)
+                .service(reset_password)
+                .service(invitation_form)
+                .service(complete_invitation)
+                .app_data(web::FormConfig::default().error_handler(payload_error_handler))
+                .app_data(web::JsonConfig::default().error_handler(payload_error_handler))
+                .app_data(web::QueryConfig::default().error_handler(payload_error_handler))
+                .app_data(web::PathConfig::default().error_handler(payload_error_handler))
                 .app_data(db_pool.clone())
                 .app_data(email_client.clone())
                 .app_data(base_url.clone())
+                .app_data(subscriptions_settings.clone())
+                .app_data(rate_limiter.clone())
+                .app_data(signup_challenge.clone())
+                .app_data(email_policy.clone())
+                .app_data(two_factor_settings.clone())
+                .app_data(password_reset_settings.clone())
+                .app_data(invitation_settings.clone())
+                .app_data(password_hashing.clone())
+                .app_data(session_cookie.clone())
+                .app_data(templates.clone())
+                .app_data(i18n.clone())
+                .app_data(trusted_proxies.clone())
             // .app_data(hmac_secret.clone())
         }, // .route("/health_check", web::get().to(health_check))
     )
@@ -61,6 +183,23 @@ pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
         .connect_lazy_with(configuration.with_db())
 }
 
+/// Our own origin, plus the ones configured for the subscription endpoints.
+fn allowed_origins(configuration: &Settings) -> Vec<String> {
+    std::iter::once(&configuration.application.base_url)
+        .chain(&configuration.subscriptions.allowed_origins)
+        .map(|origin| origin.trim_end_matches('/').to_string())
+        .collect()
+}
+
+fn subscriptions_cors(allowed_origins: &[String]) -> Cors {
+    allowed_origins
+        .iter()
+        .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+        .allowed_methods(["GET", "POST"])
+        .allowed_headers([header::CONTENT_TYPE, header::ACCEPT])
+        .max_age(3600)
+}
+
 #[derive(Debug)]
 pub struct ApplicationBaseUrl(String);
 impl Display for ApplicationBaseUrl {
@@ -77,16 +216,9 @@ pub struct Application {
 impl Application {
     pub async fn build(configuration: Settings) -> Result<Self, anyhow::Error> {
         let connection_pool = get_connection_pool(&configuration.database);
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender email address.");
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url.clone(),
-            sender_email,
-            configuration.email_client.authorization_token.clone(),
-            configuration.email_client.timeout(),
-        );
+        let email_client = configuration.email_client.clone().client();
+        let rate_limiter =
+            RateLimiter::new(&configuration.redis_uri, configuration.rate_limit.clone()).await?;
         let address = format!(
             "{}:{}",
             configuration.application.host, configuration.application.port
@@ -97,9 +229,8 @@ impl Application {
             listener,
             connection_pool,
             email_client,
-            configuration.application.base_url,
-            configuration.application.hmac_secret,
-            configuration.redis_uri,
+            rate_limiter,
+            configuration,
         )
         .await?;
         // We "save" the bound port in one of `Application`'s fields
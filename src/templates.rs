@@ -0,0 +1,173 @@
+//! Rendering of the pages, emails and scripts in the `templates` directory.
+//! Pages extend `base.html`, which shows the `messages` made by `flash_message_views`.
+use std::sync::RwLock;
+
+use actix_web_flash_messages::{IncomingFlashMessages, Level};
+use anyhow::Context;
+use tera::Tera;
+
+use crate::configuration::TemplateSettings;
+
+macro_rules! embed {
+    ($($name:literal),* $(,)?) => {
+        &[$(($name, include_str!(concat!("../templates/", $name)))),*]
+    };
+}
+
+/// Every template, built into the binary so that it runs without the source tree.
+const EMBEDDED: &[(&str, &str)] = embed!(
+    "base.html",
+    "home.html",
+    "admin/api_token_created.html",
+    "admin/api_tokens.html",
+    "admin/audit_log.html",
+    "admin/dashboard.html",
+    "admin/forbidden.html",
+    "admin/recovery_codes.html",
+    "admin/sessions.html",
+    "admin/two_factor.html",
+    "admin/users.html",
+    "emails/confirmation.html",
+    "emails/confirmation.txt",
+    "emails/invitation.html",
+    "emails/invitation.txt",
+    "emails/password_reset.html",
+    "emails/password_reset.txt",
+    "emails/welcome.html",
+    "emails/welcome.txt",
+    "expired_form.html",
+    "invitations/accept.html",
+    "login/forgot_password.html",
+    "login/login.html",
+    "login/reset_password.html",
+    "login/two_factor.html",
+    "subscriptions/error.html",
+    "subscriptions/subscribed.html",
+    "subscriptions_confirm/confirmed.html",
+    "subscriptions_confirm/error.html",
+    "widget/challenge.js",
+    "widget/form.js",
+    "widget/widget.html",
+    "widget/widget.js",
+);
+
+const TEMPLATES_GLOB: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/templates/**/*");
+
+/// Templates ending in `.html` are autoescaped.
+pub struct Templates {
+    tera: RwLock<Tera>,
+    hot_reload: bool,
+}
+
+impl Templates {
+    pub fn new(settings: &TemplateSettings) -> Result<Self, anyhow::Error> {
+        let tera = if settings.hot_reload {
+            Tera::new(TEMPLATES_GLOB).context("Failed to load the templates")?
+        } else {
+            let mut tera = Tera::default();
+            tera.add_raw_templates(EMBEDDED.to_vec())
+                .context("Failed to load the embedded templates")?;
+            tera
+        };
+        Ok(Self {
+            tera: RwLock::new(tera),
+            hot_reload: settings.hot_reload,
+        })
+    }
+
+    pub fn render(&self, name: &str, context: &tera::Context) -> Result<String, anyhow::Error> {
+        let rendered = if self.hot_reload {
+            let mut tera = self
+                .tera
+                .write()
+                .map_err(|_| anyhow::anyhow!("The templates lock is poisoned"))?;
+            tera.full_reload()
+                .context("Failed to reload the templates")?;
+            tera.render(name, context)
+        } else {
+            self.tera
+                .read()
+                .map_err(|_| anyhow::anyhow!("The templates lock is poisoned"))?
+                .render(name, context)
+        };
+        rendered.with_context(|| format!("Failed to render `{}`", name))
+    }
+}
+
+/// A flash message, as `base.html` expects it.
+#[derive(serde::Serialize)]
+pub struct FlashMessageView<'a> {
+    level: &'static str,
+    content: &'a str,
+}
+
+pub fn flash_message_views(messages: &IncomingFlashMessages) -> Vec<FlashMessageView<'_>> {
+    messages
+        .iter()
+        .map(|m| FlashMessageView {
+            level: match m.level() {
+                Level::Debug => "debug",
+                Level::Info => "info",
+                Level::Success => "success",
+                Level::Warning => "warning",
+                Level::Error => "error",
+            },
+            content: m.content(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files_under(dir: &std::path::Path, prefix: &str, files: &mut Vec<String>) {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let entry = entry.unwrap();
+            let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+            if entry.file_type().unwrap().is_dir() {
+                files_under(&entry.path(), &format!("{}/", name), files);
+            } else {
+                files.push(name);
+            }
+        }
+    }
+
+    #[test]
+    fn every_template_is_embedded() {
+        let root = concat!(env!("CARGO_MANIFEST_DIR"), "/templates");
+        let mut on_disk = vec![];
+        files_under(std::path::Path::new(root), "", &mut on_disk);
+        on_disk.sort();
+        let mut embedded: Vec<String> = EMBEDDED.iter().map(|(name, _)| name.to_string()).collect();
+        embedded.sort();
+        assert_eq!(on_disk, embedded);
+    }
+
+    #[test]
+    fn embedded_templates_render_with_the_layout() {
+        let templates = Templates::new(&TemplateSettings { hot_reload: false }).unwrap();
+        let mut context = tera::Context::new();
+        context.insert(
+            "messages",
+            &[FlashMessageView {
+                level: "info",
+                content: "<script>alert(1)</script>",
+            }],
+        );
+        let html = templates.render("home.html", &context).unwrap();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains(r#"<div class="flash flash-info" role="status">"#));
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(!html.contains("<script>"));
+    }
+
+    #[test]
+    fn hot_reloaded_templates_are_read_from_the_source_tree() {
+        let templates = Templates::new(&TemplateSettings { hot_reload: true }).unwrap();
+        let mut context = tera::Context::new();
+        context.insert("base_url", "https://example.com");
+        let script = templates.render("widget/widget.js", &context).unwrap();
+        assert!(script.contains(r#"var endpoint = "https://example.com/subscriptions";"#));
+    }
+}
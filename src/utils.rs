@@ -0,0 +1,129 @@
+use std::net::IpAddr;
+
+use actix_web::{error::InternalError, http::header::LOCATION, web, HttpRequest, HttpResponse};
+
+use crate::routes::Problem;
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// Return an opaque 500 while preserving the error root's cause for logging.
+/// Only a generic problem is sent back: the error message must not reach the client.
+pub fn e500<T>(e: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    tracing::error!(error.cause_chain = ?e, error.message = %e, "Internal server error");
+    InternalError::from_response(e, Problem::internal_error().response()).into()
+}
+
+/// Compare secrets without leaking, through the time taken, how much of them matched.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+pub fn see_other(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, location))
+        .finish()
+}
+
+/// The reverse proxies allowed to tell the client's address.
+#[derive(Debug, Default)]
+pub struct TrustedProxies(Vec<IpAddr>);
+
+impl TrustedProxies {
+    pub fn new(proxies: Vec<IpAddr>) -> Self {
+        Self(proxies)
+    }
+
+    /// `X-Forwarded-For` is read from the right, as every proxy appends the
+    /// address it got the request from: the client is the first address that
+    /// was not added by a trusted proxy. Anything to its left is whatever the
+    /// client chose to send.
+    fn client_address(&self, peer: IpAddr, forwarded_for: &str) -> IpAddr {
+        let mut client = peer;
+        if !self.0.contains(&peer) {
+            return client;
+        }
+        for hop in forwarded_for.rsplit(',') {
+            match hop.trim().parse::<IpAddr>() {
+                Ok(address) => {
+                    client = address;
+                    if !self.0.contains(&address) {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+        client
+    }
+}
+
+/// The address of the client, which rate limits and audit entries are keyed on.
+/// It is the peer address, unless the peer is one of the `TrustedProxies`.
+pub fn client_ip(request: &HttpRequest) -> String {
+    let peer = match request.peer_addr() {
+        Some(peer) => peer.ip(),
+        None => return "unknown".to_string(),
+    };
+    let forwarded_for = request
+        .headers()
+        .get_all(X_FORWARDED_FOR)
+        .filter_map(|h| h.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",");
+    match request.app_data::<web::Data<TrustedProxies>>() {
+        Some(proxies) => proxies.client_address(peer, &forwarded_for),
+        None => peer,
+    }
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[actix_web::test]
+    async fn internal_errors_are_not_shown_to_the_client() {
+        let response =
+            e500("password authentication failed for user \"postgres\"").error_response();
+
+        assert_eq!(response.status().as_u16(), 500);
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("internal-error"));
+        assert!(!body.contains("postgres"));
+    }
+
+    #[test]
+    fn forwarded_addresses_are_ignored_from_untrusted_peers() {
+        let proxies = TrustedProxies::new(vec![ip("10.0.0.1")]);
+        assert_eq!(
+            proxies.client_address(ip("203.0.113.7"), "198.51.100.1"),
+            ip("203.0.113.7")
+        );
+    }
+
+    #[test]
+    fn the_client_is_the_first_address_not_added_by_a_trusted_proxy() {
+        let proxies = TrustedProxies::new(vec![ip("10.0.0.1"), ip("10.0.0.2")]);
+        // The client made up the leftmost address.
+        let forwarded_for = "192.0.2.66, 203.0.113.7, 10.0.0.2";
+        assert_eq!(
+            proxies.client_address(ip("10.0.0.1"), forwarded_for),
+            ip("203.0.113.7")
+        );
+        assert_eq!(
+            proxies.client_address(ip("10.0.0.1"), "garbage, 10.0.0.2"),
+            ip("10.0.0.2")
+        );
+        assert_eq!(proxies.client_address(ip("10.0.0.1"), ""), ip("10.0.0.1"));
+    }
+}
@@ -0,0 +1,41 @@
+// Signup challenge solver, shared by the widgets. `solveSignupChallenge` resolves
+// to the `challenge_response` to submit with the form, or to null when no
+// challenge is configured. The proof of work is described in
+// `src/signup_challenge.rs`: find a nonce such that
+// SHA-256("<challenge>:<nonce>") starts with `difficulty_bits` zero bits.
+function solveSignupChallenge() {
+  return fetch("{{ base_url }}/subscriptions/challenge").then(function (response) {
+    if (response.status === 404) {
+      return null;
+    }
+    if (!response.ok) {
+      throw new Error("Failed to get a signup challenge");
+    }
+    return response.json().then(solveProofOfWork);
+  });
+}
+
+function solveProofOfWork(challenge) {
+  var encoder = new TextEncoder();
+  function attempt(nonce) {
+    var candidate = challenge.challenge + ":" + nonce;
+    return crypto.subtle.digest("SHA-256", encoder.encode(candidate)).then(function (hash) {
+      if (leadingZeroBits(new Uint8Array(hash)) >= challenge.difficulty_bits) {
+        return candidate;
+      }
+      return attempt(nonce + 1);
+    });
+  }
+  return attempt(0);
+}
+
+function leadingZeroBits(bytes) {
+  var bits = 0;
+  for (var i = 0; i < bytes.length; i++) {
+    if (bytes[i] !== 0) {
+      return bits + Math.clz32(bytes[i]) - 24;
+    }
+    bits += 8;
+  }
+  return bits;
+}
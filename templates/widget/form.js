@@ -0,0 +1,26 @@
+// Script of the iframe widget: the signup challenge is solved when the form
+// is submitted, as inline scripts are not allowed by the Content-Security-Policy.
+(function () {
+  "use strict";
+  {% include "widget/challenge.js" %}
+
+  var form = document.querySelector("form.zero2prod-widget");
+  var solved = false;
+
+  form.addEventListener("submit", function (event) {
+    if (solved) {
+      return;
+    }
+    event.preventDefault();
+    form.querySelector("button[type=submit]").disabled = true;
+    solveSignupChallenge()
+      .then(function (response) {
+        form.elements.challenge_response.value = response || "";
+      })
+      .catch(function () {})
+      .then(function () {
+        solved = true;
+        form.submit();
+      });
+  });
+})();
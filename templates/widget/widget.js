@@ -0,0 +1,59 @@
+// Signup widget: include it with
+//   <script src="{{ base_url }}/widget.js" data-source="your-site"></script>
+// and the subscription form is rendered where the script tag is.
+(function () {
+  "use strict";
+  var script = document.currentScript;
+  var endpoint = "{{ base_url }}/subscriptions";
+  var source = (script && script.getAttribute("data-source")) || "";
+  {% include "widget/challenge.js" %}
+
+  var form = document.createElement("form");
+  form.className = "zero2prod-widget";
+  form.innerHTML =
+    '<label>Name <input type="text" name="name" required></label>' +
+    '<label>Email <input type="email" name="email" required></label>' +
+    '<label style="position:absolute;left:-10000px" aria-hidden="true">Website ' +
+    '<input type="text" name="website" tabindex="-1" autocomplete="off"></label>' +
+    '<button type="submit">Subscribe</button>' +
+    '<p class="zero2prod-widget-message" role="status"></p>';
+  var message = form.querySelector(".zero2prod-widget-message");
+
+  form.addEventListener("submit", function (event) {
+    event.preventDefault();
+    var payload = {
+      name: form.elements.name.value,
+      email: form.elements.email.value,
+      website: form.elements.website.value,
+      source: source
+    };
+    solveSignupChallenge()
+      .then(function (challengeResponse) {
+        if (challengeResponse) {
+          payload.challenge_response = challengeResponse;
+        }
+        return fetch(endpoint, {
+          method: "POST",
+          headers: { "Content-Type": "application/json", "Accept": "application/json" },
+          body: JSON.stringify(payload)
+        });
+      })
+      .then(function (response) {
+        if (response.ok) {
+          form.reset();
+          message.textContent =
+            "Almost there! Please check your inbox to confirm your subscription.";
+          return;
+        }
+        return response.json().then(function (problem) {
+          var errors = (problem.errors || []).map(function (e) { return e.message; });
+          message.textContent = errors.length ? errors.join(" ") : problem.detail;
+        });
+      })
+      .catch(function () {
+        message.textContent = "Something went wrong, please try again later.";
+      });
+  });
+
+  script.parentNode.insertBefore(form, script.nextSibling);
+})();
@@ -0,0 +1,250 @@
+use crate::helpers::{spawn_app, TestApp};
+use reqwest::Method;
+use sqlx::query;
+
+async fn create_subscriber(app: &TestApp, name: &str, email: &str) -> String {
+    app.post_subscription_json(&serde_json::json!({ "name": name, "email": email }))
+        .await
+        .error_for_status()
+        .unwrap();
+    query!("SELECT id FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id
+        .to_string()
+}
+
+async fn list(app: &TestApp, query: &str) -> serde_json::Value {
+    app.api_request(Method::GET, &format!("/subscribers{}", query))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn the_api_requires_authentication() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/api/v1/subscribers", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        response.headers()["WWW-Authenticate"],
+        r#"Basic realm="api""#
+    );
+}
+
+#[tokio::test]
+async fn subscribers_are_listed_with_the_subscriptions_schema() {
+    let app = spawn_app().await;
+    let id = create_subscriber(&app, "le guin", "ursula@example.com").await;
+
+    let page = list(&app, "").await;
+
+    let subscriber = &page["data"][0];
+    assert_eq!(subscriber["id"], id);
+    assert_eq!(subscriber["email"], "ursula@example.com");
+    assert_eq!(subscriber["canonical_email"], "ursula@example.com");
+    assert_eq!(subscriber["name"], "le guin");
+    assert_eq!(subscriber["status"], "pending_confirmation");
+    assert!(subscriber["subscribed_at"].is_string());
+    assert!(subscriber["source"].is_null());
+    assert!(page["next_cursor"].is_null());
+}
+
+#[tokio::test]
+async fn the_listing_is_paginated_with_a_cursor() {
+    let app = spawn_app().await;
+    for i in 0..5 {
+        create_subscriber(&app, "le guin", &format!("ursula{}@example.com", i)).await;
+    }
+
+    let mut emails = vec![];
+    let mut query = "?limit=2".to_string();
+    loop {
+        let page = list(&app, &query).await;
+        let data = page["data"].as_array().unwrap();
+        assert!(data.len() <= 2);
+        emails.extend(
+            data.iter()
+                .map(|s| s["email"].as_str().unwrap().to_string()),
+        );
+        match page["next_cursor"].as_str() {
+            Some(cursor) => query = format!("?limit=2&cursor={}", cursor),
+            None => break,
+        }
+    }
+
+    let expected: Vec<String> = (0..5).map(|i| format!("ursula{}@example.com", i)).collect();
+    assert_eq!(emails, expected);
+}
+
+#[tokio::test]
+async fn the_listing_can_be_filtered() {
+    let app = spawn_app().await;
+    let confirmed = create_subscriber(&app, "le guin", "ursula@example.com").await;
+    create_subscriber(&app, "butler", "octavia@example.com").await;
+    app.api_request(Method::PATCH, &format!("/subscribers/{}", confirmed))
+        .json(&serde_json::json!({ "status": "confirmed" }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let page = list(&app, "?status=confirmed").await;
+    assert_eq!(page["data"].as_array().unwrap().len(), 1);
+    assert_eq!(page["data"][0]["id"], confirmed);
+
+    let page = list(&app, "?email=OCTAVIA").await;
+    assert_eq!(page["data"].as_array().unwrap().len(), 1);
+    assert_eq!(page["data"][0]["email"], "octavia@example.com");
+
+    let page = list(&app, "?subscribed_after=2999-01-01T00:00:00Z").await;
+    assert!(page["data"].as_array().unwrap().is_empty());
+    let page = list(&app, "?subscribed_before=2999-01-01T00:00:00Z").await;
+    assert_eq!(page["data"].as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn invalid_listing_parameters_are_rejected() {
+    let app = spawn_app().await;
+
+    for query in [
+        "?limit=0",
+        "?limit=1000",
+        "?cursor=garbage",
+        "?status=bogus",
+    ] {
+        let response = app
+            .api_request(Method::GET, &format!("/subscribers{}", query))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 400, "{}", query);
+    }
+}
+
+#[tokio::test]
+async fn a_single_subscriber_can_be_fetched_updated_and_deleted() {
+    let app = spawn_app().await;
+    let id = create_subscriber(&app, "le guin", "ursula@example.com").await;
+    let path = format!("/subscribers/{}", id);
+
+    let subscriber: serde_json::Value = app
+        .api_request(Method::GET, &path)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(subscriber["name"], "le guin");
+
+    let subscriber: serde_json::Value = app
+        .api_request(Method::PATCH, &path)
+        .json(&serde_json::json!({ "name": "Ursula K. Le Guin", "email": "Ursula@Example.org" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(subscriber["name"], "Ursula K. Le Guin");
+    assert_eq!(subscriber["email"], "Ursula@example.org");
+    assert_eq!(subscriber["canonical_email"], "ursula@example.org");
+    assert_eq!(subscriber["status"], "pending_confirmation");
+
+    let response = app.api_request(Method::DELETE, &path).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 204);
+    let response = app.api_request(Method::GET, &path).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn deleting_a_subscriber_drops_the_emails_queued_for_them() {
+    let app = spawn_app().await;
+    // Their confirmation emails are still in the outbox.
+    let id = create_subscriber(&app, "le guin", "ursula@example.com").await;
+    create_subscriber(&app, "butler", "octavia@example.com").await;
+
+    let response = app
+        .api_request(Method::DELETE, &format!("/subscribers/{}", id))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 204);
+    let recipients: Vec<String> = query!("SELECT recipient_email FROM email_outbox")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.recipient_email)
+        .collect();
+    assert_eq!(recipients, vec!["octavia@example.com".to_string()]);
+}
+
+#[tokio::test]
+async fn updating_with_an_address_in_use_is_a_conflict() {
+    let app = spawn_app().await;
+    let id = create_subscriber(&app, "le guin", "ursula@example.com").await;
+    create_subscriber(&app, "butler", "octavia@example.com").await;
+
+    let response = app
+        .api_request(Method::PATCH, &format!("/subscribers/{}", id))
+        .json(&serde_json::json!({ "email": "OCTAVIA@example.com" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn invalid_updates_are_rejected() {
+    let app = spawn_app().await;
+    let id = create_subscriber(&app, "le guin", "ursula@example.com").await;
+
+    for body in [
+        serde_json::json!({ "name": "" }),
+        serde_json::json!({ "email": "not-an-email" }),
+        serde_json::json!({ "status": "bogus" }),
+        serde_json::json!({ "subscribed_at": "2020-01-01T00:00:00Z" }),
+    ] {
+        let response = app
+            .api_request(Method::PATCH, &format!("/subscribers/{}", id))
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 400, "{}", body);
+    }
+}
+
+#[tokio::test]
+async fn unknown_subscribers_are_not_found() {
+    let app = spawn_app().await;
+    let path = format!("/subscribers/{}", uuid::Uuid::new_v4());
+
+    for method in [Method::GET, Method::PATCH, Method::DELETE] {
+        let response = app
+            .api_request(method.clone(), &path)
+            .json(&serde_json::json!({}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 404, "{}", method);
+    }
+}
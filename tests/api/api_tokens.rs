@@ -0,0 +1,183 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use sqlx::query;
+
+async fn list_subscribers(app: &TestApp, token: &str) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}/api/v1/subscribers", app.address))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_access_the_admin_area() {
+    let app = spawn_app().await;
+
+    for path in ["/dashboard", "/api_tokens"] {
+        let response = app
+            .api_client
+            .get(format!("{}/admin{}", app.address, path))
+            .send()
+            .await
+            .unwrap();
+        assert_is_redirect_to(&response, "/login");
+    }
+}
+
+#[tokio::test]
+async fn logging_in_leads_to_the_admin_dashboard() {
+    let app = spawn_app().await;
+
+    app.login().await;
+
+    let html = app.get_admin_html("/dashboard").await;
+    assert!(html.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn logging_out_ends_the_session() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app.post_admin_form("/logout", &[("", "")]).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app
+        .api_client
+        .get(format!("{}/admin/dashboard", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn minted_tokens_are_accepted_as_bearer_tokens() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let token = app
+        .mint_api_token("CI pipeline", &["subscribers:read"])
+        .await;
+    let response = list_subscribers(&app, &token).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let stored = query!("SELECT token_hash, scopes, last_used_at FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(stored.token_hash, token);
+    assert_eq!(stored.scopes, vec!["subscribers:read".to_string()]);
+    assert!(stored.last_used_at.is_some());
+    let html = app.get_admin_html("/api_tokens").await;
+    assert!(html.contains("CI pipeline"));
+    assert!(!html.contains(&token));
+}
+
+#[tokio::test]
+async fn tokens_are_limited_to_their_scopes() {
+    let app = spawn_app().await;
+    app.login().await;
+    let token = app.mint_api_token("read only", &["subscribers:read"]).await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/newsletters", app.address))
+        .bearer_auth(&token)
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "text": "text", "html": "<p>html</p>" },
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["required_scope"], "newsletters:publish");
+
+    let response = app
+        .api_client
+        .delete(format!(
+            "{}/api/v1/subscribers/{}",
+            app.address,
+            uuid::Uuid::new_v4()
+        ))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn a_publish_token_can_publish_newsletters() {
+    let app = spawn_app().await;
+    app.login().await;
+    let token = app.mint_api_token("CI", &["newsletters:publish"]).await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/newsletters", app.address))
+        .bearer_auth(&token)
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "text": "text", "html": "<p>html</p>" },
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn unknown_expired_and_revoked_tokens_are_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = list_subscribers(&app, "z2p_not-a-real-token").await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let token = app
+        .mint_api_token("short lived", &["subscribers:read"])
+        .await;
+    query!("UPDATE api_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let response = list_subscribers(&app, &token).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let token = app.mint_api_token("revoked", &["subscribers:read"]).await;
+    let token_id = query!("SELECT id FROM api_tokens WHERE name = 'revoked'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    let response = app
+        .post_admin_form(&format!("/api_tokens/{}/revoke", token_id), &[("", "")])
+        .await;
+    assert_is_redirect_to(&response, "/admin/api_tokens");
+    let response = list_subscribers(&app, &token).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn invalid_token_forms_are_reported() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app
+        .post_admin_form("/api_tokens", &[("name", "no scope")])
+        .await;
+    assert_is_redirect_to(&response, "/admin/api_tokens");
+    let html = app.get_admin_html("/api_tokens").await;
+    assert!(html.contains("A token needs at least one scope."));
+    let count = query!("SELECT count(*) AS \"count!\" FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(count, 0);
+}
@@ -0,0 +1,187 @@
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+use reqwest::Method;
+
+async fn audit_log(app: &TestApp, query: &str) -> serde_json::Value {
+    app.api_request(Method::GET, &format!("/audit_log{}", query))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn failed_and_successful_logins_are_audited() {
+    let app = spawn_app_with(|c| c.rate_limit.login_delay_base_ms = 0).await;
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": "not-the-password",
+    }))
+    .await;
+    app.post_login(&serde_json::json!({
+        "username": "nobody",
+        "password": "not-the-password",
+    }))
+    .await;
+    app.login().await;
+
+    let failed = audit_log(&app, "?action=login.failed").await;
+    let failed = failed["data"].as_array().unwrap();
+    assert_eq!(failed.len(), 2);
+    // Newest first.
+    assert_eq!(failed[0]["actor_username"], "nobody");
+    assert!(failed[0]["actor_id"].is_null());
+    assert_eq!(failed[1]["actor_username"], app.test_user.username);
+    assert_eq!(failed[1]["actor_id"], app.test_user.user_id.to_string());
+    assert!(failed[1]["changes"]["reason"].is_string());
+
+    let succeeded = audit_log(&app, "?action=login.succeeded").await;
+    let entry = &succeeded["data"][0];
+    assert_eq!(entry["actor_username"], app.test_user.username);
+    assert!(entry["ip"].is_string());
+}
+
+#[tokio::test]
+async fn subscriber_updates_are_audited_with_what_changed() {
+    let app = spawn_app().await;
+    app.post_subscription_json(&serde_json::json!({
+        "name": "le guin",
+        "email": "ursula@example.com",
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    let id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+
+    app.api_request(Method::PATCH, &format!("/subscribers/{}", id))
+        .json(&serde_json::json!({ "name": "Ursula K. Le Guin" }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let page = audit_log(&app, "?action=subscriber.updated").await;
+    let entry = &page["data"][0];
+    assert_eq!(entry["target"], id.to_string());
+    assert_eq!(entry["actor_username"], app.test_user.username);
+    assert_eq!(
+        entry["changes"],
+        serde_json::json!({"name": {"from": "le guin", "to": "Ursula K. Le Guin"}})
+    );
+}
+
+#[tokio::test]
+async fn publications_and_exports_are_audited() {
+    let app = spawn_app().await;
+    app.post_newsletter(serde_json::json!({
+        "title": "Newsletter title",
+        "content": { "text": "Newsletter body", "html": "<p>Newsletter body</p>" },
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.api_request(Method::GET, "/subscribers?status=confirmed")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let page = audit_log(&app, "").await;
+    let data = page["data"].as_array().unwrap();
+    assert_eq!(data[0]["action"], "subscribers.exported");
+    assert_eq!(data[0]["changes"]["filters"]["status"], "confirmed");
+    assert_eq!(data[1]["action"], "newsletter.published");
+    assert_eq!(data[1]["target"], "Newsletter title");
+}
+
+#[tokio::test]
+async fn token_creation_is_audited_without_the_token() {
+    let app = spawn_app().await;
+    app.login().await;
+    let token = app.mint_api_token("CI", &["subscribers:read"]).await;
+
+    let page = audit_log(&app, "?action=api_token.created").await;
+    let entry = &page["data"][0];
+    assert_eq!(entry["changes"]["name"], "CI");
+    assert_eq!(
+        entry["changes"]["scopes"],
+        serde_json::json!(["subscribers:read"])
+    );
+    assert!(!entry.to_string().contains(&token));
+}
+
+#[tokio::test]
+async fn the_audit_log_is_paged() {
+    let app = spawn_app().await;
+    for _ in 0..3 {
+        app.api_request(Method::GET, "/subscribers")
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    let first = audit_log(&app, "?limit=2").await;
+    assert_eq!(first["data"].as_array().unwrap().len(), 2);
+    let cursor = first["next_cursor"].as_str().unwrap();
+    let second = audit_log(&app, &format!("?limit=2&cursor={}", cursor)).await;
+    assert_eq!(second["data"].as_array().unwrap().len(), 1);
+    assert!(second["next_cursor"].is_null());
+    assert!(second["data"][0]["id"].as_i64() < first["data"][1]["id"].as_i64());
+}
+
+#[tokio::test]
+async fn unknown_actions_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_request(Method::GET, "/audit_log?action=coffee.brewed")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn only_owners_can_read_the_audit_log() {
+    let app = spawn_app().await;
+    app.login().await;
+    app.set_role("editor").await;
+
+    let response = app
+        .api_request(Method::GET, "/audit_log")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+    let response = app
+        .api_client
+        .get(format!("{}/admin/audit_log", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn owners_can_browse_the_audit_log() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let dashboard = app.get_admin_html("/dashboard").await;
+    assert!(dashboard.contains(r#"href="/admin/audit_log""#));
+    let html = app.get_admin_html("/audit_log").await;
+    assert!(html.contains("login.succeeded"));
+    assert!(html.contains(&app.test_user.username));
+}
@@ -0,0 +1,156 @@
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+fn newsletter_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+#[tokio::test]
+async fn analysts_can_read_but_not_modify_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_role("analyst").await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscription("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+
+    // Act
+    let list = app
+        .api_request(reqwest::Method::GET, "/subscribers")
+        .send()
+        .await
+        .unwrap();
+    let update = app
+        .api_request(
+            reqwest::Method::PATCH,
+            &format!("/subscribers/{}", subscriber_id),
+        )
+        .json(&serde_json::json!({ "name": "Ursula" }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(list.status().as_u16(), 200);
+    assert_eq!(update.status().as_u16(), 403);
+    let problem: serde_json::Value = update.json().await.unwrap();
+    assert_eq!(
+        problem["detail"],
+        "Your role does not allow you to manage subscribers."
+    );
+}
+
+#[tokio::test]
+async fn analysts_cannot_publish_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_role("analyst").await;
+
+    // Act
+    let response = app.post_newsletter(newsletter_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn editors_can_publish_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_role("editor").await;
+
+    // Act
+    let response = app.post_newsletter(newsletter_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn a_token_loses_the_scopes_its_owner_s_role_no_longer_permits() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login().await;
+    let token = app
+        .mint_api_token("CI", &["newsletters:publish", "subscribers:read"])
+        .await;
+    app.set_role("analyst").await;
+
+    // Act
+    let publish = app
+        .api_client
+        .post(format!("{}/newsletters", app.address))
+        .bearer_auth(&token)
+        .json(&newsletter_body())
+        .send()
+        .await
+        .unwrap();
+    let list = app
+        .api_client
+        .get(format!("{}/api/v1/subscribers", app.address))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(publish.status().as_u16(), 403);
+    assert_eq!(list.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn analysts_are_only_offered_the_scopes_of_their_role() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_role("analyst").await;
+    app.login().await;
+
+    // Act - Part 1 - The form
+    let html = app.get_admin_html("/api_tokens").await;
+    assert!(html.contains(r#"value="subscribers:read""#));
+    assert!(!html.contains(r#"value="subscribers:write""#));
+
+    // Act - Part 2 - A forged submission
+    let response = app
+        .post_admin_form(
+            "/api_tokens",
+            &[("name", "sneaky"), ("scope", "subscribers:write")],
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/api_tokens");
+    let html = app.get_admin_html("/api_tokens").await;
+    assert!(html.contains("Your role does not allow you to manage subscribers."));
+    assert!(html.contains("You have no API tokens yet."));
+}
+
+#[tokio::test]
+async fn the_dashboard_shows_the_role() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_role("editor").await;
+    app.login().await;
+
+    // Act
+    let html = app.get_admin_html("/dashboard").await;
+
+    // Assert
+    assert!(html.contains("You are signed in as editor."));
+}
@@ -0,0 +1,199 @@
+use crate::helpers::{assert_is_redirect_to, csrf_token, spawn_app};
+
+/// The `base_url` of the test configuration.
+const OWN_ORIGIN: &str = "http://127.0.0.1";
+
+#[tokio::test]
+async fn forms_without_a_csrf_token_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.csrf_token().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/login", app.address))
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    let dashboard = app
+        .api_client
+        .get(format!("{}/admin/dashboard", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&dashboard, "/login");
+}
+
+#[tokio::test]
+async fn the_token_is_accepted_as_a_form_field() {
+    // Arrange
+    let app = spawn_app().await;
+    let csrf_token = app.csrf_token().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/login", app.address))
+        .form(&serde_json::json!({
+            "csrf_token": csrf_token,
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn a_token_from_another_session_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login().await;
+    let attacker = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    let attacker_token = csrf_token(&attacker, &app.address).await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/admin/api_tokens", app.address))
+        .form(&serde_json::json!({
+            "csrf_token": attacker_token,
+            "name": "planted",
+            "scope": "subscribers:read",
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    assert!(!app.get_admin_html("/api_tokens").await.contains("planted"));
+}
+
+#[tokio::test]
+async fn subscription_forms_need_a_token_but_json_does_not() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let form = app
+        .api_client
+        .post(format!("{}/subscriptions", app.address))
+        .form(&[("name", "le guin"), ("email", "ursula_le_guin@gmail.com")])
+        .send()
+        .await
+        .unwrap();
+    let json = app
+        .post_subscription_json(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(form.status().as_u16(), 403);
+    assert_eq!(json.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn the_rejection_page_is_in_the_language_of_the_browser() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/subscriptions", app.address))
+        .header("Accept-Language", "fr")
+        .form(&[("name", "le guin"), ("email", "ursula_le_guin@gmail.com")])
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(r#"<html lang="fr">"#));
+    assert!(html_page.contains("Ce formulaire a expiré"));
+}
+
+#[tokio::test]
+async fn the_widget_form_needs_no_session_when_posted_from_our_origin() {
+    // Arrange
+    let app = spawn_app().await;
+    // As from a cross-site iframe: no session cookie.
+    let client = reqwest::Client::new();
+    let post_from = |origin: &'static str| {
+        client
+            .post(format!("{}/subscriptions", app.address))
+            .header("Origin", origin)
+            .form(&[("name", "le guin"), ("email", "ursula_le_guin@gmail.com")])
+            .send()
+    };
+
+    // Act
+    let other_site = post_from("https://evil.example.com").await.unwrap();
+    let own_origin = post_from(OWN_ORIGIN).await.unwrap();
+
+    // Assert
+    assert_eq!(other_site.status().as_u16(), 403);
+    assert_eq!(own_origin.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn only_the_embedded_forms_are_accepted_by_origin() {
+    // Arrange
+    let app = spawn_app().await;
+    app.csrf_token().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/login", app.address))
+        .header("Origin", OWN_ORIGIN)
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn rendered_forms_carry_the_session_token() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login().await;
+    let csrf_token = app.csrf_token().await;
+    let hidden_field = format!(r#"name="csrf_token" value="{}""#, csrf_token);
+
+    // Act
+    let pages = [
+        app.get_admin_html("/dashboard").await,
+        app.get_admin_html("/api_tokens").await,
+        app.get_admin_html("/users").await,
+    ];
+
+    // Assert
+    for html in pages {
+        assert!(html.contains(&hidden_field));
+    }
+}
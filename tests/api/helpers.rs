@@ -3,7 +3,9 @@ use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
 use once_cell::sync::Lazy;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use wiremock::MockServer;
-use zero2prod::configuration::{get_configuration, DatabaseSettings};
+use zero2prod::configuration::{get_configuration, DatabaseSettings, Settings};
+use zero2prod::email_client::EmailClient;
+use zero2prod::email_outbox::{try_execute_task, ExecutionOutcome};
 use zero2prod::startup::{get_connection_pool, Application};
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
@@ -14,17 +16,45 @@ pub struct TestApp {
     pub port: u16,
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
+    pub email_client: EmailClient,
 }
 
 impl TestApp {
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_execute_task(&self.db_pool, &self.email_client)
+                    .await
+                    .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    /// The CSRF token of the session of `api_client`.
+    pub async fn csrf_token(&self) -> String {
+        csrf_token(&self.api_client, &self.address).await
+    }
+
     pub async fn post_subscription(&self, body: String) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("X-CSRF-Token", self.csrf_token().await)
             .body(body)
             .send()
             .await
-            .expect("FAiled to execute request in test APP")
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_subscription_json(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/subscriptions", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
     }
 
     pub async fn get_confirmation_links(
@@ -38,27 +68,95 @@ impl TestApp {
 
     pub async fn post_newsletter(&self, body: serde_json::Value) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/newsletters", self.address))
+            .post(format!("{}/newsletters", self.address))
             .basic_auth(&self.test_user.username, Some(&self.test_user.password))
             .json(&body)
             .send()
             .await
             .expect("request failed")
     }
+    /// A request to the versioned API, authenticated as the test user.
+    pub fn api_request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.api_client
+            .request(method, format!("{}/api/v1{}", self.address, path))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+    }
+
     pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
     where
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/login", &self.address))
+            .post(format!("{}/login", &self.address))
+            .header("X-CSRF-Token", self.csrf_token().await)
             .form(body)
             .send()
             .await
             .expect("Failed to execute request.")
     }
+    /// Log in through the login form as the test user.
+    pub async fn login(&self) {
+        let response = self
+            .post_login(&serde_json::json!({
+                "username": &self.test_user.username,
+                "password": &self.test_user.password,
+            }))
+            .await;
+        assert_is_redirect_to(&response, "/admin/dashboard");
+    }
+
+    pub async fn get_admin_html(&self, path: &str) -> String {
+        self.api_client
+            .get(format!("{}/admin{}", &self.address, path))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_admin_form<Body>(&self, path: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize + ?Sized,
+    {
+        self.api_client
+            .post(format!("{}/admin{}", &self.address, path))
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Mint an API token through the admin UI and return it.
+    pub async fn mint_api_token(&self, name: &str, scopes: &[&str]) -> String {
+        let mut form: Vec<(&str, &str)> = vec![("name", name), ("expires_in_days", "")];
+        form.extend(scopes.iter().map(|scope| ("scope", *scope)));
+        let response = self.post_admin_form("/api_tokens", &form).await;
+        assert_eq!(response.status().as_u16(), 200);
+        let html = response.text().await.unwrap();
+        let start =
+            html.find(r#"<code id="api-token">"#).unwrap() + r#"<code id="api-token">"#.len();
+        let end = start + html[start..].find("</code>").unwrap();
+        html[start..end].to_string()
+    }
+
+    /// Give the test user another role, e.g. `analyst`.
+    pub async fn set_role(&self, role: &str) {
+        sqlx::query!(
+            "UPDATE users SET role = $1 WHERE user_id = $2",
+            role,
+            self.test_user.user_id
+        )
+        .execute(&self.db_pool)
+        .await
+        .expect("Failed to change the role");
+    }
+
     pub async fn get_login_html(&self) -> String {
         self.api_client
-            .get(&format!("{}/login", &self.address))
+            .get(format!("{}/login", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -85,9 +183,9 @@ impl ConfirmationLinks {
             assert_eq!(links.len(), 1);
             links[0].as_str().to_owned()
         };
-        let html = reqwest::Url::parse(&get_links(&body["HtmlBody"].as_str().unwrap())).unwrap();
+        let html = reqwest::Url::parse(&get_links(body["HtmlBody"].as_str().unwrap())).unwrap();
         let plain_text =
-            reqwest::Url::parse(&get_links(&body["TextBody"].as_str().unwrap())).unwrap();
+            reqwest::Url::parse(&get_links(body["TextBody"].as_str().unwrap())).unwrap();
 
         Self { html, plain_text }
     }
@@ -110,6 +208,11 @@ static TRACING: Lazy<()> = Lazy::new(|| {
 });
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawn the application after letting the caller tweak its configuration.
+pub async fn spawn_app_with(customize: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
 
     let email_server = MockServer::start().await;
@@ -119,6 +222,9 @@ pub async fn spawn_app() -> TestApp {
         c.application.port = 0;
         c.email_client.timeout_ms = 50;
         c.email_client.base_url = email_server.uri();
+        // Rate limiting counters are shared through Redis between all the tests.
+        c.rate_limit.key_prefix = uuid::Uuid::new_v4().to_string();
+        customize(&mut c);
         c
     };
 
@@ -135,7 +241,7 @@ pub async fn spawn_app() -> TestApp {
         .await
         .expect("DAuild to build Application");
     let application_port = application.port();
-    let _ = tokio::spawn(application.run_until_stopped());
+    tokio::spawn(application.run_until_stopped());
 
     let test_user = TestUser::generate();
     let db_pool = get_connection_pool(&configuration.database);
@@ -147,6 +253,7 @@ pub async fn spawn_app() -> TestApp {
         port: application_port,
         test_user,
         api_client,
+        email_client: configuration.email_client.client(),
     };
     test_app
 }
@@ -154,14 +261,14 @@ pub async fn spawn_app() -> TestApp {
 async fn configure_database(config: &DatabaseSettings) -> PgPool {
     PgConnection::connect_with(&config.without_db())
         .await
-        .expect("Echec de connection à Postgre")
+        .expect("Failed to connect to Postgres")
         .execute(format!(r#"CREATE DATABASE "{}";"#, config.database_name).as_str())
         .await
-        .expect("Echec de création de la bdd de test");
+        .expect("Failed to create the test database");
 
     let connection_pool = PgPool::connect_with(config.with_db())
         .await
-        .expect("Echec de création du pool");
+        .expect("Failed to connect to the test database");
     sqlx::migrate!("./migrations")
         .run(&connection_pool)
         .await
@@ -174,6 +281,7 @@ pub struct TestUser {
     pub user_id: uuid::Uuid,
     pub username: String,
     pub password: String,
+    pub email: String,
 }
 
 impl TestUser {
@@ -182,6 +290,7 @@ impl TestUser {
             user_id: uuid::Uuid::new_v4(),
             username: uuid::Uuid::new_v4().to_string(),
             password: uuid::Uuid::new_v4().to_string(),
+            email: format!("{}@example.com", uuid::Uuid::new_v4()),
         }
     }
     pub async fn store(&self, pool: &PgPool) {
@@ -196,19 +305,37 @@ impl TestUser {
         .to_string();
         sqlx::query!(
             r#"
-        INSERT INTO users (user_id, username, password_hash)
-        VALUES ($1,$2,$3)
+        INSERT INTO users (user_id, username, password_hash, email)
+        VALUES ($1,$2,$3,$4)
         "#,
             self.user_id,
             self.username,
-            password_hash
+            password_hash,
+            self.email
         )
         .execute(pool)
         .await
-        .expect("FAiled to store user");
+        .expect("Failed to store the test user");
     }
 }
 
+/// The CSRF token of the session of `client`, read from the login form.
+pub async fn csrf_token(client: &reqwest::Client, address: &str) -> String {
+    let html = client
+        .get(format!("{}/login", address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap();
+    let start = html
+        .find(r#"name="csrf_token" value=""#)
+        .expect("The login form has no CSRF token")
+        + r#"name="csrf_token" value=""#.len();
+    html[start..start + html[start..].find('"').unwrap()].to_string()
+}
+
 pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
     assert_eq!(response.status().as_u16(), 303);
     assert_eq!(response.headers().get("Location").unwrap(), location);
@@ -0,0 +1,228 @@
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+use reqwest::Method;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::i18n::Locale;
+
+async fn subscribe(
+    app: &TestApp,
+    accept_language: Option<&str>,
+    form: &[(&str, &str)],
+) -> reqwest::Response {
+    let mut request = app
+        .api_client
+        .post(format!("{}/subscriptions", app.address))
+        .header("X-CSRF-Token", app.csrf_token().await)
+        .form(form);
+    if let Some(accept_language) = accept_language {
+        request = request.header("Accept-Language", accept_language);
+    }
+    request.send().await.unwrap()
+}
+
+async fn stored_locale(app: &TestApp) -> String {
+    sqlx::query!("SELECT locale FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .locale
+}
+
+/// The bodies of the emails sent so far, oldest first.
+async fn sent_emails(app: &TestApp) -> Vec<serde_json::Value> {
+    app.dispatch_all_pending_emails().await;
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| serde_json::from_slice(&request.body).unwrap())
+        .collect()
+}
+
+async fn mock_email_server(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+const URSULA: [(&str, &str); 2] = [("name", "le guin"), ("email", "ursula_le_guin@gmail.com")];
+
+#[tokio::test]
+async fn subscribers_are_written_to_in_the_language_of_their_browser() {
+    let app = spawn_app().await;
+    mock_email_server(&app).await;
+
+    let response = subscribe(&app, Some("fr-CH, fr;q=0.9, en;q=0.8"), &URSULA).await;
+
+    assert_eq!(response.status().as_u16(), 201);
+    assert_eq!(stored_locale(&app).await, "fr");
+    let email = &sent_emails(&app).await[0];
+    assert_eq!(email["Subject"], "Bienvenue !");
+    assert!(email["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("Bienvenue dans notre newsletter !"));
+    assert!(email["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains("Cliquez ici pour confirmer votre abonnement."));
+}
+
+#[tokio::test]
+async fn the_locale_field_takes_precedence_over_the_browser() {
+    let app = spawn_app().await;
+
+    subscribe(&app, Some("fr"), &[URSULA[0], URSULA[1], ("locale", "en")]).await;
+
+    assert_eq!(stored_locale(&app).await, "en");
+}
+
+#[tokio::test]
+async fn the_default_locale_is_used_when_none_is_supported() {
+    let app = spawn_app_with(|c| c.i18n.default_locale = Locale::Fr).await;
+
+    subscribe(
+        &app,
+        Some("de, es;q=0.5"),
+        &[URSULA[0], URSULA[1], ("locale", "it")],
+    )
+    .await;
+
+    assert_eq!(stored_locale(&app).await, "fr");
+}
+
+#[tokio::test]
+async fn validation_errors_are_translated() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscription_json(&serde_json::json!({
+            "name": "<script>",
+            "email": "ursula@mailinator.com",
+            "locale": "fr",
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["title"], "Données d'inscription invalides");
+    assert_eq!(
+        body["errors"],
+        serde_json::json!([
+            {
+                "field": "name",
+                "code": "forbidden_character",
+                "message": "Le nom contient le caractère interdit `<`."
+            },
+            {
+                "field": "email",
+                "code": "disposable_domain",
+                "message": "Les adresses e-mail de `mailinator.com` ne sont pas acceptées."
+            }
+        ])
+    );
+}
+
+#[tokio::test]
+async fn confirmation_uses_the_locale_of_the_subscriber() {
+    let app = spawn_app().await;
+    mock_email_server(&app).await;
+    subscribe(&app, Some("fr"), &URSULA).await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let links = app.get_confirmation_links(email_request).await;
+
+    // Followed from a browser set to English.
+    let html = app
+        .api_client
+        .get(links.html)
+        .header("Accept-Language", "en")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    assert!(html.contains(r#"<html lang="fr">"#));
+    assert!(html.contains("Merci le guin, votre abonnement est confirmé !"));
+    let welcome_email = &sent_emails(&app).await[1];
+    assert_eq!(welcome_email["Subject"], "Votre abonnement est confirmé");
+    assert!(welcome_email["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("Bonjour le guin,"));
+}
+
+#[tokio::test]
+async fn confirmation_errors_are_in_the_language_of_the_browser() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .get(format!(
+            "{}/subscriptions/confirm?subscription_token=unknown",
+            app.address
+        ))
+        .header("Accept-Language", "fr")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Lien de confirmation inconnu"));
+}
+
+#[tokio::test]
+async fn the_widget_is_translated_and_posts_its_locale() {
+    let app = spawn_app().await;
+
+    let html = app
+        .api_client
+        .get(format!("{}/widget?locale=fr", app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    assert!(html.contains("Adresse e-mail"));
+    assert!(html.contains(r#"name="locale" value="fr""#));
+}
+
+#[tokio::test]
+async fn the_locale_of_a_subscriber_can_be_changed_through_the_api() {
+    let app = spawn_app().await;
+    subscribe(&app, None, &URSULA).await;
+    let id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    let patch = |locale: &'static str| {
+        app.api_request(Method::PATCH, &format!("/subscribers/{}", id))
+            .json(&serde_json::json!({ "locale": locale }))
+            .send()
+    };
+
+    let response = patch("fr").await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let subscriber: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(subscriber["locale"], "fr");
+
+    let response = patch("klingon").await.unwrap();
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(stored_locale(&app).await, "fr");
+}
@@ -1,4 +1,4 @@
-use crate::helpers::{assert_is_redirect_to, spawn_app};
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with};
 use serde_json::json;
 
 #[tokio::test]
@@ -19,3 +19,226 @@ async fn an_error_flash_message_is_set_on_failure() {
     let html_page = app.get_login_html().await;
     assert!(!html_page.contains("<p><i>Authentication login Failed</i></p>"));
 }
+
+#[tokio::test]
+async fn flash_messages_are_shown_with_their_level() {
+    let app = spawn_app().await;
+    app.login().await;
+    app.post_admin_form("/logout", &json!({})).await;
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(r#"<div class="flash flash-info" role="status">"#));
+    assert!(html_page.contains("<p><i>You have successfully logged out.</i></p>"));
+
+    app.post_login(&json!({"username": "random-username", "password": "random-password"}))
+        .await;
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(r#"<div class="flash flash-error" role="alert">"#));
+}
+
+#[tokio::test]
+async fn a_username_is_locked_out_after_too_many_failures() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.rate_limit.login_failures_per_username = 3;
+        c.rate_limit.login_delay_base_ms = 0;
+    })
+    .await;
+    let wrong_login = json!({
+        "username": &app.test_user.username,
+        "password": "wrong-password",
+    });
+    for _ in 0..3 {
+        let response = app.post_login(&wrong_login).await;
+        assert_is_redirect_to(&response, "/login");
+    }
+
+    // Act - even the right password is refused
+    let response = app
+        .post_login(&json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+
+    // Assert - with the same message as a wrong password
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Authentication login Failed</i></p>"));
+}
+
+#[tokio::test]
+async fn a_successful_login_resets_the_failure_count() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.rate_limit.login_failures_per_username = 3;
+        c.rate_limit.login_delay_base_ms = 0;
+    })
+    .await;
+    let wrong_login = json!({
+        "username": &app.test_user.username,
+        "password": "wrong-password",
+    });
+    for _ in 0..2 {
+        app.post_login(&wrong_login).await;
+    }
+    app.login().await;
+
+    // Act
+    for _ in 0..2 {
+        app.post_login(&wrong_login).await;
+    }
+
+    // Assert
+    app.login().await;
+}
+
+#[tokio::test]
+async fn repeated_failures_are_throttled() {
+    // Arrange
+    let app = spawn_app_with(|c| c.rate_limit.login_delay_base_ms = 60_000).await;
+    let wrong_login = json!({
+        "username": &app.test_user.username,
+        "password": "wrong-password",
+    });
+    // The first failure is free, the second one imposes a wait.
+    for _ in 0..2 {
+        app.post_login(&wrong_login).await;
+    }
+
+    // Act
+    let response = app
+        .post_login(&json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn an_ip_is_locked_out_across_usernames() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.rate_limit.login_failures_per_ip = 3;
+        c.rate_limit.login_delay_base_ms = 0;
+    })
+    .await;
+    for i in 0..3 {
+        app.post_login(&json!({
+            "username": format!("someone-{}", i),
+            "password": "wrong-password",
+        }))
+        .await;
+    }
+
+    // Act
+    let response = app
+        .post_login(&json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_spoofed_forwarded_for_header_does_not_escape_the_ip_lockout() {
+    let app = spawn_app_with(|c| {
+        c.rate_limit.login_failures_per_ip = 3;
+        c.rate_limit.login_delay_base_ms = 0;
+    })
+    .await;
+    let post_login_from = |forwarded_for: String, username: String, password: String| {
+        let app = &app;
+        async move {
+            app.api_client
+                .post(format!("{}/login", &app.address))
+                .header("X-CSRF-Token", app.csrf_token().await)
+                .header("X-Forwarded-For", forwarded_for)
+                .form(&json!({ "username": username, "password": password }))
+                .send()
+                .await
+                .unwrap()
+        }
+    };
+    for i in 0..3 {
+        post_login_from(
+            format!("198.51.100.{}", i),
+            format!("someone-{}", i),
+            "wrong-password".into(),
+        )
+        .await;
+    }
+
+    let response = post_login_from(
+        "203.0.113.7".into(),
+        app.test_user.username.clone(),
+        app.test_user.password.clone(),
+    )
+    .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+async fn stored_password_hash(app: &crate::helpers::TestApp) -> String {
+    sqlx::query!(
+        "SELECT password_hash FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .password_hash
+    .unwrap()
+}
+
+#[tokio::test]
+async fn a_successful_login_upgrades_a_hash_made_with_older_parameters() {
+    // Arrange
+    let app = spawn_app_with(|c| c.password_hashing.memory_kib = 16384).await;
+    assert!(stored_password_hash(&app).await.contains("m=15000"));
+
+    // Act
+    let response = app
+        .post_login(&json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    assert!(stored_password_hash(&app).await.contains("m=16384,t=2,p=1"));
+    // The upgraded hash still matches the password.
+    let response = app
+        .post_login(&json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn a_failed_login_does_not_upgrade_the_hash() {
+    // Arrange
+    let app = spawn_app_with(|c| c.password_hashing.memory_kib = 16384).await;
+    let before = stored_password_hash(&app).await;
+
+    // Act
+    let response = app
+        .post_login(&json!({
+            "username": &app.test_user.username,
+            "password": "wrong-password",
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    assert_eq!(stored_password_hash(&app).await, before);
+}
@@ -1,6 +1,19 @@
+mod api_subscribers;
+mod api_tokens;
+mod audit_log;
+mod authorization;
+mod csrf;
 mod health_check;
 mod helpers;
+mod localization;
+mod login;
+mod newsletter;
+mod password_reset;
+mod security_headers;
+mod sessions;
+mod signup_challenge;
 mod subscriptions;
 mod subscriptions_confirm;
-mod newsletter;
-mod login;
\ No newline at end of file
+mod two_factor;
+mod users;
+mod widget;
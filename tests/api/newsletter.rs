@@ -1,4 +1,4 @@
-use crate::helpers::{spawn_app, ConfirmationLinks, TestApp};
+use crate::helpers::{spawn_app, spawn_app_with, ConfirmationLinks, TestApp};
 use uuid::Uuid;
 use wiremock::matchers::{any, method, path};
 use wiremock::{Mock, ResponseTemplate};
@@ -21,6 +21,7 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     });
     let response = app.post_newsletter(newsletter_request_body).await;
     assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
 }
 
 #[tokio::test]
@@ -43,6 +44,40 @@ async fn newsletters_are_delivered_ton_confirmed_subscibers() {
 
     let response = app.post_newsletter(newsletter_request_body).await;
     assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn delivery_failures_do_not_lose_the_publication_report() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletter(serde_json::json!({
+            "title": "Our news",
+            "content": { "text": "News", "html": "<p>News</p>" },
+        }))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        report["variants"],
+        serde_json::json!([{ "locale": "en", "title": "Our news", "recipients": 1 }])
+    );
+    // The issue stays in the outbox, to be retried later on.
+    let queued = sqlx::query!("SELECT n_retries FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.n_retries, 1);
 }
 
 #[tokio::test]
@@ -74,7 +109,11 @@ async fn newsletters_return_400_for_invalid_data() {
 /// Use the public API of the application under test to create
 /// an unconfirmed subscriber.
 async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    create_unconfirmed_subscriber_from(app, "name=le%20guin&email=ursula_le_guin%40gmail.com").await
+}
+
+/// `body` is the url-encoded subscription form.
+async fn create_unconfirmed_subscriber_from(app: &TestApp, body: &str) -> ConfirmationLinks {
     let _mock_guard = Mock::given(path("/email"))
         .and(method("POST"))
         .respond_with(ResponseTemplate::new(200))
@@ -82,10 +121,11 @@ async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
         .expect(1)
         .mount_as_scoped(&app.email_server)
         .await;
-    app.post_subscription(body.into())
+    app.post_subscription(body.to_string())
         .await
         .error_for_status()
         .unwrap();
+    app.dispatch_all_pending_emails().await;
     app.get_confirmation_links(
         &app.email_server
             .received_requests()
@@ -104,13 +144,155 @@ async fn create_confirmed_subscriber(app: &TestApp) {
         .unwrap()
         .error_for_status()
         .unwrap();
+    dispatch_welcome_email(app).await;
+}
+
+async fn create_confirmed_subscriber_from(app: &TestApp, body: &str) {
+    let link = create_unconfirmed_subscriber_from(app, body).await.html;
+    reqwest::get(link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    dispatch_welcome_email(app).await;
+}
+
+/// Deliver the welcome email, if any, before the issue gets published.
+async fn dispatch_welcome_email(app: &TestApp) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Welcome a confirmed subscriber")
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+/// The subject of the email received by each recipient.
+async fn received_subjects(app: &TestApp) -> Vec<(String, String)> {
+    let mut subjects: Vec<(String, String)> = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| {
+            let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            (
+                body["To"].as_str().unwrap().to_string(),
+                body["Subject"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect();
+    subjects.sort();
+    subjects
+}
+
+#[tokio::test]
+async fn each_subscriber_receives_the_variant_of_their_locale() {
+    // Only the confirmation emails are expected before the issue.
+    let app = spawn_app_with(|c| c.subscriptions.send_welcome_email = false).await;
+    create_confirmed_subscriber_from(&app, "name=Ursula&email=ursula%40example.com&locale=en")
+        .await;
+    create_confirmed_subscriber_from(&app, "name=Colette&email=colette%40example.com&locale=fr")
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletter(serde_json::json!({
+            "title": "Our news",
+            "content": { "text": "News", "html": "<p>News</p>" },
+            "variants": {
+                "fr": {
+                    "title": "Nos nouvelles",
+                    "content": { "text": "Nouvelles", "html": "<p>Nouvelles</p>" },
+                },
+            },
+        }))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        report["variants"],
+        serde_json::json!([
+            { "locale": "en", "title": "Our news", "recipients": 1 },
+            { "locale": "fr", "title": "Nos nouvelles", "recipients": 1 },
+        ])
+    );
+    let subjects = received_subjects(&app).await;
+    // Along with the confirmation emails of the subscribers.
+    assert!(subjects.contains(&("colette@example.com".into(), "Nos nouvelles".into())));
+    assert!(subjects.contains(&("ursula@example.com".into(), "Our news".into())));
+}
+
+#[tokio::test]
+async fn subscribers_without_a_variant_receive_the_default_one() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber_from(&app, "name=Colette&email=colette%40example.com&locale=fr")
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletter(serde_json::json!({
+            "title": "Our news",
+            "content": { "text": "News", "html": "<p>News</p>" },
+        }))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        report["variants"],
+        serde_json::json!([{ "locale": "en", "title": "Our news", "recipients": 1 }])
+    );
+    assert!(received_subjects(&app)
+        .await
+        .contains(&("colette@example.com".into(), "Our news".into())));
+}
+
+#[tokio::test]
+async fn variants_must_be_in_a_supported_locale_other_than_the_default() {
+    let app = spawn_app().await;
+    let variant = serde_json::json!({
+        "title": "Title",
+        "content": { "text": "Body", "html": "<p>Body</p>" },
+    });
+
+    for locale in ["klingon", "en"] {
+        let response = app
+            .post_newsletter(serde_json::json!({
+                "title": "Our news",
+                "content": { "text": "News", "html": "<p>News</p>" },
+                "variants": { locale: &variant },
+            }))
+            .await;
+
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "With a `{}` variant",
+            locale
+        );
+    }
 }
 
 #[tokio::test]
 async fn requests_missing_authorization_are_rejected() {
     let app = spawn_app().await;
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &app.address))
+        .post(format!("{}/newsletters", &app.address))
         .json(&serde_json::json![{"title":"titre", "content": {
             "text": "text content",
             "html": "<p>html content</p>"
@@ -122,8 +304,15 @@ async fn requests_missing_authorization_are_rejected() {
     assert_eq!(response.status().as_u16(), 401);
     assert_eq!(
         response.headers()["WWW-Authenticate"],
-        r#"Basic realm="publish""#
+        r#"Basic realm="api""#
+    );
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
     );
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["type"], "/problems/unauthorized");
+    assert_eq!(body["status"], 401);
 }
 
 #[tokio::test]
@@ -133,7 +322,7 @@ async fn non_existing_user_is_rejected() {
     let password = Uuid::new_v4();
 
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &app.address))
+        .post(format!("{}/newsletters", &app.address))
         .basic_auth(username, Some(password))
         .json(&serde_json::json![{"title":"titre", "content": {
             "text": "text content",
@@ -146,7 +335,7 @@ async fn non_existing_user_is_rejected() {
     assert_eq!(response.status().as_u16(), 401);
     assert_eq!(
         response.headers()["WWW-Authenticate"],
-        r#"Basic realm="publish""#
+        r#"Basic realm="api""#
     );
 }
 
@@ -159,7 +348,7 @@ async fn invalid_password_is_rejected() {
     let password = Uuid::new_v4().to_string();
     assert_ne!(app.test_user.password, password);
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &app.address))
+        .post(format!("{}/newsletters", &app.address))
         .basic_auth(username, Some(password))
         .json(&serde_json::json!({
         "title": "Newsletter title",
@@ -174,7 +363,40 @@ async fn invalid_password_is_rejected() {
     // Assert
     assert_eq!(401, response.status().as_u16());
     assert_eq!(
-        r#"Basic realm="publish""#,
+        r#"Basic realm="api""#,
         response.headers()["WWW-Authenticate"]
     );
 }
+
+#[tokio::test]
+async fn basic_auth_is_locked_out_after_too_many_failures() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.rate_limit.login_failures_per_username = 3;
+        c.rate_limit.login_delay_base_ms = 0;
+    })
+    .await;
+    let body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    for _ in 0..3 {
+        let response = reqwest::Client::new()
+            .post(format!("{}/newsletters", &app.address))
+            .basic_auth(&app.test_user.username, Some(Uuid::new_v4().to_string()))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        assert_eq!(401, response.status().as_u16());
+    }
+
+    // Act - the right password no longer works
+    let response = app.post_newsletter(body).await;
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+}
@@ -0,0 +1,284 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, csrf_token, spawn_app, spawn_app_with, TestApp};
+
+impl TestApp {
+    async fn post_forgot_password(&self, username: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/login/forgot_password", &self.address))
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .form(&serde_json::json!({ "username": username }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    async fn get_forgot_password_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/login/forgot_password", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    async fn post_reset_password(&self, token: &str, new_password: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/login/reset_password", &self.address))
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .form(&serde_json::json!({
+                "token": token,
+                "new_password": new_password,
+                "new_password_check": new_password,
+            }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Ask for a reset link for the test user, and return the token it carries.
+    async fn request_password_reset_token(&self) -> String {
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&self.email_server)
+            .await;
+        self.post_forgot_password(&self.test_user.username).await;
+        self.dispatch_all_pending_emails().await;
+        let email_request = self
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        let links = self.get_confirmation_links(&email_request).await;
+        assert_eq!(links.html.path(), "/login/reset_password");
+        links
+            .html
+            .query_pairs()
+            .find(|(key, _)| key == "token")
+            .map(|(_, token)| token.into_owned())
+            .unwrap()
+    }
+}
+
+#[tokio::test]
+async fn the_response_is_the_same_for_known_and_unknown_usernames() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let unknown = app.post_forgot_password("not-a-user").await;
+    let unknown_html = app.get_forgot_password_html().await;
+    let known = app.post_forgot_password(&app.test_user.username).await;
+    let known_html = app.get_forgot_password_html().await;
+
+    // Assert
+    assert_is_redirect_to(&unknown, "/login/forgot_password");
+    assert_is_redirect_to(&known, "/login/forgot_password");
+    assert_eq!(unknown_html, known_html);
+    assert!(known_html.contains("If this account exists"));
+    app.dispatch_all_pending_emails().await;
+    let emails = app.email_server.received_requests().await.unwrap();
+    assert_eq!(emails.len(), 1);
+    let body: serde_json::Value = serde_json::from_slice(&emails[0].body).unwrap();
+    assert_eq!(body["To"], app.test_user.email);
+}
+
+#[tokio::test]
+async fn reset_links_are_rate_limited_per_email_without_telling() {
+    // Arrange
+    let app = spawn_app_with(|c| c.rate_limit.password_resets_per_email_per_hour = 1).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_forgot_password(&app.test_user.username).await;
+    let first_html = app.get_forgot_password_html().await;
+    app.post_forgot_password(&app.test_user.username).await;
+    let second_html = app.get_forgot_password_html().await;
+
+    // Assert
+    assert_eq!(first_html, second_html);
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn reset_requests_are_rate_limited_per_ip() {
+    // Arrange
+    let app = spawn_app_with(|c| c.rate_limit.password_resets_per_ip_per_hour = 1).await;
+
+    // Act
+    app.post_forgot_password("not-a-user").await;
+    app.get_forgot_password_html().await;
+    let response = app.post_forgot_password(&app.test_user.username).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login/forgot_password");
+    assert!(app
+        .get_forgot_password_html()
+        .await
+        .contains("Too many password reset requests"));
+    let outbox = sqlx::query!("SELECT id FROM email_outbox")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(outbox.is_empty());
+}
+
+#[tokio::test]
+async fn the_reset_link_sets_a_new_password() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = app.request_password_reset_token().await;
+    let new_password = uuid::Uuid::new_v4().to_string();
+
+    // Act - Part 1 - Follow the link
+    let form = app
+        .api_client
+        .get(format!(
+            "{}/login/reset_password?token={}",
+            &app.address, token
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(form.status().as_u16(), 200);
+    assert!(form.text().await.unwrap().contains(&token));
+
+    // Act - Part 2 - Submit the new password
+    let response = app.post_reset_password(&token, &new_password).await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Assert
+    let old_login = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+    assert_is_redirect_to(&old_login, "/login");
+    let new_login = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&new_login, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn a_reset_token_can_only_be_used_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = app.request_password_reset_token().await;
+    app.post_reset_password(&token, &uuid::Uuid::new_v4().to_string())
+        .await;
+
+    // Act
+    let response = app
+        .post_reset_password(&token, &uuid::Uuid::new_v4().to_string())
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login/forgot_password");
+    let html_page = app.get_forgot_password_html().await;
+    assert!(html_page.contains("This password reset link is invalid or has expired."));
+}
+
+#[tokio::test]
+async fn an_expired_reset_token_is_rejected() {
+    // Arrange
+    let app = spawn_app_with(|c| c.password_reset.token_validity_minutes = 0).await;
+    let token = app.request_password_reset_token().await;
+
+    // Act
+    let response = app
+        .post_reset_password(&token, &uuid::Uuid::new_v4().to_string())
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login/forgot_password");
+    app.login().await;
+}
+
+#[tokio::test]
+async fn mismatching_passwords_do_not_burn_the_token() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = app.request_password_reset_token().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/login/reset_password", &app.address))
+        .header("X-CSRF-Token", app.csrf_token().await)
+        .form(&serde_json::json!({
+            "token": &token,
+            "new_password": uuid::Uuid::new_v4().to_string(),
+            "new_password_check": uuid::Uuid::new_v4().to_string(),
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    let retry_location = format!("/login/reset_password?token={}", token);
+    assert_is_redirect_to(&response, &retry_location);
+    let response = app
+        .post_reset_password(&token, &uuid::Uuid::new_v4().to_string())
+        .await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn resetting_the_password_logs_out_existing_sessions() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login().await;
+    let token = app.request_password_reset_token().await;
+
+    // Act - reset from another browser
+    let other_browser = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    let response = other_browser
+        .post(format!("{}/login/reset_password", &app.address))
+        .header(
+            "X-CSRF-Token",
+            csrf_token(&other_browser, &app.address).await,
+        )
+        .form(&serde_json::json!({
+            "token": &token,
+            "new_password": "a-brand-new-password",
+            "new_password_check": "a-brand-new-password",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+
+    // Assert
+    let response = app
+        .api_client
+        .get(format!("{}/admin/dashboard", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+}
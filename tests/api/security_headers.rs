@@ -0,0 +1,140 @@
+use crate::helpers::{spawn_app, spawn_app_with};
+
+#[tokio::test]
+async fn pages_carry_the_security_headers() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/login", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    let headers = response.headers();
+    assert_eq!(headers["X-Frame-Options"], "DENY");
+    assert_eq!(headers["X-Content-Type-Options"], "nosniff");
+    assert_eq!(
+        headers["Referrer-Policy"],
+        "strict-origin-when-cross-origin"
+    );
+    let policy = headers["Content-Security-Policy"].to_str().unwrap();
+    assert!(policy.contains("default-src 'self'"));
+    assert!(policy.contains("frame-ancestors 'none'"));
+    // Only in production, over HTTPS.
+    assert!(headers.get("Strict-Transport-Security").is_none());
+}
+
+#[tokio::test]
+async fn error_responses_carry_the_security_headers_too() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/login", app.address))
+        .form(&[("username", "someone"), ("password", "something")])
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    assert_eq!(response.headers()["X-Frame-Options"], "DENY");
+}
+
+#[tokio::test]
+async fn the_widget_can_be_framed() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/widget", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    let headers = response.headers();
+    assert!(headers.get("X-Frame-Options").is_none());
+    let policy = headers["Content-Security-Policy"].to_str().unwrap();
+    assert!(policy.contains("default-src 'self'"));
+    assert!(!policy.contains("frame-ancestors"));
+}
+
+#[tokio::test]
+async fn hsts_is_sent_when_configured() {
+    // Arrange
+    let app = spawn_app_with(|c| c.security_headers.hsts_max_age_secs = Some(31536000)).await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/health_check", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(
+        response.headers()["Strict-Transport-Security"],
+        "max-age=31536000; includeSubDomains"
+    );
+}
+
+async fn session_cookie(app: &crate::helpers::TestApp) -> String {
+    let response = app
+        .api_client
+        .get(format!("{}/login", app.address))
+        .send()
+        .await
+        .unwrap();
+    response
+        .headers()
+        .get_all("Set-Cookie")
+        .iter()
+        .map(|h| h.to_str().unwrap().to_string())
+        .find(|cookie| cookie.starts_with("id="))
+        .expect("No session cookie was set")
+}
+
+#[tokio::test]
+async fn the_session_cookie_follows_the_local_policy_by_default() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let cookie = session_cookie(&app).await;
+
+    // Assert
+    assert!(cookie.contains("HttpOnly"));
+    assert!(cookie.contains("SameSite=Lax"));
+    assert!(!cookie.contains("Secure"));
+    assert!(!cookie.contains("Max-Age"));
+}
+
+#[tokio::test]
+async fn the_session_cookie_policy_is_configurable() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.session_cookie.secure = true;
+        c.session_cookie.same_site = zero2prod::configuration::SameSitePolicy::Strict;
+        c.session_cookie.persistent = true;
+        c.session_cookie.max_age_hours = 12;
+    })
+    .await;
+
+    // Act
+    let cookie = session_cookie(&app).await;
+
+    // Assert
+    assert!(cookie.contains("Secure"));
+    assert!(cookie.contains("SameSite=Strict"));
+    assert!(cookie.contains("Max-Age=43200"));
+}
@@ -0,0 +1,174 @@
+use crate::helpers::{assert_is_redirect_to, csrf_token, spawn_app, TestApp, TestUser};
+
+/// A browser of its own, logged in as the test user.
+async fn log_in_elsewhere(app: &TestApp, user_agent: &str) -> reqwest::Client {
+    let browser = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .user_agent(user_agent)
+        .build()
+        .unwrap();
+    let response = browser
+        .post(format!("{}/login", app.address))
+        .header("X-CSRF-Token", csrf_token(&browser, &app.address).await)
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    browser
+}
+
+async fn get_dashboard(app: &TestApp, browser: &reqwest::Client) -> reqwest::Response {
+    browser
+        .get(format!("{}/admin/dashboard", app.address))
+        .send()
+        .await
+        .unwrap()
+}
+
+/// The id of the session listed with `user_agent`.
+fn session_id_for(html: &str, user_agent: &str) -> String {
+    let row = html
+        .split("<tr>")
+        .find(|row| row.contains(user_agent))
+        .expect("The session is not listed");
+    let start = row.find("/admin/sessions/").unwrap() + "/admin/sessions/".len();
+    row[start..start + 36].to_string()
+}
+
+#[tokio::test]
+async fn sessions_are_listed_with_their_browser_and_ip() {
+    // Arrange
+    let app = spawn_app().await;
+    log_in_elsewhere(&app, "Phone browser").await;
+    app.login().await;
+
+    // Act
+    let html = app.get_admin_html("/sessions").await;
+
+    // Assert
+    assert!(html.contains("Phone browser"));
+    assert!(html.contains("127.0.0.1"));
+    assert!(html.contains("this session"));
+    assert_eq!(html.matches("value=\"Revoke\"").count(), 1);
+}
+
+#[tokio::test]
+async fn a_revoked_session_is_logged_out() {
+    // Arrange
+    let app = spawn_app().await;
+    let phone = log_in_elsewhere(&app, "Phone browser").await;
+    app.login().await;
+    let session_id = session_id_for(&app.get_admin_html("/sessions").await, "Phone browser");
+
+    // Act
+    let response = app
+        .post_admin_form(&format!("/sessions/{}/revoke", session_id), &[("", "")])
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/sessions");
+    let html = app.get_admin_html("/sessions").await;
+    assert!(html.contains("The session has been revoked."));
+    assert!(!html.contains("Phone browser"));
+    assert_is_redirect_to(&get_dashboard(&app, &phone).await, "/login");
+    assert_eq!(
+        app.api_client
+            .get(format!("{}/admin/dashboard", app.address))
+            .send()
+            .await
+            .unwrap()
+            .status()
+            .as_u16(),
+        200
+    );
+}
+
+#[tokio::test]
+async fn all_other_sessions_can_be_revoked_at_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let phone = log_in_elsewhere(&app, "Phone browser").await;
+    let laptop = log_in_elsewhere(&app, "Laptop browser").await;
+    app.login().await;
+
+    // Act
+    let response = app
+        .post_admin_form("/sessions/revoke_others", &[("", "")])
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/sessions");
+    let html = app.get_admin_html("/sessions").await;
+    assert!(html.contains("2 other session(s) revoked."));
+    assert!(html.contains("this session"));
+    assert_is_redirect_to(&get_dashboard(&app, &phone).await, "/login");
+    assert_is_redirect_to(&get_dashboard(&app, &laptop).await, "/login");
+}
+
+#[tokio::test]
+async fn sessions_of_other_users_cannot_be_revoked() {
+    // Arrange
+    let app = spawn_app().await;
+    let phone = log_in_elsewhere(&app, "Phone browser").await;
+    let session_id = sqlx::query!("SELECT session_id FROM user_sessions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .session_id;
+    let other = TestUser::generate();
+    other.store(&app.db_pool).await;
+    let browser = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    browser
+        .post(format!("{}/login", app.address))
+        .header("X-CSRF-Token", csrf_token(&browser, &app.address).await)
+        .form(&serde_json::json!({
+            "username": &other.username,
+            "password": &other.password,
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Act
+    browser
+        .post(format!(
+            "{}/admin/sessions/{}/revoke",
+            app.address, session_id
+        ))
+        .header("X-CSRF-Token", csrf_token(&browser, &app.address).await)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(get_dashboard(&app, &phone).await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn logging_out_ends_the_session_record() {
+    // Arrange
+    let app = spawn_app().await;
+    let phone = log_in_elsewhere(&app, "Phone browser").await;
+    app.login().await;
+
+    // Act
+    phone
+        .post(format!("{}/admin/logout", app.address))
+        .header("X-CSRF-Token", csrf_token(&phone, &app.address).await)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    let html = app.get_admin_html("/sessions").await;
+    assert!(!html.contains("Phone browser"));
+}
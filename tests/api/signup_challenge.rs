@@ -0,0 +1,143 @@
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+use secrecy::Secret;
+use wiremock::matchers::{body_string_contains, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::configuration::SignupChallengeSettings;
+use zero2prod::signup_challenge::{solve_proof_of_work, Challenge};
+
+const BODY: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+async fn get_challenge(app: &TestApp) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}/subscriptions/challenge", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn spawn_app_with_remote_verification(verification_server: &MockServer) -> TestApp {
+    let verification_url = format!("{}/siteverify", verification_server.uri());
+    spawn_app_with(|c| {
+        c.signup_challenge = SignupChallengeSettings::Remote {
+            verification_url,
+            secret: Secret::new("verification-secret".into()),
+            timeout_ms: 200,
+        }
+    })
+    .await
+}
+
+#[tokio::test]
+async fn there_is_no_challenge_to_solve_when_disabled() {
+    let app = spawn_app().await;
+
+    let response = get_challenge(&app).await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn a_solved_proof_of_work_is_accepted() {
+    let app = spawn_app_with(|c| {
+        c.signup_challenge = SignupChallengeSettings::ProofOfWork {
+            difficulty_bits: 8,
+            validity_secs: 60,
+        }
+    })
+    .await;
+    let challenge: Challenge = get_challenge(&app).await.json().await.unwrap();
+    let solution = solve_proof_of_work(&challenge);
+
+    let response = app
+        .post_subscription(format!("{}&challenge_response={}", BODY, solution))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn a_proof_of_work_cannot_be_replayed() {
+    let app = spawn_app_with(|c| {
+        c.signup_challenge = SignupChallengeSettings::ProofOfWork {
+            difficulty_bits: 8,
+            validity_secs: 60,
+        }
+    })
+    .await;
+    let challenge: Challenge = get_challenge(&app).await.json().await.unwrap();
+    let solution = solve_proof_of_work(&challenge);
+
+    let first = app
+        .post_subscription(format!("{}&challenge_response={}", BODY, solution))
+        .await;
+    let replayed = app
+        .post_subscription(format!(
+            "name=le%20guin&email=ursula%40example.com&challenge_response={}",
+            solution
+        ))
+        .await;
+
+    assert_eq!(first.status().as_u16(), 201);
+    assert_eq!(replayed.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn a_subscription_without_proof_of_work_is_rejected() {
+    let app = spawn_app_with(|c| {
+        c.signup_challenge = SignupChallengeSettings::ProofOfWork {
+            difficulty_bits: 8,
+            validity_secs: 60,
+        }
+    })
+    .await;
+
+    let response = app.post_subscription(BODY.into()).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+    let saved = sqlx::query!("SELECT id FROM subscriptions",)
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_empty());
+}
+
+#[tokio::test]
+async fn a_token_accepted_by_the_verification_endpoint_is_accepted() {
+    let verification_server = MockServer::start().await;
+    let app = spawn_app_with_remote_verification(&verification_server).await;
+    Mock::given(path("/siteverify"))
+        .and(method("POST"))
+        .and(body_string_contains("secret=verification-secret"))
+        .and(body_string_contains("response=captcha-token"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({"success": true})),
+        )
+        .expect(1)
+        .mount(&verification_server)
+        .await;
+
+    let response = app
+        .post_subscription(format!("{}&challenge_response=captcha-token", BODY))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn a_token_refused_by_the_verification_endpoint_is_rejected() {
+    let verification_server = MockServer::start().await;
+    let app = spawn_app_with_remote_verification(&verification_server).await;
+    Mock::given(path("/siteverify"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({"success": false})),
+        )
+        .expect(1)
+        .mount(&verification_server)
+        .await;
+
+    let response = app
+        .post_subscription(format!("{}&challenge_response=captcha-token", BODY))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 403);
+}
@@ -1,7 +1,8 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
 use sqlx::query;
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
+use zero2prod::routes::strip_plus_tags_from_canonical_emails;
 
 #[tokio::test]
 async fn subscribe_returns_a_201_for_valid_form_data() {
@@ -74,6 +75,7 @@ async fn subscribe_sends_email_for_valid_data() {
         .await;
 
     app.post_subscription(body.to_string()).await;
+    app.dispatch_all_pending_emails().await;
 }
 
 #[tokio::test]
@@ -87,12 +89,49 @@ async fn subscribe_sends_email_with_a_link() {
         .await;
 
     app.post_subscription(body.to_string()).await;
+    app.dispatch_all_pending_emails().await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let links = app.get_confirmation_links(&email_request).await;
+    let links = app.get_confirmation_links(email_request).await;
     assert_eq!(links.html, links.plain_text);
 }
 
+#[tokio::test]
+async fn validation_errors_are_reported_as_problem_details() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscription("name=&email=le%40mail.fr".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["type"], "/problems/validation-error");
+    assert_eq!(body["title"], "Invalid subscription data");
+    assert_eq!(body["status"], 400);
+    assert!(body["detail"].is_string());
+    assert!(body["request_id"].is_string());
+}
+
+#[tokio::test]
+async fn missing_fields_are_reported_as_problem_details() {
+    let app = spawn_app().await;
+
+    let response = app.post_subscription("name=le%20guin".into()).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["type"], "/problems/invalid-payload");
+}
+
 #[tokio::test]
 async fn subscibre_filas_if_there_is_a_fatal_database_error() {
     let app = spawn_app().await;
@@ -103,4 +142,482 @@ async fn subscibre_filas_if_there_is_a_fatal_database_error() {
         .unwrap();
     let response = app.post_subscription(body.into()).await;
     assert_eq!(response.status().as_u16(), 500);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["type"], "/problems/internal-error");
+    // The internal error chain stays in the logs.
+    assert!(!body.to_string().contains("email"));
+}
+
+#[tokio::test]
+async fn subscribe_succeeds_even_if_the_email_cannot_be_delivered() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscription(body.into()).await;
+
+    assert_eq!(response.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn subscribe_stores_the_confirmation_email_in_the_outbox() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    app.post_subscription(body.into()).await;
+
+    let saved = query!("SELECT recipient_email, n_retries FROM email_outbox",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the outbox");
+    assert_eq!(saved.recipient_email, "ursula_le_guin@gmail.com");
+    assert_eq!(saved.n_retries, 0);
+}
+
+#[tokio::test]
+async fn a_failed_confirmation_email_is_kept_for_a_later_retry() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscription(body.into()).await;
+
+    app.dispatch_all_pending_emails().await;
+
+    let saved = query!("SELECT n_retries, execute_after FROM email_outbox",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the outbox");
+    assert_eq!(saved.n_retries, 1);
+    assert!(saved.execute_after > chrono::Utc::now());
+}
+
+#[tokio::test]
+async fn a_delivered_confirmation_email_is_removed_from_the_outbox() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscription(body.into()).await;
+
+    app.dispatch_all_pending_emails().await;
+
+    let remaining = query!("SELECT id FROM email_outbox",)
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch the outbox");
+    assert!(remaining.is_empty());
+}
+
+#[tokio::test]
+async fn a_filled_honeypot_is_silently_dropped() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=le%40mail.fr&website=http%3A%2F%2Fspam.example";
+
+    let response = app.post_subscription(body.into()).await;
+
+    assert_eq!(response.status().as_u16(), 201);
+    let saved = query!("SELECT id FROM subscriptions",)
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_empty());
+}
+
+#[tokio::test]
+async fn subscriptions_are_rate_limited_per_ip() {
+    let app = spawn_app_with(|c| c.rate_limit.subscriptions_per_ip_per_hour = 2).await;
+
+    for i in 0..2 {
+        let body = format!("name=le%20guin&email=le{}%40mail.fr", i);
+        let response = app.post_subscription(body).await;
+        assert_eq!(response.status().as_u16(), 201);
+    }
+    let response = app
+        .post_subscription("name=le%20guin&email=other%40mail.fr".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+async fn post_subscription_forwarded_for(
+    app: &crate::helpers::TestApp,
+    body: String,
+    forwarded_for: &str,
+) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("X-CSRF-Token", app.csrf_token().await)
+        .header("X-Forwarded-For", forwarded_for)
+        .body(body)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn a_spoofed_forwarded_for_header_does_not_reset_the_ip_limit() {
+    let app = spawn_app_with(|c| c.rate_limit.subscriptions_per_ip_per_hour = 2).await;
+
+    for i in 0..3 {
+        let body = format!("name=le%20guin&email=le{}%40mail.fr", i);
+        let response =
+            post_subscription_forwarded_for(&app, body, &format!("198.51.100.{}", i)).await;
+        let expected = if i < 2 { 201 } else { 429 };
+        assert_eq!(response.status().as_u16(), expected);
+    }
+}
+
+#[tokio::test]
+async fn trusted_proxies_tell_the_client_address() {
+    let app = spawn_app_with(|c| {
+        c.rate_limit.subscriptions_per_ip_per_hour = 1;
+        c.application.trusted_proxies = vec!["127.0.0.1".parse().unwrap()];
+    })
+    .await;
+
+    for i in 0..2 {
+        let body = format!("name=le%20guin&email=le{}%40mail.fr", i);
+        let forwarded_for = format!("198.51.100.{}", i);
+        let response = post_subscription_forwarded_for(&app, body, &forwarded_for).await;
+        assert_eq!(response.status().as_u16(), 201);
+    }
+}
+
+#[tokio::test]
+async fn subscriptions_are_rate_limited_per_email() {
+    let app = spawn_app_with(|c| c.rate_limit.subscriptions_per_email_per_hour = 1).await;
+    let body = "name=le%20guin&email=le%40mail.fr";
+
+    let first = app.post_subscription(body.into()).await;
+    let second = app
+        .post_subscription("name=le%20guin&email=LE%40mail.fr".into())
+        .await;
+
+    assert_eq!(first.status().as_u16(), 201);
+    assert_eq!(second.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn confirmation_emails_have_a_global_hourly_ceiling() {
+    let app = spawn_app_with(|c| c.rate_limit.confirmation_emails_per_hour = 1).await;
+
+    let first = app
+        .post_subscription("name=le%20guin&email=first%40mail.fr".into())
+        .await;
+    let second = app
+        .post_subscription("name=le%20guin&email=second%40mail.fr".into())
+        .await;
+
+    assert_eq!(first.status().as_u16(), 201);
+    assert_eq!(second.status().as_u16(), 429);
+    let outbox = query!("SELECT id FROM email_outbox",)
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(outbox.len(), 1);
+}
+
+#[tokio::test]
+async fn rejected_subscriptions_do_not_use_up_the_confirmation_email_ceiling() {
+    let app = spawn_app_with(|c| c.rate_limit.confirmation_emails_per_hour = 2).await;
+    app.post_subscription("name=le%20guin&email=first%40mail.fr".into())
+        .await;
+    confirm_all_subscribers(&app).await;
+
+    let duplicate = app
+        .post_subscription("name=le%20guin&email=first%40mail.fr".into())
+        .await;
+    let invalid = app
+        .post_subscription("name=le%20guin&email=not-an-email".into())
+        .await;
+    let second = app
+        .post_subscription("name=le%20guin&email=second%40mail.fr".into())
+        .await;
+
+    assert_eq!(duplicate.status().as_u16(), 201);
+    assert_eq!(invalid.status().as_u16(), 400);
+    assert_eq!(second.status().as_u16(), 201);
+}
+
+async fn confirm_all_subscribers(app: &TestApp) {
+    query!("UPDATE subscriptions SET status = 'confirmed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn subscribing_again_before_confirming_sends_a_new_confirmation_email() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula%40example.com";
+
+    let first = app.post_subscription(body.into()).await;
+    let second = app.post_subscription(body.into()).await;
+
+    assert_eq!(first.status().as_u16(), 201);
+    assert_eq!(second.status().as_u16(), 201);
+    let saved = query!("SELECT id FROM subscriptions",)
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    let tokens = query!("SELECT subscription_id FROM subscription_tokens",)
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens.len(), 2);
+    let outbox = query!("SELECT id FROM email_outbox",)
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(outbox.len(), 2);
+}
+
+#[tokio::test]
+async fn the_same_address_with_a_different_case_is_not_subscribed_twice() {
+    let app = spawn_app().await;
+
+    let first = app
+        .post_subscription("name=le%20guin&email=ursula%40example.com".into())
+        .await;
+    confirm_all_subscribers(&app).await;
+    let second = app
+        .post_subscription("name=le%20guin&email=Ursula%40Example.COM".into())
+        .await;
+
+    assert_eq!(first.status().as_u16(), 201);
+    assert_eq!(second.status().as_u16(), 201);
+    let saved = query!("SELECT email, canonical_email FROM subscriptions",)
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].canonical_email, "ursula@example.com");
+    let outbox = query!("SELECT id FROM email_outbox",)
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(outbox.len(), 1);
+}
+
+#[tokio::test]
+async fn plus_tags_are_ignored_for_duplicates_when_configured() {
+    let app = spawn_app_with(|c| c.email_policy.strip_plus_tags = true).await;
+
+    app.post_subscription("name=le%20guin&email=ursula%40example.com".into())
+        .await;
+    confirm_all_subscribers(&app).await;
+    let response = app
+        .post_subscription("name=le%20guin&email=ursula%2Bnews%40example.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 201);
+    let saved = query!("SELECT id FROM subscriptions",)
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+}
+
+#[tokio::test]
+async fn stripping_plus_tags_merges_the_subscriptions_stored_before() {
+    let app = spawn_app().await;
+    app.post_subscription("name=le%20guin&email=ursula%2Bnews%40example.com".into())
+        .await;
+    confirm_all_subscribers(&app).await;
+    app.post_subscription("name=le%20guin&email=ursula%40example.com".into())
+        .await;
+    app.post_subscription("name=le%20guin&email=Ursula%2Bblog%40example.com".into())
+        .await;
+
+    strip_plus_tags_from_canonical_emails(&app.db_pool)
+        .await
+        .unwrap();
+
+    // The confirmed subscription is kept.
+    let saved = sqlx::query!("SELECT email, canonical_email, status FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email, "ursula+news@example.com");
+    assert_eq!(saved[0].canonical_email, "ursula@example.com");
+    assert_eq!(saved[0].status, "confirmed");
+}
+
+#[tokio::test]
+async fn disposable_email_domains_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscription("name=le%20guin&email=ursula%40mailinator.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn subscribe_reports_every_invalid_field() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscription("name=%3Cscript%3E&email=not-an-email".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        body["errors"],
+        serde_json::json!([
+            {
+                "field": "name",
+                "code": "forbidden_character",
+                "message": "The name contains the forbidden character `<`."
+            },
+            {
+                "field": "email",
+                "code": "invalid_syntax",
+                "message": "The email address is not valid."
+            }
+        ])
+    );
+}
+
+#[tokio::test]
+async fn validation_errors_do_not_echo_the_submitted_value() {
+    let app = spawn_app().await;
+    let name = "a".repeat(300);
+
+    let response = app
+        .post_subscription(format!("name={}&email=le%40mail.fr", name))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let body = response.text().await.unwrap();
+    assert!(body.contains("too_long"));
+    assert!(!body.contains(&name));
+}
+
+#[tokio::test]
+async fn subscribe_accepts_json_and_answers_with_json() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscription_json(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 201);
+    assert_eq!(response.headers()["Content-Type"], "application/json");
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "pending_confirmation");
+    let saved = query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+}
+
+#[tokio::test]
+async fn json_subscriptions_are_validated_like_forms() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscription_json(&serde_json::json!({
+            "name": "",
+            "email": "not-an-email",
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["type"], "/problems/validation-error");
+    assert_eq!(body["errors"].as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn malformed_json_subscriptions_are_reported_as_problem_details() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscription_json(&serde_json::json!({ "name": "le guin" }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["type"], "/problems/invalid-payload");
+}
+
+#[tokio::test]
+async fn form_posts_asking_for_json_get_json_back() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/subscriptions", app.address))
+        .header("X-CSRF-Token", app.csrf_token().await)
+        .header("Accept", "application/json")
+        .form(&[("name", "le guin"), ("email", "ursula_le_guin@gmail.com")])
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 201);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "pending_confirmation");
+}
+
+#[tokio::test]
+async fn browsers_get_an_html_page_back() {
+    let app = spawn_app().await;
+    let csrf_token = app.csrf_token().await;
+    let post_from_browser = |email: &'static str| {
+        app.api_client
+            .post(format!("{}/subscriptions", app.address))
+            .header("X-CSRF-Token", &csrf_token)
+            .header("Accept", "text/html,application/xhtml+xml,*/*;q=0.8")
+            .form(&[("name", "le guin"), ("email", email)])
+            .send()
+    };
+
+    let response = post_from_browser("ursula_le_guin@gmail.com").await.unwrap();
+    assert_eq!(response.status().as_u16(), 201);
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+    assert!(response.text().await.unwrap().contains("check your inbox"));
+
+    let response = post_from_browser("not-an-email").await.unwrap();
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("The email address is not valid."));
 }
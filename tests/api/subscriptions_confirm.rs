@@ -1,4 +1,4 @@
-use crate::{helpers::spawn_app };
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
 use wiremock::{
     matchers::{method, path},
     Mock, ResponseTemplate,
@@ -23,9 +23,10 @@ async fn the_link_returned_by_subscriber_returns_a_200_if_called() {
         .await;
     app.post_subscription("name=bla&email=bla@email.com".into())
         .await;
+    app.dispatch_all_pending_emails().await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
     let links = app.get_confirmation_links(email_request).await;
-    let mut confirmation_link = reqwest::Url::parse(&links.html.as_str()).unwrap();
+    let mut confirmation_link = reqwest::Url::parse(links.html.as_str()).unwrap();
     assert_eq!(confirmation_link.host_str().unwrap(), "127.0.0.1");
     confirmation_link.set_port(Some(app.port)).unwrap();
     let res2 = reqwest::get(confirmation_link.as_str()).await.unwrap();
@@ -43,8 +44,9 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
         .mount(&app.email_server)
         .await;
     app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request).await;
+    let confirmation_links = app.get_confirmation_links(email_request).await;
     // Act
     reqwest::get(confirmation_links.html)
         .await
@@ -60,3 +62,220 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
     assert_eq!(saved.name, "le guin");
     assert_eq!(saved.status, "confirmed");
 }
+
+async fn subscribe_and_get_confirmation_link(app: &TestApp) -> reqwest::Url {
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    app.get_confirmation_links(email_request).await.html
+}
+
+#[tokio::test]
+async fn confirmation_renders_a_landing_page_with_the_redirect_url() {
+    let app = spawn_app_with(|c| {
+        c.subscriptions.confirmation_redirect_url = Some("https://example.com/welcome".into())
+    })
+    .await;
+    let link = subscribe_and_get_confirmation_link(&app).await;
+
+    let response = reqwest::get(link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/html; charset=utf-8"
+    );
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("your subscription is confirmed"));
+    // Tera escapes the slashes, which browsers decode back in attributes.
+    assert!(html_page.contains("https:&#x2F;&#x2F;example.com&#x2F;welcome"));
+}
+
+#[tokio::test]
+async fn confirmation_sends_a_welcome_email() {
+    let app = spawn_app().await;
+    let link = subscribe_and_get_confirmation_link(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    reqwest::get(link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "ursula_le_guin@gmail.com");
+    assert!(body["TextBody"].as_str().unwrap().contains("Hello le guin"));
+}
+
+#[tokio::test]
+async fn no_welcome_email_is_sent_when_disabled() {
+    let app = spawn_app_with(|c| c.subscriptions.send_welcome_email = false).await;
+    let link = subscribe_and_get_confirmation_link(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    reqwest::get(link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn a_second_click_is_rejected_as_already_used_without_a_new_welcome_email() {
+    let app = spawn_app().await;
+    let link = subscribe_and_get_confirmation_link(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    reqwest::get(link.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let response = reqwest::get(link).await.unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(response.status().as_u16(), 409);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("already been used"));
+    assert!(html_page.contains("already confirmed"));
+}
+
+#[tokio::test]
+async fn an_unknown_token_is_rejected_with_an_html_page() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(&format!(
+        "{}/subscriptions/confirm?subscription_token=unknown",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/html; charset=utf-8"
+    );
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Unknown confirmation link"));
+}
+
+#[tokio::test]
+async fn an_expired_token_is_rejected_with_an_html_page() {
+    let app = spawn_app().await;
+    let link = subscribe_and_get_confirmation_link(&app).await;
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '73 hours'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::get(link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 410);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Expired confirmation link"));
+    let saved = sqlx::query!("SELECT status FROM subscriptions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn subscribing_again_replaces_an_expired_link() {
+    let app = spawn_app().await;
+    let expired_link = subscribe_and_get_confirmation_link(&app).await;
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '73 hours'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        reqwest::get(expired_link).await.unwrap().status().as_u16(),
+        410
+    );
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_subscription("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let new_link = app.get_confirmation_links(email_request).await.html;
+
+    reqwest::get(new_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let saved = sqlx::query!("SELECT status FROM subscriptions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscription_tokens_are_stored_hashed() {
+    let app = spawn_app().await;
+    let link = subscribe_and_get_confirmation_link(&app).await;
+    let token = link
+        .query_pairs()
+        .find(|(k, _)| k == "subscription_token")
+        .unwrap()
+        .1
+        .into_owned();
+
+    let saved = sqlx::query!("SELECT subscription_token_hash FROM subscription_tokens",)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    assert_ne!(saved.subscription_token_hash, token);
+    assert_eq!(
+        saved.subscription_token_hash,
+        zero2prod::routes::hash_subscription_token(&token)
+    );
+}
@@ -0,0 +1,248 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+use secrecy::Secret;
+use sqlx::query;
+use zero2prod::authentication::totp_code;
+
+fn extract_between<'a>(html: &'a str, start: &str, end: &str) -> Vec<&'a str> {
+    html.split(start)
+        .skip(1)
+        .map(|chunk| &chunk[..chunk.find(end).unwrap()])
+        .collect()
+}
+
+fn code_at(secret: &Secret<String>, offset_secs: i64) -> String {
+    totp_code(secret, chrono::Utc::now().timestamp() + offset_secs).unwrap()
+}
+
+/// Enroll the logged-in test user, returning its TOTP secret and recovery codes.
+async fn enroll(app: &TestApp) -> (Secret<String>, Vec<String>) {
+    let html = app.get_admin_html("/two_factor").await;
+    let secret = extract_between(&html, r#"<code id="totp-secret">"#, "</code>")[0];
+    // Tera escapes the slashes of the link.
+    assert!(html.contains("otpauth:&#x2F;&#x2F;totp&#x2F;"));
+    assert!(html.contains("<svg"));
+    let secret = Secret::new(secret.to_string());
+
+    let response = app
+        .post_admin_form("/two_factor/enable", &[("code", code_at(&secret, 0))])
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    let codes = extract_between(&html, "<li><code>", "</code>")
+        .into_iter()
+        .map(String::from)
+        .collect();
+    (secret, codes)
+}
+
+async fn log_in_with_password(app: &TestApp) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    }))
+    .await
+}
+
+async fn post_second_factor(app: &TestApp, code: &str) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/login/two_factor", app.address))
+        .header("X-CSRF-Token", app.csrf_token().await)
+        .form(&[("code", code)])
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn enrollment_requires_a_valid_code() {
+    let app = spawn_app().await;
+    app.login().await;
+    app.get_admin_html("/two_factor").await;
+
+    let response = app
+        .post_admin_form("/two_factor/enable", &[("code", "000000")])
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/two_factor");
+    assert!(app
+        .get_admin_html("/two_factor")
+        .await
+        .contains("Invalid authentication code."));
+    let user = query!("SELECT totp_enabled_at FROM users")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(user.totp_enabled_at.is_none());
+}
+
+#[tokio::test]
+async fn confirming_an_enrollment_twice_is_not_an_error() {
+    let app = spawn_app().await;
+    app.login().await;
+    let (secret, _) = enroll(&app).await;
+
+    let response = app
+        .post_admin_form("/two_factor/enable", &[("code", code_at(&secret, 0))])
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/two_factor");
+    assert!(app
+        .get_admin_html("/two_factor")
+        .await
+        .contains("There is no two-factor authentication setup to confirm."));
+}
+
+#[tokio::test]
+async fn enrolled_users_need_a_second_factor_to_log_in() {
+    let app = spawn_app().await;
+    app.login().await;
+    let (secret, codes) = enroll(&app).await;
+    assert_eq!(codes.len(), 10);
+    app.post_admin_form("/logout", &[("", "")]).await;
+
+    let response = log_in_with_password(&app).await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+    // The password alone does not open the admin area.
+    let response = app
+        .api_client
+        .get(format!("{}/admin/dashboard", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+
+    let code = code_at(&secret, 30);
+    let response = post_second_factor(&app, &code).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    assert!(app
+        .get_admin_html("/dashboard")
+        .await
+        .contains(&app.test_user.username));
+
+    // The same code cannot be replayed.
+    app.post_admin_form("/logout", &[("", "")]).await;
+    log_in_with_password(&app).await;
+    let response = post_second_factor(&app, &code).await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+}
+
+#[tokio::test]
+async fn recovery_codes_can_be_used_once() {
+    let app = spawn_app().await;
+    app.login().await;
+    let (_, codes) = enroll(&app).await;
+    app.post_admin_form("/logout", &[("", "")]).await;
+
+    log_in_with_password(&app).await;
+    let response = post_second_factor(&app, &codes[0].to_uppercase()).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    app.post_admin_form("/logout", &[("", "")]).await;
+    log_in_with_password(&app).await;
+    let response = post_second_factor(&app, &codes[0]).await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+}
+
+#[tokio::test]
+async fn too_many_invalid_codes_require_logging_in_again() {
+    let app = spawn_app().await;
+    app.login().await;
+    enroll(&app).await;
+    app.post_admin_form("/logout", &[("", "")]).await;
+    log_in_with_password(&app).await;
+
+    for _ in 0..4 {
+        let response = post_second_factor(&app, "000000").await;
+        assert_is_redirect_to(&response, "/login/two_factor");
+    }
+    let response = post_second_factor(&app, "000000").await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app
+        .api_client
+        .get(format!("{}/login/two_factor", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn logging_in_again_does_not_give_more_codes_to_try() {
+    let app = spawn_app().await;
+    app.login().await;
+    let (secret, _) = enroll(&app).await;
+    app.post_admin_form("/logout", &[("", "")]).await;
+    log_in_with_password(&app).await;
+    for _ in 0..5 {
+        post_second_factor(&app, "000000").await;
+    }
+
+    // Entering the password again starts a new session, but a valid code
+    // is still refused.
+    let response = log_in_with_password(&app).await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+    let response = post_second_factor(&app, &code_at(&secret, 30)).await;
+
+    assert_is_redirect_to(&response, "/login");
+    assert!(app
+        .get_login_html()
+        .await
+        .contains("Authentication login Failed"));
+}
+
+#[tokio::test]
+async fn enrollment_can_be_enforced_for_all_admins() {
+    let app = spawn_app_with(|c| c.two_factor.required = true).await;
+    app.login().await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/admin/dashboard", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/two_factor");
+
+    enroll(&app).await;
+    let response = app
+        .api_client
+        .get(format!("{}/admin/dashboard", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn two_factor_can_be_disabled_with_a_valid_code() {
+    let app = spawn_app().await;
+    app.login().await;
+    let (secret, _) = enroll(&app).await;
+
+    let response = app
+        .post_admin_form("/two_factor/disable", &[("code", code_at(&secret, 30))])
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/two_factor");
+    let user = query!("SELECT totp_secret, totp_enabled_at FROM users")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(user.totp_secret.is_none());
+    assert!(user.totp_enabled_at.is_none());
+}
+
+#[tokio::test]
+async fn enrolled_users_cannot_use_basic_auth_on_the_api() {
+    let app = spawn_app().await;
+    app.login().await;
+    enroll(&app).await;
+
+    let response = app
+        .api_request(reqwest::Method::GET, "/subscribers")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+}
@@ -0,0 +1,363 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{
+    assert_is_redirect_to, csrf_token, spawn_app, spawn_app_with, TestApp, TestUser,
+};
+
+/// A browser of its own, for a second user.
+fn new_browser() -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap()
+}
+
+async fn login_as(app: &TestApp, browser: &reqwest::Client, username: &str, password: &str) {
+    let response = browser
+        .post(format!("{}/login", app.address))
+        .header("X-CSRF-Token", csrf_token(browser, &app.address).await)
+        .form(&serde_json::json!({ "username": username, "password": password }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+/// Invite `username` and return the token of the emailed link.
+async fn invite(app: &TestApp, username: &str, role: &str) -> String {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_admin_form(
+            "/users",
+            &[
+                ("username", username),
+                ("email", "invitee@example.com"),
+                ("role", role),
+            ],
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let links = app.get_confirmation_links(&email_request).await;
+    assert_eq!(links.html.path(), "/invitations/accept");
+    links
+        .html
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .map(|(_, token)| token.into_owned())
+        .unwrap()
+}
+
+async fn accept(app: &TestApp, token: &str, password: &str) -> reqwest::Response {
+    let browser = new_browser();
+    browser
+        .post(format!("{}/invitations/accept", app.address))
+        .header("X-CSRF-Token", csrf_token(&browser, &app.address).await)
+        .form(&serde_json::json!({
+            "token": token,
+            "new_password": password,
+            "new_password_check": password,
+        }))
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn only_owners_can_manage_users() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_role("editor").await;
+    app.login().await;
+
+    // Act
+    let page = app
+        .api_client
+        .get(format!("{}/admin/users", app.address))
+        .send()
+        .await
+        .unwrap();
+    let invitation = app
+        .post_admin_form(
+            "/users",
+            &[
+                ("username", "intruder"),
+                ("email", "intruder@example.com"),
+                ("role", "owner"),
+            ],
+        )
+        .await;
+
+    // Assert
+    assert_eq!(page.status().as_u16(), 403);
+    let html_page = page.text().await.unwrap();
+    assert!(html_page.contains("<title>Forbidden</title>"));
+    assert!(html_page.contains("Your role does not allow you to manage users."));
+    assert_eq!(invitation.status().as_u16(), 403);
+    assert!(!app
+        .get_admin_html("/dashboard")
+        .await
+        .contains("/admin/users"));
+}
+
+#[tokio::test]
+async fn owners_see_the_list_of_users() {
+    // Arrange
+    let app = spawn_app().await;
+    let other = TestUser::generate();
+    other.store(&app.db_pool).await;
+    app.login().await;
+
+    // Act
+    let html = app.get_admin_html("/users").await;
+
+    // Assert
+    assert!(html.contains(&app.test_user.username));
+    assert!(html.contains(&other.username));
+    assert!(html.contains(&format!("/admin/users/{}/disable", other.user_id)));
+    assert!(!html.contains(&format!("/admin/users/{}/disable", app.test_user.user_id)));
+}
+
+#[tokio::test]
+async fn an_invitee_sets_their_password_and_gets_the_invited_role() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login().await;
+    let token = invite(&app, "ursula", "analyst").await;
+    let html = app.get_admin_html("/users").await;
+    assert!(html.contains("invited"));
+
+    // Act - Part 1 - Follow the link
+    let form = new_browser()
+        .get(format!(
+            "{}/invitations/accept?token={}",
+            app.address, token
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(form.status().as_u16(), 200);
+    assert!(form.text().await.unwrap().contains("Welcome ursula!"));
+
+    // Act - Part 2 - Choose a password
+    let response = accept(&app, &token, "a-long-enough-password").await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Assert
+    let browser = new_browser();
+    login_as(&app, &browser, "ursula", "a-long-enough-password").await;
+    let dashboard = browser
+        .get(format!("{}/admin/dashboard", app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(dashboard.contains("You are signed in as analyst."));
+}
+
+#[tokio::test]
+async fn an_invitation_can_only_be_accepted_once() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login().await;
+    let token = invite(&app, "ursula", "editor").await;
+    accept(&app, &token, "a-long-enough-password").await;
+
+    // Act
+    let response = accept(&app, &token, "another-long-password").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    login_as(&app, &new_browser(), "ursula", "a-long-enough-password").await;
+}
+
+#[tokio::test]
+async fn the_username_is_escaped_in_the_invitation_email() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login().await;
+
+    // Act
+    invite(&app, "<b>mallory</b>", "editor").await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html = body["HtmlBody"].as_str().unwrap();
+    assert!(html.contains("&lt;b&gt;mallory&lt;&#x2F;b&gt;"));
+    assert!(!html.contains("<b>"));
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("Your username is <b>mallory</b>."));
+}
+
+#[tokio::test]
+async fn invited_users_cannot_log_in_before_accepting() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login().await;
+    invite(&app, "ursula", "editor").await;
+
+    // Act
+    let browser = new_browser();
+    let response = browser
+        .post(format!("{}/login", app.address))
+        .header("X-CSRF-Token", csrf_token(&browser, &app.address).await)
+        .form(&serde_json::json!({ "username": "ursula", "password": "" }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn usernames_must_be_unique() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login().await;
+
+    // Act
+    let response = app
+        .post_admin_form(
+            "/users",
+            &[
+                ("username", app.test_user.username.as_str()),
+                ("email", "someone@example.com"),
+                ("role", "editor"),
+            ],
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/users");
+    let html = app.get_admin_html("/users").await;
+    assert!(html.contains("is already taken."));
+}
+
+#[tokio::test]
+async fn disabled_users_are_logged_out_and_cannot_log_back_in() {
+    // Arrange
+    // The refused attempts must not get the final login throttled.
+    let app = spawn_app_with(|c| c.rate_limit.login_delay_base_ms = 0).await;
+    let other = TestUser::generate();
+    other.store(&app.db_pool).await;
+    let other_browser = new_browser();
+    login_as(&app, &other_browser, &other.username, &other.password).await;
+    app.login().await;
+
+    // Act
+    let response = app
+        .post_admin_form(&format!("/users/{}/disable", other.user_id), &[("", "")])
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    // Assert
+    let dashboard = other_browser
+        .get(format!("{}/admin/dashboard", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&dashboard, "/login");
+    let login = other_browser
+        .post(format!("{}/login", app.address))
+        .header(
+            "X-CSRF-Token",
+            csrf_token(&other_browser, &app.address).await,
+        )
+        .form(&serde_json::json!({
+            "username": &other.username,
+            "password": &other.password,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&login, "/login");
+    let api = app
+        .api_client
+        .get(format!("{}/api/v1/subscribers", app.address))
+        .basic_auth(&other.username, Some(&other.password))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(api.status().as_u16(), 401);
+
+    // Enabling them back lets them log in again
+    app.post_admin_form(&format!("/users/{}/enable", other.user_id), &[("", "")])
+        .await;
+    login_as(&app, &new_browser(), &other.username, &other.password).await;
+}
+
+#[tokio::test]
+async fn deleted_users_cannot_log_in() {
+    // Arrange
+    let app = spawn_app().await;
+    let other = TestUser::generate();
+    other.store(&app.db_pool).await;
+    app.login().await;
+
+    // Act
+    let response = app
+        .post_admin_form(&format!("/users/{}/delete", other.user_id), &[("", "")])
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/users");
+    let html = app.get_admin_html("/users").await;
+    assert!(html.contains(&format!("{} has been deleted.", other.username)));
+    assert!(!html.contains(&format!("/admin/users/{}/disable", other.user_id)));
+    let browser = new_browser();
+    let login = browser
+        .post(format!("{}/login", app.address))
+        .header("X-CSRF-Token", csrf_token(&browser, &app.address).await)
+        .form(&serde_json::json!({
+            "username": &other.username,
+            "password": &other.password,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&login, "/login");
+}
+
+#[tokio::test]
+async fn owners_cannot_disable_or_delete_themselves() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login().await;
+    let own_id = app.test_user.user_id;
+
+    // Act
+    app.post_admin_form(&format!("/users/{}/disable", own_id), &[("", "")])
+        .await;
+    app.post_admin_form(&format!("/users/{}/delete", own_id), &[("", "")])
+        .await;
+
+    // Assert
+    let html = app.get_admin_html("/users").await;
+    assert!(html.contains("You cannot delete your own account."));
+    assert!(html.contains(&app.test_user.username));
+}
@@ -0,0 +1,292 @@
+use crate::helpers::{spawn_app, spawn_app_with};
+use secrecy::Secret;
+use sqlx::query;
+use zero2prod::configuration::{get_configuration, SignupChallengeSettings};
+use zero2prod::signup_challenge::{solve_proof_of_work, Challenge};
+
+const PARTNER: &str = "https://partner.example.com";
+
+#[tokio::test]
+async fn preflight_requests_from_allowed_origins_are_accepted() {
+    let app = spawn_app_with(|c| c.subscriptions.allowed_origins = vec![PARTNER.into()]).await;
+
+    let response = app
+        .api_client
+        .request(
+            reqwest::Method::OPTIONS,
+            format!("{}/subscriptions", app.address),
+        )
+        .header("Origin", PARTNER)
+        .header("Access-Control-Request-Method", "POST")
+        .header("Access-Control-Request-Headers", "content-type")
+        .send()
+        .await
+        .unwrap();
+
+    assert!(response.status().is_success());
+    assert_eq!(response.headers()["Access-Control-Allow-Origin"], PARTNER);
+}
+
+#[tokio::test]
+async fn requests_from_other_origins_are_not_allowed() {
+    let app = spawn_app_with(|c| c.subscriptions.allowed_origins = vec![PARTNER.into()]).await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/subscriptions", app.address))
+        .header("Origin", "https://evil.example.com")
+        .json(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    assert!(response
+        .headers()
+        .get("Access-Control-Allow-Origin")
+        .is_none());
+    let saved = query!("SELECT id FROM subscriptions")
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_none());
+}
+
+#[tokio::test]
+async fn cross_origin_subscriptions_store_their_source() {
+    let app = spawn_app_with(|c| c.subscriptions.allowed_origins = vec![PARTNER.into()]).await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/subscriptions", app.address))
+        .header("Origin", PARTNER)
+        .json(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+            "source": "Partner-Blog",
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 201);
+    assert_eq!(response.headers()["Access-Control-Allow-Origin"], PARTNER);
+    let saved = query!("SELECT source FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.source.as_deref(), Some("partner-blog"));
+}
+
+#[tokio::test]
+async fn invalid_source_tags_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscription_json(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+            "source": "not a tag!",
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["errors"][0]["field"], "source");
+    assert_eq!(body["errors"][0]["code"], "invalid_character");
+}
+
+#[tokio::test]
+async fn the_widget_form_posts_its_source_tag() {
+    let app = spawn_app().await;
+
+    let html = app
+        .api_client
+        .get(format!("{}/widget?source=partner-blog", app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    assert!(html.contains(r#"method="post""#));
+    assert!(html.contains(r#"name="source" value="partner-blog""#));
+}
+
+#[tokio::test]
+async fn the_widget_escapes_the_source_tag() {
+    let app = spawn_app().await;
+
+    let html = app
+        .api_client
+        .get(format!(
+            "{}/widget?source=%22%3E%3Cscript%3Ealert(1)%3C/script%3E",
+            app.address
+        ))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    assert!(!html.contains("<script>"));
+}
+
+#[tokio::test]
+async fn the_widget_script_is_served_as_javascript() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/widget.js", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("application/javascript"));
+    assert!(response.text().await.unwrap().contains("/subscriptions"));
+}
+
+fn hidden_field<'a>(html: &'a str, name: &str) -> &'a str {
+    let start = html
+        .find(&format!(r#"name="{}" value=""#, name))
+        .map(|i| i + format!(r#"name="{}" value=""#, name).len())
+        .unwrap();
+    &html[start..start + html[start..].find('"').unwrap()]
+}
+
+#[tokio::test]
+async fn the_widget_form_solves_the_signup_challenge() {
+    let app = spawn_app_with(|c| {
+        c.signup_challenge = SignupChallengeSettings::ProofOfWork {
+            difficulty_bits: 8,
+            validity_secs: 60,
+        }
+    })
+    .await;
+    let html = app
+        .api_client
+        .get(format!("{}/widget?source=partner-blog", app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains(r#"name="challenge_response""#));
+    assert!(html.contains("/widget/form.js"));
+    let script = app
+        .api_client
+        .get(format!("{}/widget/form.js", app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(script.contains("/subscriptions/challenge"));
+
+    // What the script does on submit.
+    let challenge: Challenge = app
+        .api_client
+        .get(format!("{}/subscriptions/challenge", app.address))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .header("Origin", "http://127.0.0.1")
+        .form(&[
+            ("name", "le guin"),
+            ("email", "ursula_le_guin@gmail.com"),
+            ("website", ""),
+            ("source", hidden_field(&html, "source")),
+            ("locale", hidden_field(&html, "locale")),
+            ("challenge_response", &solve_proof_of_work(&challenge)),
+        ])
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 201);
+    let saved = query!("SELECT source FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.source.as_deref(), Some("partner-blog"));
+}
+
+#[tokio::test]
+async fn the_widget_script_solves_the_signup_challenge() {
+    let app = spawn_app_with(|c| {
+        c.subscriptions.allowed_origins = vec![PARTNER.into()];
+        c.signup_challenge = SignupChallengeSettings::ProofOfWork {
+            difficulty_bits: 8,
+            validity_secs: 60,
+        }
+    })
+    .await;
+    let script = app
+        .api_client
+        .get(format!("{}/widget.js", app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(script.contains("/subscriptions/challenge"));
+    assert!(script.contains("challenge_response"));
+
+    // What the script does on submit.
+    let challenge: Challenge = app
+        .api_client
+        .get(format!("{}/subscriptions/challenge", app.address))
+        .header("Origin", PARTNER)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let response = app
+        .api_client
+        .post(format!("{}/subscriptions", app.address))
+        .header("Origin", PARTNER)
+        .json(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+            "website": "",
+            "source": "partner-blog",
+            "challenge_response": solve_proof_of_work(&challenge),
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 201);
+}
+
+#[test]
+fn the_widget_cannot_be_offered_with_a_remote_signup_challenge() {
+    let mut configuration = get_configuration().unwrap();
+    configuration.subscriptions.allowed_origins = vec![PARTNER.into()];
+    configuration.signup_challenge = SignupChallengeSettings::Remote {
+        verification_url: "https://challenge.example.com/siteverify".into(),
+        secret: Secret::new("verification-secret".into()),
+        timeout_ms: 200,
+    };
+
+    assert!(configuration.validate().is_err());
+}